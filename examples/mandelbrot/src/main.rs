@@ -1,12 +1,16 @@
 use std::time::Instant;
 
 use paya::{
-    common::{AccessFlags, ImageLayout, ImageTransition, ImageUsageFlags},
-    device::{Device, ImageInfo, PresentInfo, SubmitInfo},
+    common::{
+        AccessFlags2, BarrierInfo, ImageBarrier, ImageLayout, ImageSubresourceRange,
+        ImageUsageFlags, PipelineStageFlags, PipelineStageFlags2,
+    },
+    device::{Device, DeviceCreateInfo, ImageInfo, PresentInfo, SubmitInfo},
     gpu_resources::{self, GpuResourcePool, PackedGpuResourceId},
     instance::{Instance, InstanceCreateInfo},
     pipeline::ComputePipelineInfo,
-    shader::{ShaderCompiler, ShaderInfo},
+    queue::QueueType,
+    shader::ShaderInfo,
     swapchain::SwapchainCreateInfo,
     task_list::{Task, TaskList},
 };
@@ -33,34 +37,52 @@ fn main() {
     // Initialize Paya
     let instance = Instance::new(InstanceCreateInfo {
         display_handle: Some(&window),
-    });
-    let mut device = Device::new(&instance, |device_properties| {
-        // Select the first discrete GPU
-        let score = match device_properties.device_type {
-            paya::device::DeviceType::Discrete => 100,
-            _ => 0,
-        };
-
-        score
-    });
-    let mut swapchain = device.create_swapchain(SwapchainCreateInfo {
-        window_handle: &window,
-        display_handle: &window,
-        preferred_extent: (1280, 720),
-        image_usage: ImageUsageFlags::STORAGE,
-        max_frames_in_flight: 2,
-    });
-
-    let shader_compiler = ShaderCompiler::new();
-    let compute_pipeline = device.create_compute_pipeline(ComputePipelineInfo {
-        shader: ShaderInfo {
-            byte_code: shader_compiler
-                .load_from_file("shaders/mandelbrot.comp.glsl".to_owned())
-                .expect("Failed to load shader."),
-            entry_point: "main".to_owned(),
+        ..Default::default()
+    })
+    .expect("Failed to create instance.");
+    let device = Device::new(
+        &instance,
+        DeviceCreateInfo {
+            selector: &|device_info| {
+                // Select the first discrete GPU
+                let score = match device_info.device_type {
+                    paya::device::DeviceType::Discrete => 100,
+                    _ => 0,
+                };
+
+                Some(score)
+            },
+            ..Default::default()
         },
-        push_constant_size: std::mem::size_of::<PushConstants>() as u32,
-    });
+    )
+    .expect("Failed to create device.");
+    let mut swapchain = device
+        .create_swapchain(SwapchainCreateInfo {
+            name: "mandelbrot swapchain",
+            window_handle: &window,
+            display_handle: &window,
+            preferred_extent: (1280, 720),
+            preferred_image_count: 3,
+            image_usage: ImageUsageFlags::STORAGE,
+            max_frames_in_flight: 2,
+        })
+        .expect("Failed to create swapchain.");
+
+    let shader_compiler = device
+        .create_shader_compiler()
+        .expect("Failed to create shader compiler.");
+    let compute_pipeline = device
+        .create_compute_pipeline(ComputePipelineInfo {
+            name: "mandelbrot".to_owned(),
+            shader: ShaderInfo {
+                byte_code: shader_compiler
+                    .load_from_file("shaders/mandelbrot.comp.glsl".to_owned())
+                    .expect("Failed to load shader."),
+                entry_point: "main".to_owned(),
+            },
+            push_constant_size: std::mem::size_of::<PushConstants>() as u32,
+        })
+        .expect("Failed to create compute pipeline.");
 
     let start_time = Instant::now();
 
@@ -74,29 +96,46 @@ fn main() {
                         window.exit();
                     }
                     WindowEvent::Resized(size) => {
-                        swapchain.resize(&mut device, size.width, size.height);
+                        swapchain
+                            .resize(&device, size.width, size.height)
+                            .expect("Failed to resize swapchain.");
                     }
                     _ => {}
                 },
                 Event::AboutToWait => {
-                    let Some(image) = swapchain.acquire_next_image() else {
+                    let Some(image) = swapchain
+                        .acquire_next_image()
+                        .expect("Failed to acquire image.")
+                    else {
                         return;
                     };
-                    let image_extent = device.get_image(image).info.extent;
-
-                    let mut recorder = device.create_command_recorder();
-
-                    recorder.pipeline_barrier_image_transition(
-                        &device,
-                        ImageTransition {
-                            image,
-                            src_layout: ImageLayout::Undefined,
-                            src_access: AccessFlags::empty(),
-                            dst_layout: ImageLayout::General,
-                            dst_access: AccessFlags::SHADER_WRITE,
-                        },
-                    );
-
+                    let image_extent = device.get_image(image).unwrap().info.extent;
+
+                    let mut recorder = device
+                        .create_command_recorder(QueueType::Graphics, "mandelbrot frame")
+                        .unwrap();
+
+                    recorder
+                        .pipeline_barrier(
+                            &device,
+                            &BarrierInfo {
+                                image_barriers: vec![ImageBarrier {
+                                    image,
+                                    src_stages: PipelineStageFlags2::ALL_COMMANDS,
+                                    src_access: AccessFlags2::empty(),
+                                    dst_stages: PipelineStageFlags2::COMPUTE_SHADER,
+                                    dst_access: AccessFlags2::SHADER_WRITE,
+                                    src_layout: ImageLayout::Undefined,
+                                    dst_layout: ImageLayout::General,
+                                    subresource_range: ImageSubresourceRange::default(),
+                                    ownership_transfer: None,
+                                }],
+                                ..Default::default()
+                            },
+                        )
+                        .unwrap();
+
+                    recorder.begin_label(&device, "mandelbrot", [0.2, 0.4, 1.0, 1.0]);
                     recorder.bind_compute_pipeline(&device, &compute_pipeline);
                     recorder.upload_push_constants(
                         &device,
@@ -113,36 +152,52 @@ fn main() {
                         f32::ceil(image_extent.height as f32 / 16.0) as u32,
                         1,
                     );
-
-                    recorder.pipeline_barrier_image_transition(
-                        &device,
-                        ImageTransition {
-                            image,
-                            src_layout: ImageLayout::General,
-                            src_access: AccessFlags::SHADER_WRITE,
-                            dst_layout: ImageLayout::PresentSrc,
-                            dst_access: AccessFlags::empty(),
-                        },
-                    );
-
-                    let command_buffer = recorder.finish(&device);
-
-                    device.submit(SubmitInfo {
-                        commands: vec![command_buffer],
-                        wait_semaphores: vec![swapchain.current_acquire_semaphore()],
-                        signal_semaphores: vec![swapchain.current_present_semaphore()],
-                        signal_timeline_semaphores: vec![(
-                            swapchain.gpu_timeline_semaphore(),
-                            device.cpu_frame_index() as u64 + 1,
-                        )],
-                    });
-
-                    device.present(PresentInfo {
-                        swapchain: &swapchain,
-                        wait_semaphores: vec![swapchain.current_present_semaphore()],
-                    });
-
-                    device.collect_garbage(swapchain.gpu_timeline_semaphore());
+                    recorder.end_label(&device);
+
+                    recorder
+                        .pipeline_barrier(
+                            &device,
+                            &BarrierInfo {
+                                image_barriers: vec![ImageBarrier {
+                                    image,
+                                    src_stages: PipelineStageFlags2::COMPUTE_SHADER,
+                                    src_access: AccessFlags2::SHADER_WRITE,
+                                    dst_stages: PipelineStageFlags2::BOTTOM_OF_PIPE,
+                                    dst_access: AccessFlags2::empty(),
+                                    src_layout: ImageLayout::General,
+                                    dst_layout: ImageLayout::PresentSrc,
+                                    subresource_range: ImageSubresourceRange::default(),
+                                    ownership_transfer: None,
+                                }],
+                                ..Default::default()
+                            },
+                        )
+                        .unwrap();
+
+                    let command_buffer = recorder.finish(&device).unwrap();
+
+                    device
+                        .submit(SubmitInfo {
+                            queue: QueueType::Graphics,
+                            commands: vec![command_buffer],
+                            wait_semaphores: vec![(
+                                swapchain.current_acquire_semaphore(),
+                                PipelineStageFlags::ALL_COMMANDS,
+                            )],
+                            wait_timeline_semaphores: vec![],
+                            signal_semaphores: vec![swapchain.current_present_semaphore()],
+                            signal_timeline_semaphores: vec![swapchain.current_frame_signal()],
+                        })
+                        .unwrap();
+
+                    device
+                        .present(PresentInfo {
+                            swapchain: &swapchain,
+                            wait_semaphores: vec![swapchain.current_present_semaphore()],
+                        })
+                        .unwrap();
+
+                    device.collect_garbage().unwrap();
                 }
                 _ => {}
             }
@@ -1,8 +1,12 @@
 use paya::{
-    common::{AccessFlags, ImageLayout, ImageTransition, ImageUsageFlags},
-    device::{Device, ImageInfo, PresentInfo, SubmitInfo},
+    common::{
+        AccessFlags2, BarrierInfo, ImageBarrier, ImageLayout, ImageSubresourceRange,
+        ImageUsageFlags, PipelineStageFlags, PipelineStageFlags2,
+    },
+    device::{Device, DeviceCreateInfo, ImageInfo, PresentInfo, SubmitInfo},
     gpu_resources::{self, GpuResourcePool},
     instance::{Instance, InstanceCreateInfo},
+    queue::QueueType,
     swapchain::SwapchainCreateInfo,
     task_list::{Task, TaskList},
 };
@@ -19,23 +23,36 @@ fn main() {
     // Initialize Paya
     let instance = Instance::new(InstanceCreateInfo {
         display_handle: Some(&window),
-    });
-    let mut device = Device::new(&instance, |device_properties| {
-        // Select the first discrete GPU
-        let score = match device_properties.device_type {
-            paya::device::DeviceType::Discrete => 100,
-            _ => 0,
-        };
+        ..Default::default()
+    })
+    .expect("Failed to create instance.");
+    let device = Device::new(
+        &instance,
+        DeviceCreateInfo {
+            selector: &|device_info| {
+                // Select the first discrete GPU
+                let score = match device_info.device_type {
+                    paya::device::DeviceType::Discrete => 100,
+                    _ => 0,
+                };
 
-        score
-    });
-    let mut swapchain = device.create_swapchain(SwapchainCreateInfo {
-        window_handle: &window,
-        display_handle: &window,
-        preferred_extent: (1280, 720),
-        image_usage: ImageUsageFlags::TRANSFER_DST,
-        max_frames_in_flight: 2,
-    });
+                Some(score)
+            },
+            ..Default::default()
+        },
+    )
+    .expect("Failed to create device.");
+    let mut swapchain = device
+        .create_swapchain(SwapchainCreateInfo {
+            name: "triangle swapchain",
+            window_handle: &window,
+            display_handle: &window,
+            preferred_extent: (1280, 720),
+            preferred_image_count: 3,
+            image_usage: ImageUsageFlags::TRANSFER_DST,
+            max_frames_in_flight: 2,
+        })
+        .expect("Failed to create swapchain.");
 
     event_loop
         .run(|event, window| {
@@ -47,55 +64,90 @@ fn main() {
                         window.exit();
                     }
                     WindowEvent::Resized(size) => {
-                        swapchain.resize(&mut device, size.width, size.height);
+                        swapchain
+                            .resize(&device, size.width, size.height)
+                            .expect("Failed to resize swapchain.");
                     }
                     WindowEvent::RedrawRequested => {
-                        let Some(image) = swapchain.acquire_next_image() else {
+                        let Some(image) = swapchain
+                            .acquire_next_image()
+                            .expect("Failed to acquire image.")
+                        else {
                             return;
                         };
 
-                        let mut recorder = device.create_command_recorder();
+                        let mut recorder = device
+                            .create_command_recorder(QueueType::Graphics, "triangle frame")
+                            .unwrap();
+
+                        recorder
+                            .pipeline_barrier(
+                                &device,
+                                &BarrierInfo {
+                                    image_barriers: vec![ImageBarrier {
+                                        image,
+                                        src_stages: PipelineStageFlags2::ALL_COMMANDS,
+                                        src_access: AccessFlags2::empty(),
+                                        dst_stages: PipelineStageFlags2::ALL_TRANSFER,
+                                        dst_access: AccessFlags2::TRANSFER_WRITE,
+                                        src_layout: ImageLayout::Undefined,
+                                        dst_layout: ImageLayout::TransferDstOptimal,
+                                        subresource_range: ImageSubresourceRange::default(),
+                                        ownership_transfer: None,
+                                    }],
+                                    ..Default::default()
+                                },
+                            )
+                            .unwrap();
 
-                        recorder.pipeline_barrier_image_transition(
-                            &device,
-                            ImageTransition {
-                                image,
-                                src_layout: ImageLayout::Undefined,
-                                src_access: AccessFlags::empty(),
-                                dst_layout: ImageLayout::TransferDstOptimal,
-                                dst_access: AccessFlags::TRANSFER_WRITE,
-                            },
-                        );
+                        recorder
+                            .clear_color_image(&device, image, 1.0, 0.0, 0.0, 1.0)
+                            .unwrap();
 
-                        recorder.clear_color_image(&device, image, 1.0, 0.0, 0.0, 1.0);
+                        recorder
+                            .pipeline_barrier(
+                                &device,
+                                &BarrierInfo {
+                                    image_barriers: vec![ImageBarrier {
+                                        image,
+                                        src_stages: PipelineStageFlags2::ALL_TRANSFER,
+                                        src_access: AccessFlags2::TRANSFER_WRITE,
+                                        dst_stages: PipelineStageFlags2::BOTTOM_OF_PIPE,
+                                        dst_access: AccessFlags2::empty(),
+                                        src_layout: ImageLayout::TransferDstOptimal,
+                                        dst_layout: ImageLayout::PresentSrc,
+                                        subresource_range: ImageSubresourceRange::default(),
+                                        ownership_transfer: None,
+                                    }],
+                                    ..Default::default()
+                                },
+                            )
+                            .unwrap();
 
-                        recorder.pipeline_barrier_image_transition(
-                            &device,
-                            ImageTransition {
-                                image,
-                                src_layout: ImageLayout::TransferDstOptimal,
-                                src_access: AccessFlags::TRANSFER_WRITE,
-                                dst_layout: ImageLayout::PresentSrc,
-                                dst_access: AccessFlags::empty(),
-                            },
-                        );
+                        let command_buffer = recorder.finish(&device).unwrap();
 
-                        let command_buffer = recorder.finish(&device);
+                        device
+                            .submit(SubmitInfo {
+                                queue: QueueType::Graphics,
+                                commands: vec![command_buffer],
+                                wait_semaphores: vec![(
+                                    swapchain.current_acquire_semaphore(),
+                                    PipelineStageFlags::ALL_COMMANDS,
+                                )],
+                                wait_timeline_semaphores: vec![],
+                                signal_semaphores: vec![swapchain.current_present_semaphore()],
+                                signal_timeline_semaphores: vec![swapchain.current_frame_signal()],
+                            })
+                            .unwrap();
 
-                        device.submit(SubmitInfo {
-                            commands: vec![command_buffer],
-                            wait_semaphores: vec![swapchain.current_acquire_semaphore()],
-                            signal_semaphores: vec![swapchain.current_present_semaphore()],
-                            signal_timeline_semaphores: vec![(
-                                swapchain.gpu_timeline_semaphore(),
-                                device.cpu_frame_index() as u64 + 1,
-                            )],
-                        });
+                        device
+                            .present(PresentInfo {
+                                swapchain: &swapchain,
+                                wait_semaphores: vec![swapchain.current_present_semaphore()],
+                            })
+                            .unwrap();
 
-                        device.present(PresentInfo {
-                            swapchain: &swapchain,
-                            wait_semaphores: vec![swapchain.current_present_semaphore()],
-                        });
+                        device.collect_garbage().unwrap();
                     }
                     _ => {}
                 },
@@ -1,10 +1,11 @@
-use std::sync::Arc;
+use std::{collections::HashMap, fmt::Write, sync::Arc};
 
 use ash::vk;
 use bitflags::bitflags;
 
 use crate::{
     device::{Device, DeviceInner},
+    error::Result,
     gpu_resources::{BufferId, ImageId},
 };
 
@@ -34,6 +35,9 @@ impl AllocationId {
 pub struct GpuAllocator {
     device_dep: Arc<DeviceInner>,
     gpu_allocator: gpu_allocator::vulkan::Allocator, //dedicated_allocations: Vec<Allocation>,
+    // Every allocation that hasn't been freed yet, used for memory reports.
+    live_allocations: HashMap<AllocationId, AllocationReport>,
+    next_allocation_index: usize,
 }
 
 #[derive(Clone, Debug)]
@@ -104,6 +108,7 @@ impl Into<vk::MemoryPropertyFlags> for MemoryFlags {
 }
 
 pub struct Allocation {
+    pub(crate) id: AllocationId,
     pub(crate) allocation: gpu_allocator::vulkan::Allocation,
 }
 
@@ -118,8 +123,8 @@ impl Allocation {
 }
 
 impl GpuAllocator {
-    pub(crate) fn new(device_dep: Arc<DeviceInner>) -> Self {
-        GpuAllocator {
+    pub(crate) fn new(device_dep: Arc<DeviceInner>) -> Result<Self> {
+        Ok(GpuAllocator {
             device_dep: device_dep.clone(),
             gpu_allocator: gpu_allocator::vulkan::Allocator::new(
                 &gpu_allocator::vulkan::AllocatorCreateDesc {
@@ -130,10 +135,11 @@ impl GpuAllocator {
                     debug_settings: gpu_allocator::AllocatorDebugSettings::default(),
                     allocation_sizes: gpu_allocator::AllocationSizes::default(),
                 },
-            )
-            .expect("Failed to create allocator."),
+            )?,
             // dedicated_allocations: Vec::new(),
-        }
+            live_allocations: HashMap::new(),
+            next_allocation_index: 0,
+        })
     }
 
     pub(crate) fn allocate_memory(
@@ -143,22 +149,142 @@ impl GpuAllocator {
         location: MemoryLocation,
         mem_type: MemoryType,
         requirements: vk::MemoryRequirements,
-    ) -> Allocation {
+    ) -> Result<Allocation> {
+        let name = name.into();
         let allocation = self
             .gpu_allocator
             .allocate(&gpu_allocator::vulkan::AllocationCreateDesc {
-                name: &name.into(),
+                name: &name,
                 requirements,
                 location: location.into(),
                 linear,
                 allocation_scheme: mem_type.into_gpu_allocator_type(),
-            })
-            .expect("coudlnt make alloc");
+            })?;
+
+        let id = AllocationId::new(allocation.is_dedicated(), self.next_allocation_index);
+        self.next_allocation_index += 1;
+        self.live_allocations.insert(
+            id,
+            AllocationReport {
+                name,
+                size: allocation.size(),
+                location,
+                memory_flags: MemoryFlags::from_bits_truncate(
+                    allocation.memory_properties().as_raw(),
+                ),
+                dedicated: allocation.is_dedicated(),
+            },
+        );
 
-        Allocation { allocation }
+        Ok(Allocation { id, allocation })
     }
 
     pub(crate) fn deallocate_memory(&mut self, allocation: Allocation) {
+        self.live_allocations.remove(&allocation.id);
         self.gpu_allocator.free(allocation.allocation);
     }
+
+    /// Every live allocation, largest first.
+    pub(crate) fn allocation_reports(&self) -> Vec<AllocationReport> {
+        let mut reports = self.live_allocations.values().cloned().collect::<Vec<_>>();
+        reports.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+        reports
+    }
+}
+
+/// A snapshot of the device's memory returned by `Device::memory_report`.
+#[derive(Debug, Clone)]
+pub struct MemoryReport {
+    pub heaps: Vec<MemoryHeapReport>,
+    /// Every live allocation made by paya, largest first.
+    pub allocations: Vec<AllocationReport>,
+    /// The sum of the sizes of `allocations`.
+    pub allocated_bytes: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryHeapReport {
+    pub index: u32,
+    pub size: u64,
+    pub device_local: bool,
+    /// How much the process can allocate from the heap before performance suffers, `None` when
+    /// `VK_EXT_memory_budget` isn't supported.
+    pub budget: Option<u64>,
+    /// Memory used by the whole process on this heap, including allocations made outside of
+    /// paya. `None` when `VK_EXT_memory_budget` isn't supported.
+    pub usage: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AllocationReport {
+    /// The buffer name or `"image"`.
+    pub name: String,
+    pub size: u64,
+    pub location: MemoryLocation,
+    pub memory_flags: MemoryFlags,
+    pub dedicated: bool,
+}
+
+impl MemoryReport {
+    /// Serializes the report so it can be saved and compared between builds.
+    pub fn to_json(&self) -> String {
+        let optional = |value: Option<u64>| value.map_or("null".to_owned(), |v| v.to_string());
+
+        let mut json = String::new();
+        json.push_str("{\n  \"heaps\": [");
+        for (i, heap) in self.heaps.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            let _ = write!(
+                json,
+                "{}\n    {{\"index\": {}, \"size\": {}, \"device_local\": {}, \
+                 \"budget\": {}, \"usage\": {}}}",
+                separator,
+                heap.index,
+                heap.size,
+                heap.device_local,
+                optional(heap.budget),
+                optional(heap.usage),
+            );
+        }
+        json.push_str("\n  ],\n  \"allocations\": [");
+        for (i, allocation) in self.allocations.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            let _ = write!(
+                json,
+                "{}\n    {{\"name\": {}, \"size\": {}, \"location\": \"{:?}\", \
+                 \"dedicated\": {}}}",
+                separator,
+                json_string(&allocation.name),
+                allocation.size,
+                allocation.location,
+                allocation.dedicated,
+            );
+        }
+        let _ = write!(
+            json,
+            "\n  ],\n  \"allocated_bytes\": {}\n}}\n",
+            self.allocated_bytes
+        );
+        json
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
 }
@@ -1,79 +1,181 @@
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::CString,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex, Weak},
+    thread::ThreadId,
+};
 
 use ash::vk;
 
 use crate::{
     common::{
-        AttachmentLoadOp, AttachmentStoreOp, BufferTransition, ClearValue, Extent2D, ImageLayout,
-        ImageTransition,
+        AccessFlags, AccessFlags2, AttachmentLoadOp, AttachmentStoreOp, BarrierInfo,
+        BufferTransition, ClearValue, Extent2D, Format, ImageLayout, ImageSubresourceLayers,
+        ImageSubresourceRange, ImageTransition, ImageUsageFlags, PipelineStageFlags2,
+        QueueOwnershipTransfer,
     },
+    debug::report_error,
     device::{Device, DeviceInner},
-    gpu_resources::{BufferId, ImageId},
+    error::{Error, Result},
+    garbage::Garbage,
+    gpu_resources::{BufferId, ImageId, SamplerId},
+    mipmap,
     pipeline::{ComputePipeline, Pipeline, RasterPipeline},
+    query::{QueryPool, QueryType},
+    queue::QueueType,
 };
 
-#[derive(Clone)]
 pub struct CommandList {
     pub(crate) id: CommandRecorderId,
+    pub(crate) queue_type: QueueType,
     command_pool: vk::CommandPool,
     command_buffer: vk::CommandBuffer,
-    pub(crate) deferred_delete_buffers: Vec<BufferId>,
+    // Destroyed once the submission this list is part of has completed.
+    pub(crate) deferred_garbage: Vec<Garbage>,
+    // Returns the recorder when the list is dropped without being submitted, empty for the
+    // pool's own copies and once the list has been submitted.
+    pool: Weak<Mutex<CommandRecorderPoolInner>>,
 }
 
 impl CommandList {
     pub fn handle(&self) -> vk::CommandBuffer {
         self.command_buffer
     }
+
+    pub fn queue_type(&self) -> QueueType {
+        self.queue_type
+    }
+
+    /// Detaches the list from the pool once it has been submitted, the recorder is freed
+    /// through the garbage queue after the submission has completed instead.
+    pub(crate) fn into_submitted(mut self) -> (CommandRecorderId, Vec<Garbage>) {
+        self.pool = Weak::new();
+        (self.id, std::mem::take(&mut self.deferred_garbage))
+    }
+}
+
+impl Drop for CommandList {
+    fn drop(&mut self) {
+        // Lists that were never submitted aren't pending, the recorder can be reused right away.
+        let Some(pool) = self.pool.upgrade() else {
+            return;
+        };
+        let result = pool.lock().unwrap().free_command_recorder(self.id);
+        if let Err(error) = result {
+            report_error(format_args!(
+                "failed to return an unsubmitted command recorder to the pool: {error}"
+            ));
+        }
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct CommandRecorderId(u32);
 
+/// Hands out recorders to any thread. Every recorder owns its command pool so recorders on
+/// different threads can record at the same time, the lock only covers handing them out and
+/// taking them back. Recorders whose commands are dropped without being submitted are taken
+/// back right away.
 pub struct CommandRecorderPool {
     device_dep: Arc<DeviceInner>,
+    inner: Arc<Mutex<CommandRecorderPoolInner>>,
+}
+
+#[derive(Default)]
+struct CommandRecorderPoolInner {
     recorders: Vec<CommandRecorder>,
-    free_recorders: Vec<CommandRecorderId>,
+    // The thread each recorder was last handed out to, indexed by `CommandRecorderId`.
+    recorder_threads: Vec<ThreadId>,
+    // Freed recorders go back to the thread that used them so each worker keeps reusing its own
+    // command pools. Indexed by `QueueType`, recorders allocate from a pool of their queue's
+    // family.
+    free_recorders: HashMap<ThreadId, [Vec<CommandRecorderId>; 3]>,
 }
 
 impl CommandRecorderPool {
     pub(crate) fn new(device_dep: Arc<DeviceInner>) -> Self {
         Self {
             device_dep,
-            recorders: Vec::new(),
-            free_recorders: Vec::new(),
+            inner: Arc::new(Mutex::new(CommandRecorderPoolInner::default())),
         }
     }
 
-    pub(crate) fn create_command_recorder(&mut self) -> CommandRecorder {
-        if self.free_recorders.is_empty() {
-            let id = CommandRecorderId(self.recorders.len() as u32);
-            self.recorders
-                .push(CommandRecorder::new(self.device_dep.clone(), id));
-            self.free_recorders.push(id);
-        }
-
-        let recorder_id = self
+    /// Reuses a recorder freed on the calling thread, then one freed on any other thread,
+    /// before creating a new one. The command buffer is renamed to `name` on every handout.
+    pub(crate) fn create_command_recorder(
+        &self,
+        queue_type: QueueType,
+        name: &str,
+    ) -> Result<CommandRecorder> {
+        let thread = std::thread::current().id();
+        let mut inner = self.inner.lock().unwrap();
+
+        let free_recorder = inner
             .free_recorders
-            .pop()
-            .expect("Failed to create a command recorder");
+            .get_mut(&thread)
+            .and_then(|free_recorders| free_recorders[queue_type as usize].pop())
+            .or_else(|| {
+                inner
+                    .free_recorders
+                    .values_mut()
+                    .find_map(|free_recorders| free_recorders[queue_type as usize].pop())
+            });
+
+        let recorder_id = match free_recorder {
+            Some(id) => {
+                inner.recorder_threads[id.0 as usize] = thread;
+                id
+            }
+            None => {
+                let id = CommandRecorderId(inner.recorders.len() as u32);
+                inner.recorders.push(CommandRecorder::new(
+                    self.device_dep.clone(),
+                    id,
+                    queue_type,
+                )?);
+                inner.recorder_threads.push(thread);
+                id
+            }
+        };
 
-        self.get_recorder(recorder_id).clone()
+        let recorder =
+            inner.recorders[recorder_id.0 as usize].hand_out(Arc::downgrade(&self.inner));
+        self.device_dep
+            .set_debug_name(recorder.current_command_list.command_buffer, name);
+
+        Ok(recorder)
     }
 
-    pub(crate) fn free_command_recorder(&mut self, id: CommandRecorderId) {
-        self.get_recorder(id).reset();
-        self.free_recorders.push(id);
+    pub(crate) fn free_command_recorder(&self, id: CommandRecorderId) -> Result<()> {
+        self.inner.lock().unwrap().free_command_recorder(id)
     }
+}
 
-    fn get_recorder(&self, id: CommandRecorderId) -> &CommandRecorder {
-        &self.recorders[id.0 as usize]
+impl CommandRecorderPoolInner {
+    fn free_command_recorder(&mut self, id: CommandRecorderId) -> Result<()> {
+        // The recorders are gone once the pool has been dropped.
+        let Some(recorder) = self.recorders.get(id.0 as usize) else {
+            return Ok(());
+        };
+        recorder.reset()?;
+
+        let queue_type = recorder.queue_type;
+        let thread = self.recorder_threads[id.0 as usize];
+        self.free_recorders.entry(thread).or_default()[queue_type as usize].push(id);
+        Ok(())
     }
 }
 
 impl Drop for CommandRecorderPool {
     fn drop(&mut self) {
-        unsafe { self.device_dep.device.device_wait_idle() }.expect("Faile dto wait for device");
-        for recorder in &self.recorders {
+        if let Err(error) = unsafe { self.device_dep.device.device_wait_idle() } {
+            report_error(format_args!(
+                "failed to wait for the device before destroying the command recorders: {error}"
+            ));
+        }
+        let mut inner = self.inner.lock().unwrap();
+        for recorder in std::mem::take(&mut inner.recorders) {
             unsafe {
                 self.device_dep
                     .device
@@ -83,49 +185,77 @@ impl Drop for CommandRecorderPool {
     }
 }
 
-#[derive(Clone)]
 pub struct CommandRecorder {
     device_dep: Arc<DeviceInner>,
     id: CommandRecorderId,
+    queue_type: QueueType,
     pool: vk::CommandPool,
     current_command_list: CommandList,
 }
 
 impl CommandRecorder {
-    pub(crate) fn new(device_dep: Arc<DeviceInner>, id: CommandRecorderId) -> Self {
+    pub(crate) fn new(
+        device_dep: Arc<DeviceInner>,
+        id: CommandRecorderId,
+        queue_type: QueueType,
+    ) -> Result<Self> {
         let command_pool_create_info = vk::CommandPoolCreateInfo::default()
-            .queue_family_index(device_dep.main_queue_family_index)
+            .queue_family_index(device_dep.queue_families.family_index(queue_type))
             .flags(vk::CommandPoolCreateFlags::TRANSIENT);
         let command_pool = unsafe {
             device_dep
                 .device
                 .create_command_pool(&command_pool_create_info, None)
-                .unwrap()
-        };
+        }?;
+        device_dep.set_debug_name(
+            command_pool,
+            &format!("paya {:?} recorder pool {}", queue_type, id.0),
+        );
 
         let mut s = CommandRecorder {
             device_dep,
             pool: command_pool,
             id,
+            queue_type,
             current_command_list: CommandList {
-                deferred_delete_buffers: Vec::new(),
+                deferred_garbage: Vec::new(),
                 id,
+                queue_type,
                 command_pool,
                 command_buffer: vk::CommandBuffer::null(),
+                pool: Weak::new(),
             },
         };
 
-        s.new_command_list();
-        s
+        s.new_command_list()?;
+        Ok(s)
+    }
+
+    /// A copy of a pooled recorder for `create_command_recorder`, the pool keeps the original
+    /// to reset it once its commands have executed.
+    fn hand_out(&self, pool: Weak<Mutex<CommandRecorderPoolInner>>) -> CommandRecorder {
+        CommandRecorder {
+            device_dep: self.device_dep.clone(),
+            id: self.id,
+            queue_type: self.queue_type,
+            pool: self.pool,
+            current_command_list: CommandList {
+                deferred_garbage: Vec::new(),
+                id: self.id,
+                queue_type: self.queue_type,
+                command_pool: self.pool,
+                command_buffer: self.current_command_list.command_buffer,
+                pool,
+            },
+        }
     }
 
-    fn reset(&self) {
+    fn reset(&self) -> Result<()> {
         unsafe {
             self.device_dep
                 .device
                 .reset_command_pool(self.pool, vk::CommandPoolResetFlags::empty())
-        }
-        .expect("Couldnt reset command pool");
+        }?;
 
         unsafe {
             self.device_dep.device.begin_command_buffer(
@@ -133,11 +263,12 @@ impl CommandRecorder {
                 &vk::CommandBufferBeginInfo::default()
                     .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
             )
-        }
-        .expect("Couldnt reset and re begin command buffer");
+        }?;
+
+        Ok(())
     }
 
-    fn new_command_list(&mut self) {
+    fn new_command_list(&mut self) -> Result<()> {
         let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::default()
             .command_pool(self.pool)
             .level(vk::CommandBufferLevel::PRIMARY)
@@ -146,30 +277,63 @@ impl CommandRecorder {
             self.device_dep
                 .device
                 .allocate_command_buffers(&command_buffer_allocate_info)
-                .unwrap()[0]
-        };
+        }?[0];
 
         unsafe {
-            self.device_dep
-                .device
-                .begin_command_buffer(
-                    command_buffer,
-                    &vk::CommandBufferBeginInfo::default()
-                        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
-                )
-                .unwrap();
-        }
+            self.device_dep.device.begin_command_buffer(
+                command_buffer,
+                &vk::CommandBufferBeginInfo::default()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )
+        }?;
 
         self.current_command_list = CommandList {
-            deferred_delete_buffers: Vec::new(),
+            deferred_garbage: Vec::new(),
             id: self.id,
+            queue_type: self.queue_type,
             command_pool: self.pool,
             command_buffer,
+            pool: Weak::new(),
         };
+
+        Ok(())
     }
 
+    pub fn queue_type(&self) -> QueueType {
+        self.queue_type
+    }
+
+    pub(crate) fn handle(&self) -> vk::CommandBuffer {
+        self.current_command_list.command_buffer
+    }
+
+    /// Destroys the buffer once the submission of this recorder's commands has completed, for
+    /// buffers the recorded commands still use.
     pub fn destroy_buffer_deferred(&mut self, id: BufferId) {
-        self.current_command_list.deferred_delete_buffers.push(id);
+        self.current_command_list
+            .deferred_garbage
+            .push(Garbage::Buffer(id));
+    }
+
+    /// Destroys the image once the submission of this recorder's commands has completed.
+    pub fn destroy_image_deferred(&mut self, id: ImageId) {
+        self.current_command_list
+            .deferred_garbage
+            .push(Garbage::Image(id));
+    }
+
+    /// Destroys the sampler once the submission of this recorder's commands has completed.
+    pub fn destroy_sampler_deferred(&mut self, id: SamplerId) {
+        self.current_command_list
+            .deferred_garbage
+            .push(Garbage::Sampler(id));
+    }
+
+    /// Destroys the pipeline once the submission of this recorder's commands has completed.
+    pub fn destroy_pipeline_deferred(&mut self, pipeline: impl Pipeline + 'static) {
+        self.current_command_list
+            .deferred_garbage
+            .push(Garbage::Pipeline(Box::new(pipeline)));
     }
 
     pub fn clear_color_image(
@@ -180,25 +344,35 @@ impl CommandRecorder {
         green: f32,
         blue: f32,
         alpha: f32,
-    ) {
+    ) -> Result<()> {
         let clear_color = vk::ClearColorValue {
             float32: [red, green, blue, alpha],
         };
 
-        let image_subresource_range = vk::ImageSubresourceRange::default()
-            .aspect_mask(vk::ImageAspectFlags::COLOR)
-            .level_count(1)
-            .layer_count(1);
+        let (image_handle, format) = {
+            let image = device.get_image(image)?;
+            (image.handle, image.info.format)
+        };
+        if format.has_depth() {
+            return Err(Error::InvalidUsage(format!(
+                "{:?} is a depth format, depth images are cleared through their load op",
+                format
+            )));
+        }
+        let image_subresource_range =
+            ImageSubresourceRange::default().to_vk(vk::ImageAspectFlags::COLOR);
 
         unsafe {
             device.handle().cmd_clear_color_image(
                 self.current_command_list.command_buffer,
-                device.get_image(image).handle,
+                image_handle,
                 vk::ImageLayout::TRANSFER_DST_OPTIMAL,
                 &clear_color,
                 &[image_subresource_range],
             );
         }
+
+        Ok(())
     }
 
     pub fn copy_buffer_to_buffer(
@@ -209,21 +383,24 @@ impl CommandRecorder {
         dst: BufferId,
         dst_offset: u64,
         size: u64,
-    ) {
-        let src_buffer = device.get_buffer(src);
-        let dst_buffer = device.get_buffer(dst);
+    ) -> Result<()> {
+        // Only one resource is borrowed at a time, see `ResourceRef`.
+        let src_buffer = device.get_buffer(src)?.handle;
+        let dst_buffer = device.get_buffer(dst)?.handle;
 
         unsafe {
             device.handle().cmd_copy_buffer(
                 self.current_command_list.handle(),
-                src_buffer.handle,
-                dst_buffer.handle,
+                src_buffer,
+                dst_buffer,
                 &[vk::BufferCopy::default()
                     .size(size)
                     .src_offset(src_offset)
                     .dst_offset(dst_offset)],
             )
         }
+
+        Ok(())
     }
 
     pub fn copy_buffer_to_buffer_multiple(
@@ -232,9 +409,9 @@ impl CommandRecorder {
         src: BufferId,
         dst: BufferId,
         regions: Vec<CopyRegion>,
-    ) {
-        let src_buffer = device.get_buffer(src);
-        let dst_buffer = device.get_buffer(dst);
+    ) -> Result<()> {
+        let src_buffer = device.get_buffer(src)?.handle;
+        let dst_buffer = device.get_buffer(dst)?.handle;
 
         let vk_regions = regions
             .into_iter()
@@ -244,119 +421,382 @@ impl CommandRecorder {
         unsafe {
             device.handle().cmd_copy_buffer(
                 self.current_command_list.handle(),
-                src_buffer.handle,
-                dst_buffer.handle,
+                src_buffer,
+                dst_buffer,
                 &vk_regions,
             )
         }
-    }
 
-    pub fn blit_image_to_image(&mut self, device: &Device, src: ImageId, dst: ImageId) {
-        let src_image = device.get_image(src);
-        let dst_image = device.get_image(dst);
+        Ok(())
+    }
 
-        let src_subresource = vk::ImageSubresourceLayers::default()
-            .aspect_mask(vk::ImageAspectFlags::COLOR)
-            .mip_level(0)
-            .base_array_layer(0)
-            .layer_count(1);
-        let dst_subresource = vk::ImageSubresourceLayers::default()
-            .aspect_mask(vk::ImageAspectFlags::COLOR)
-            .mip_level(0)
-            .base_array_layer(0)
-            .layer_count(1);
+    /// Blits the whole of a mip level onto another, both subresources need the same number of
+    /// array layers. Depth and stencil images are blitted with nearest filtering and only onto
+    /// images with the same aspects.
+    pub fn blit_image_to_image(
+        &mut self,
+        device: &Device,
+        src: ImageId,
+        src_subresource: ImageSubresourceLayers,
+        dst: ImageId,
+        dst_subresource: ImageSubresourceLayers,
+    ) -> Result<()> {
+        let (src_handle, src_subresource, src_extent) =
+            blit_subresource(device, src, src_subresource)?;
+        let (dst_handle, dst_subresource, dst_extent) =
+            blit_subresource(device, dst, dst_subresource)?;
+        if src_subresource.aspect_mask != dst_subresource.aspect_mask {
+            return Err(Error::InvalidUsage(
+                "Blit between images with different aspects, depth and stencil images can only \
+                 be blitted onto images with the same aspects"
+                    .to_owned(),
+            ));
+        }
+        if src_subresource.layer_count != dst_subresource.layer_count {
+            return Err(Error::InvalidUsage(format!(
+                "Blit from {} to {} array layers, both need the same number of layers",
+                src_subresource.layer_count, dst_subresource.layer_count
+            )));
+        }
 
+        // Depth and stencil can't be filtered.
+        let filter = if src_subresource.aspect_mask == vk::ImageAspectFlags::COLOR {
+            vk::Filter::LINEAR
+        } else {
+            vk::Filter::NEAREST
+        };
         let region = vk::ImageBlit::default()
             .src_subresource(src_subresource)
-            .src_offsets([
-                vk::Offset3D::default(),
-                vk::Offset3D {
-                    x: src_image.info.extent.width as i32,
-                    y: src_image.info.extent.height as i32,
-                    z: 1,
-                },
-            ])
+            .src_offsets([vk::Offset3D::default(), src_extent])
             .dst_subresource(dst_subresource)
-            .dst_offsets([
-                vk::Offset3D::default(),
-                vk::Offset3D {
-                    x: dst_image.info.extent.width as i32,
-                    y: dst_image.info.extent.height as i32,
-                    z: 1,
-                },
-            ]);
+            .dst_offsets([vk::Offset3D::default(), dst_extent]);
 
         unsafe {
             device.handle().cmd_blit_image(
                 self.current_command_list.command_buffer,
-                src_image.handle,
+                src_handle,
                 vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                dst_image.handle,
+                dst_handle,
                 vk::ImageLayout::TRANSFER_DST_OPTIMAL,
                 &[region],
-                vk::Filter::LINEAR,
+                filter,
             );
         }
+
+        Ok(())
     }
 
+    /// Fills every mip level below level 0 by blitting each level onto the next. Level 0 is
+    /// taken out of `src_layout` and the whole image ends up in `dst_layout`, the contents of
+    /// the other levels are discarded. Needs a format with linear blit support, the others go
+    /// through `generate_mipmaps_compute`.
+    pub fn generate_mipmaps(
+        &mut self,
+        device: &Device,
+        image: ImageId,
+        src_layout: ImageLayout,
+        dst_layout: ImageLayout,
+    ) -> Result<()> {
+        mipmap::generate_mipmaps(self, device, image, src_layout, dst_layout)
+    }
+
+    /// Fills the mip chain of a single layer 2D storage image by dispatching `pipeline` once
+    /// per level, see `Device::create_mip_reduce_pipeline`. Custom reducers such as depth
+    /// pyramids take `MipReducePushConstants` and run 8x8 threads per group. `pipeline` stays
+    /// bound afterwards.
+    pub fn generate_mipmaps_compute(
+        &mut self,
+        device: &Device,
+        image: ImageId,
+        pipeline: &ComputePipeline,
+        src_layout: ImageLayout,
+        dst_layout: ImageLayout,
+    ) -> Result<()> {
+        mipmap::generate_mipmaps_compute(self, device, image, pipeline, src_layout, dst_layout)
+    }
+
+    /// Stages are guessed from the accesses, which over or under synchronizes for most
+    /// accesses.
+    #[deprecated(note = "use `pipeline_barrier` with explicit stages")]
     pub fn pipeline_barrier_buffer_transition(
         &mut self,
         device: &Device,
         transition: BufferTransition,
-    ) {
-        let buffer = device.get_buffer(transition.buffer);
+    ) -> Result<()> {
+        let (buffer_handle, buffer_size) = {
+            let buffer = device.get_buffer(transition.buffer)?;
+            (buffer.handle, buffer.size)
+        };
+        let scope = self.barrier_scope(
+            device,
+            transition.src_access,
+            transition.dst_access,
+            transition.ownership_transfer,
+        )?;
 
         let barrier = vk::BufferMemoryBarrier::default()
-            .buffer(buffer.handle)
-            .size(buffer.size)
+            .src_access_mask(scope.src_access)
+            .dst_access_mask(scope.dst_access)
+            .src_queue_family_index(scope.src_queue_family_index)
+            .dst_queue_family_index(scope.dst_queue_family_index)
+            .buffer(buffer_handle)
+            .size(buffer_size)
             .offset(0);
 
         unsafe {
             device.handle().cmd_pipeline_barrier(
                 self.current_command_list.command_buffer,
-                transition.src_access.vk_stages(),
-                transition.dst_access.vk_stages(),
+                scope.src_stages,
+                scope.dst_stages,
                 vk::DependencyFlags::empty(),
                 &[],
                 &[barrier],
                 &[],
             );
         }
+
+        Ok(())
     }
 
+    /// Stages are guessed from the accesses like `pipeline_barrier_buffer_transition`.
+    #[deprecated(note = "use `pipeline_barrier` with explicit stages")]
     pub fn pipeline_barrier_image_transition(
         &mut self,
         device: &Device,
         transition: ImageTransition,
-    ) {
-        let image = device.get_image(transition.image);
+    ) -> Result<()> {
+        let (image, aspect_mask) = {
+            let image = device.get_image(transition.image)?;
+            (image.handle, image.info.format.aspect_mask())
+        };
+        let scope = self.barrier_scope(
+            device,
+            transition.src_access,
+            transition.dst_access,
+            transition.ownership_transfer,
+        )?;
 
         let barrier = vk::ImageMemoryBarrier::default()
-            .src_access_mask(transition.src_access.into())
-            .dst_access_mask(transition.dst_access.into())
+            .src_access_mask(scope.src_access)
+            .dst_access_mask(scope.dst_access)
             .old_layout(transition.src_layout.into())
             .new_layout(transition.dst_layout.into())
-            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-            .image(image.handle)
-            .subresource_range(
-                vk::ImageSubresourceRange::default()
-                    .aspect_mask(vk::ImageAspectFlags::COLOR)
-                    .level_count(1)
-                    .layer_count(1),
-            );
+            .src_queue_family_index(scope.src_queue_family_index)
+            .dst_queue_family_index(scope.dst_queue_family_index)
+            .image(image)
+            .subresource_range(transition.subresource_range.to_vk(aspect_mask));
 
         unsafe {
             device.handle().cmd_pipeline_barrier(
                 self.current_command_list.command_buffer,
-                transition.src_access.vk_stages(),
-                transition.dst_access.vk_stages(),
+                scope.src_stages,
+                scope.dst_stages,
                 vk::DependencyFlags::empty(),
                 &[],
                 &[],
                 &[barrier],
             );
         }
+
+        Ok(())
+    }
+
+    /// Records every barrier in `info` with a single `vkCmdPipelineBarrier2`. Stages and accesses
+    /// are used as given, ownership transfers only keep the release or acquire half that belongs
+    /// to this recorder's queue.
+    pub fn pipeline_barrier(&mut self, device: &Device, info: &BarrierInfo) -> Result<()> {
+        let memory_barriers = info
+            .memory_barriers
+            .iter()
+            .map(|barrier| {
+                vk::MemoryBarrier2::default()
+                    .src_stage_mask(barrier.src_stages.into())
+                    .src_access_mask(barrier.src_access.into())
+                    .dst_stage_mask(barrier.dst_stages.into())
+                    .dst_access_mask(barrier.dst_access.into())
+            })
+            .collect::<Vec<_>>();
+
+        let buffer_barriers = info
+            .buffer_barriers
+            .iter()
+            .map(|barrier| {
+                let scope = self.barrier_scope2(
+                    device,
+                    barrier.src_stages,
+                    barrier.src_access,
+                    barrier.dst_stages,
+                    barrier.dst_access,
+                    barrier.ownership_transfer,
+                )?;
+                let buffer_handle = device.get_buffer(barrier.buffer)?.handle;
+
+                Ok(vk::BufferMemoryBarrier2::default()
+                    .src_stage_mask(scope.src_stages)
+                    .src_access_mask(scope.src_access)
+                    .dst_stage_mask(scope.dst_stages)
+                    .dst_access_mask(scope.dst_access)
+                    .src_queue_family_index(scope.src_queue_family_index)
+                    .dst_queue_family_index(scope.dst_queue_family_index)
+                    .buffer(buffer_handle)
+                    .offset(barrier.offset)
+                    .size(barrier.size))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let image_barriers = info
+            .image_barriers
+            .iter()
+            .map(|barrier| {
+                let scope = self.barrier_scope2(
+                    device,
+                    barrier.src_stages,
+                    barrier.src_access,
+                    barrier.dst_stages,
+                    barrier.dst_access,
+                    barrier.ownership_transfer,
+                )?;
+                let (image_handle, aspect_mask) = {
+                    let image = device.get_image(barrier.image)?;
+                    (image.handle, image.info.format.aspect_mask())
+                };
+
+                Ok(vk::ImageMemoryBarrier2::default()
+                    .src_stage_mask(scope.src_stages)
+                    .src_access_mask(scope.src_access)
+                    .dst_stage_mask(scope.dst_stages)
+                    .dst_access_mask(scope.dst_access)
+                    .old_layout(barrier.src_layout.into())
+                    .new_layout(barrier.dst_layout.into())
+                    .src_queue_family_index(scope.src_queue_family_index)
+                    .dst_queue_family_index(scope.dst_queue_family_index)
+                    .image(image_handle)
+                    .subresource_range(barrier.subresource_range.to_vk(aspect_mask)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let dependency_info = vk::DependencyInfo::default()
+            .memory_barriers(&memory_barriers)
+            .buffer_memory_barriers(&buffer_barriers)
+            .image_memory_barriers(&image_barriers);
+
+        unsafe {
+            device
+                .inner()
+                .synchronization2_loader
+                .cmd_pipeline_barrier2(self.current_command_list.command_buffer, &dependency_info)
+        };
+
+        Ok(())
+    }
+
+    /// Like `barrier_scope` with the stages given explicitly.
+    fn barrier_scope2(
+        &self,
+        device: &Device,
+        src_stages: PipelineStageFlags2,
+        src_access: AccessFlags2,
+        dst_stages: PipelineStageFlags2,
+        dst_access: AccessFlags2,
+        ownership_transfer: Option<QueueOwnershipTransfer>,
+    ) -> Result<BarrierScope2> {
+        let mut scope = BarrierScope2 {
+            src_stages: src_stages.into(),
+            src_access: src_access.into(),
+            dst_stages: dst_stages.into(),
+            dst_access: dst_access.into(),
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        };
+
+        let Some(transfer) = ownership_transfer else {
+            return Ok(scope);
+        };
+
+        let queue_families = &device.inner().queue_families;
+        let src_family = queue_families.family_index(transfer.src_queue);
+        let dst_family = queue_families.family_index(transfer.dst_queue);
+        if src_family == dst_family {
+            return Ok(scope);
+        }
+
+        let own_family = queue_families.family_index(self.queue_type);
+        if own_family == src_family {
+            scope.dst_stages = vk::PipelineStageFlags2::NONE;
+            scope.dst_access = vk::AccessFlags2::NONE;
+        } else if own_family == dst_family {
+            scope.src_stages = vk::PipelineStageFlags2::NONE;
+            scope.src_access = vk::AccessFlags2::NONE;
+        } else {
+            return Err(Error::InvalidUsage(format!(
+                "ownership transfer from {:?} to {:?} recorded on the {:?} queue",
+                transfer.src_queue, transfer.dst_queue, self.queue_type
+            )));
+        }
+        scope.src_queue_family_index = src_family;
+        scope.dst_queue_family_index = dst_family;
+
+        Ok(scope)
+    }
+
+    /// Limits the barrier to the stages this recorder's queue supports and, for ownership
+    /// transfers, to the release or acquire half that belongs to this queue.
+    fn barrier_scope(
+        &self,
+        device: &Device,
+        src_access: AccessFlags,
+        dst_access: AccessFlags,
+        ownership_transfer: Option<QueueOwnershipTransfer>,
+    ) -> Result<BarrierScope> {
+        let supported_stages = self.queue_type.supported_stages();
+        let src_stages = src_access.vk_stages() & supported_stages;
+        let dst_stages = dst_access.vk_stages() & supported_stages;
+
+        let mut scope = BarrierScope {
+            src_access: src_access.into(),
+            dst_access: dst_access.into(),
+            src_stages: if src_stages.is_empty() {
+                vk::PipelineStageFlags::TOP_OF_PIPE
+            } else {
+                src_stages
+            },
+            dst_stages: if dst_stages.is_empty() {
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE
+            } else {
+                dst_stages
+            },
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        };
+
+        let Some(transfer) = ownership_transfer else {
+            return Ok(scope);
+        };
+
+        let queue_families = &device.inner().queue_families;
+        let src_family = queue_families.family_index(transfer.src_queue);
+        let dst_family = queue_families.family_index(transfer.dst_queue);
+        if src_family == dst_family {
+            return Ok(scope);
+        }
+
+        let own_family = queue_families.family_index(self.queue_type);
+        if own_family == src_family {
+            scope.dst_access = vk::AccessFlags::empty();
+            scope.dst_stages = vk::PipelineStageFlags::BOTTOM_OF_PIPE;
+        } else if own_family == dst_family {
+            scope.src_access = vk::AccessFlags::empty();
+            scope.src_stages = vk::PipelineStageFlags::TOP_OF_PIPE;
+        } else {
+            return Err(Error::InvalidUsage(format!(
+                "ownership transfer from {:?} to {:?} recorded on the {:?} queue",
+                transfer.src_queue, transfer.dst_queue, self.queue_type
+            )));
+        }
+        scope.src_queue_family_index = src_family;
+        scope.dst_queue_family_index = dst_family;
+
+        Ok(scope)
     }
 
     pub fn bind_compute_pipeline(&mut self, device: &Device, pipeline: &ComputePipeline) {
@@ -396,27 +836,37 @@ impl CommandRecorder {
         }
     }
 
-    pub fn begin_rendering(&mut self, device: &Device, info: &BeginRenderingInfo) {
+    pub fn begin_rendering(&mut self, device: &Device, info: &BeginRenderingInfo) -> Result<()> {
         let color_attachments = info
             .color_attachments
             .iter()
             .map(|info| {
-                let image_info = device.get_image(info.image);
-
-                vk::RenderingAttachmentInfo::default()
-                    .image_view(
-                        image_info
-                            .view
-                            .expect("Image doesnt have color attachment usage applied."),
+                let image_view = device.get_image(info.image)?.view.ok_or_else(|| {
+                    Error::InvalidUsage(
+                        "image doesn't have color attachment usage applied".to_owned(),
                     )
+                })?;
+
+                Ok(vk::RenderingAttachmentInfo::default()
+                    .image_view(image_view)
                     .load_op(info.load_op.clone().into())
                     .store_op(info.store_op.clone().into())
                     .clear_value(info.clear_value.clone().into())
-                    .image_layout(info.layout.into())
+                    .image_layout(info.layout.into()))
             })
-            .collect::<Vec<_>>();
-
-        let rendering_info = vk::RenderingInfo::default()
+            .collect::<Result<Vec<_>>>()?;
+        let depth_attachment = info
+            .depth_attachment
+            .as_ref()
+            .map(|info| depth_stencil_attachment(device, info, "depth", Format::has_depth))
+            .transpose()?;
+        let stencil_attachment = info
+            .stencil_attachment
+            .as_ref()
+            .map(|info| depth_stencil_attachment(device, info, "stencil", Format::has_stencil))
+            .transpose()?;
+
+        let mut rendering_info = vk::RenderingInfo::default()
             .render_area(
                 vk::Rect2D::default()
                     .offset(vk::Offset2D::default())
@@ -425,6 +875,12 @@ impl CommandRecorder {
             .color_attachments(&color_attachments)
             .layer_count(1)
             .view_mask(0);
+        if let Some(depth_attachment) = &depth_attachment {
+            rendering_info = rendering_info.depth_attachment(depth_attachment);
+        }
+        if let Some(stencil_attachment) = &stencil_attachment {
+            rendering_info = rendering_info.stencil_attachment(stencil_attachment);
+        }
 
         unsafe {
             device
@@ -432,6 +888,8 @@ impl CommandRecorder {
                 .dynamic_rendering_loader
                 .cmd_begin_rendering(self.current_command_list.command_buffer, &rendering_info)
         };
+
+        Ok(())
     }
 
     pub fn end_rendering(&mut self, device: &Device) {
@@ -472,28 +930,32 @@ impl CommandRecorder {
         }
     }
 
-    pub fn set_index_buffer(&mut self, device: &Device, buffer: BufferId) {
-        let buffer = device.get_buffer(buffer);
+    pub fn set_index_buffer(&mut self, device: &Device, buffer: BufferId) -> Result<()> {
+        let buffer = device.get_buffer(buffer)?.handle;
         unsafe {
             device.handle().cmd_bind_index_buffer(
                 self.current_command_list.command_buffer,
-                buffer.handle,
+                buffer,
                 0,
                 vk::IndexType::UINT32,
             );
         }
+
+        Ok(())
     }
 
-    pub fn set_vertex_buffer(&mut self, device: &Device, buffer: BufferId) {
-        let buffer = device.get_buffer(buffer);
+    pub fn set_vertex_buffer(&mut self, device: &Device, buffer: BufferId) -> Result<()> {
+        let buffer = device.get_buffer(buffer)?.handle;
         unsafe {
             device.handle().cmd_bind_vertex_buffers(
                 self.current_command_list.command_buffer,
                 0,
-                &[buffer.handle],
+                &[buffer],
                 &[0],
             );
         }
+
+        Ok(())
     }
 
     pub fn draw_indexed(&mut self, device: &Device, index_count: u32) {
@@ -522,6 +984,97 @@ impl CommandRecorder {
         }
     }
 
+    /// Resets the queries so they can be written again, has to be recorded outside of
+    /// rendering.
+    pub fn reset_query_pool(
+        &mut self,
+        device: &Device,
+        pool: &QueryPool,
+        first_query: u32,
+        query_count: u32,
+    ) -> Result<()> {
+        pool.check_range(first_query, query_count)?;
+        unsafe {
+            device.handle().cmd_reset_query_pool(
+                self.current_command_list.command_buffer,
+                pool.handle(),
+                first_query,
+                query_count,
+            )
+        };
+
+        Ok(())
+    }
+
+    /// Writes the time at which every earlier command has finished `stage`.
+    pub fn write_timestamp(
+        &mut self,
+        device: &Device,
+        pool: &QueryPool,
+        stage: PipelineStageFlags2,
+        query: u32,
+    ) -> Result<()> {
+        if pool.info().query_type != QueryType::Timestamp {
+            return Err(Error::InvalidUsage(format!(
+                "query pool {} doesn't hold timestamps",
+                pool.info().name
+            )));
+        }
+        if device.inner().timestamp_valid_bits(self.queue_type) == 0 {
+            return Err(Error::InvalidUsage(format!(
+                "the {:?} queue doesn't support timestamps",
+                self.queue_type
+            )));
+        }
+        pool.check_range(query, 1)?;
+
+        unsafe {
+            device.inner().synchronization2_loader.cmd_write_timestamp2(
+                self.current_command_list.command_buffer,
+                stage.into(),
+                pool.handle(),
+                query,
+            )
+        };
+
+        Ok(())
+    }
+
+    /// Starts a pipeline statistics or occlusion query, ended by `end_query`.
+    pub fn begin_query(&mut self, device: &Device, pool: &QueryPool, query: u32) -> Result<()> {
+        if pool.info().query_type == QueryType::Timestamp {
+            return Err(Error::InvalidUsage(format!(
+                "timestamp query pool {} is written with write_timestamp",
+                pool.info().name
+            )));
+        }
+        pool.check_range(query, 1)?;
+
+        unsafe {
+            device.handle().cmd_begin_query(
+                self.current_command_list.command_buffer,
+                pool.handle(),
+                query,
+                vk::QueryControlFlags::empty(),
+            )
+        };
+
+        Ok(())
+    }
+
+    pub fn end_query(&mut self, device: &Device, pool: &QueryPool, query: u32) -> Result<()> {
+        pool.check_range(query, 1)?;
+        unsafe {
+            device.handle().cmd_end_query(
+                self.current_command_list.command_buffer,
+                pool.handle(),
+                query,
+            )
+        };
+
+        Ok(())
+    }
+
     pub fn dispatch(&mut self, device: &Device, x: u32, y: u32, z: u32) {
         unsafe {
             device
@@ -530,18 +1083,116 @@ impl CommandRecorder {
         }
     }
 
-    pub fn finish(mut self, device: &Device) -> CommandList {
+    /// Opens a named region shown in captures and validation messages, closed by `end_label`.
+    /// Does nothing when debug utils aren't enabled.
+    pub fn begin_label(&mut self, device: &Device, label: &str, color: [f32; 4]) {
+        let Some(debug_utils) = &device.inner().debug_utils else {
+            return;
+        };
+        let c_label = CString::new(label).unwrap_or_default();
+        let label_info = vk::DebugUtilsLabelEXT::default()
+            .label_name(&c_label)
+            .color(color);
+
+        unsafe {
+            debug_utils
+                .cmd_begin_debug_utils_label(self.current_command_list.command_buffer, &label_info)
+        };
+    }
+
+    pub fn end_label(&mut self, device: &Device) {
+        let Some(debug_utils) = &device.inner().debug_utils else {
+            return;
+        };
+
+        unsafe { debug_utils.cmd_end_debug_utils_label(self.current_command_list.command_buffer) };
+    }
+
+    /// Marks a single point in the command stream.
+    pub fn insert_label(&mut self, device: &Device, label: &str, color: [f32; 4]) {
+        let Some(debug_utils) = &device.inner().debug_utils else {
+            return;
+        };
+        let c_label = CString::new(label).unwrap_or_default();
+        let label_info = vk::DebugUtilsLabelEXT::default()
+            .label_name(&c_label)
+            .color(color);
+
+        unsafe {
+            debug_utils
+                .cmd_insert_debug_utils_label(self.current_command_list.command_buffer, &label_info)
+        };
+    }
+
+    /// Begins a label that's ended when the returned scope is dropped. The scope derefs to the
+    /// recorder so commands can be recorded through it.
+    pub fn label_scope<'a>(
+        &'a mut self,
+        device: &'a Device,
+        label: &str,
+        color: [f32; 4],
+    ) -> LabelScope<'a> {
+        self.begin_label(device, label, color);
+        LabelScope {
+            recorder: self,
+            device,
+        }
+    }
+
+    pub fn finish(self, device: &Device) -> Result<CommandList> {
         unsafe {
             device
                 .handle()
                 .end_command_buffer(self.current_command_list.command_buffer)
-                .unwrap();
-        }
+        }?;
 
-        self.current_command_list
+        Ok(self.current_command_list)
+    }
+}
+
+pub struct LabelScope<'a> {
+    recorder: &'a mut CommandRecorder,
+    device: &'a Device,
+}
+
+impl Deref for LabelScope<'_> {
+    type Target = CommandRecorder;
+
+    fn deref(&self) -> &Self::Target {
+        self.recorder
+    }
+}
+
+impl DerefMut for LabelScope<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.recorder
+    }
+}
+
+impl Drop for LabelScope<'_> {
+    fn drop(&mut self) {
+        self.recorder.end_label(self.device);
     }
 }
 
+struct BarrierScope {
+    src_access: vk::AccessFlags,
+    dst_access: vk::AccessFlags,
+    src_stages: vk::PipelineStageFlags,
+    dst_stages: vk::PipelineStageFlags,
+    src_queue_family_index: u32,
+    dst_queue_family_index: u32,
+}
+
+struct BarrierScope2 {
+    src_stages: vk::PipelineStageFlags2,
+    src_access: vk::AccessFlags2,
+    dst_stages: vk::PipelineStageFlags2,
+    dst_access: vk::AccessFlags2,
+    src_queue_family_index: u32,
+    dst_queue_family_index: u32,
+}
+
 pub struct RenderingAttachment {
     pub image: ImageId,
     pub layout: ImageLayout,
@@ -553,6 +1204,10 @@ pub struct RenderingAttachment {
 pub struct BeginRenderingInfo {
     pub render_area: Extent2D,
     pub color_attachments: Vec<RenderingAttachment>,
+    /// Needs an image with a depth format.
+    pub depth_attachment: Option<RenderingAttachment>,
+    /// Needs an image with a stencil format, usually the same one as `depth_attachment`.
+    pub stencil_attachment: Option<RenderingAttachment>,
 }
 
 pub struct CopyRegion {
@@ -569,3 +1224,81 @@ impl Into<vk::BufferCopy> for CopyRegion {
             .dst_offset(self.dst_offset)
     }
 }
+
+/// The depth or stencil attachment of `begin_rendering`, `has_aspect` checks that the image's
+/// format has the aspect named `aspect`.
+fn depth_stencil_attachment(
+    device: &Device,
+    info: &RenderingAttachment,
+    aspect: &str,
+    has_aspect: fn(&Format) -> bool,
+) -> Result<vk::RenderingAttachmentInfo<'static>> {
+    let image = device.get_image(info.image)?;
+    if !has_aspect(&image.info.format) {
+        return Err(Error::InvalidUsage(format!(
+            "Image {} is the {} attachment but {:?} has no {}",
+            image.info.name, aspect, image.info.format, aspect
+        )));
+    }
+    let image_view = match image.view {
+        Some(view)
+            if image
+                .info
+                .usage
+                .contains(ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT) =>
+        {
+            view
+        }
+        _ => {
+            return Err(Error::InvalidUsage(format!(
+                "Image {} doesn't have depth stencil attachment usage applied",
+                image.info.name
+            )))
+        }
+    };
+
+    Ok(vk::RenderingAttachmentInfo::default()
+        .image_view(image_view)
+        .load_op(info.load_op.clone().into())
+        .store_op(info.store_op.clone().into())
+        .clear_value(info.clear_value.into())
+        .image_layout(info.layout.into()))
+}
+
+/// Resolves the layers of a blit and returns them with the far corner of the mip level.
+fn blit_subresource(
+    device: &Device,
+    id: ImageId,
+    subresource: ImageSubresourceLayers,
+) -> Result<(vk::Image, vk::ImageSubresourceLayers, vk::Offset3D)> {
+    let image = device.get_image(id)?;
+    let info = &image.info;
+    let range = ImageSubresourceRange::new(
+        subresource.mip_level,
+        1,
+        subresource.base_array_layer,
+        subresource.layer_count,
+    );
+    if !range.fits(info.mip_levels, info.array_layers) {
+        return Err(Error::InvalidUsage(format!(
+            "{:?} is out of bounds of image {} ({} mip levels, {} array layers)",
+            subresource, info.name, info.mip_levels, info.array_layers
+        )));
+    }
+    let range = range.resolve(info.mip_levels, info.array_layers);
+    let extent = info.mip_extent(subresource.mip_level);
+
+    Ok((
+        image.handle,
+        vk::ImageSubresourceLayers::default()
+            .aspect_mask(info.format.aspect_mask())
+            .mip_level(range.base_mip_level)
+            .base_array_layer(range.base_array_layer)
+            .layer_count(range.layer_count),
+        vk::Offset3D {
+            x: extent.width as i32,
+            y: extent.height as i32,
+            z: extent.depth as i32,
+        },
+    ))
+}
@@ -1,15 +1,138 @@
 use ash::vk;
 use bitflags::bitflags;
 
-use crate::gpu_resources::{BufferId, ImageId};
+use crate::{
+    error::{Error, Result},
+    gpu_resources::{BufferId, ImageId},
+    queue::QueueType,
+};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Format {
     R8G8B8A8Unorm,
     R8G8B8A8Srgb,
 
     B8G8R8A8Unorm,
     B8G8R8A8Srgb,
+
+    R8Unorm,
+    R8G8Unorm,
+    R16Sfloat,
+    R16G16Sfloat,
+    R16G16B16A16Sfloat,
+    R32Sfloat,
+    R32G32Sfloat,
+    R32G32B32A32Sfloat,
+    R32Uint,
+    R32G32Uint,
+    R32G32B32A32Uint,
+    R32Sint,
+
+    D16Unorm,
+    D32Sfloat,
+    D24UnormS8Uint,
+    D32SfloatS8Uint,
+}
+
+impl Format {
+    pub const ALL: &'static [Format] = &[
+        Format::R8G8B8A8Unorm,
+        Format::R8G8B8A8Srgb,
+        Format::B8G8R8A8Unorm,
+        Format::B8G8R8A8Srgb,
+        Format::R8Unorm,
+        Format::R8G8Unorm,
+        Format::R16Sfloat,
+        Format::R16G16Sfloat,
+        Format::R16G16B16A16Sfloat,
+        Format::R32Sfloat,
+        Format::R32G32Sfloat,
+        Format::R32G32B32A32Sfloat,
+        Format::R32Uint,
+        Format::R32G32Uint,
+        Format::R32G32B32A32Uint,
+        Format::R32Sint,
+        Format::D16Unorm,
+        Format::D32Sfloat,
+        Format::D24UnormS8Uint,
+        Format::D32SfloatS8Uint,
+    ];
+
+    pub fn bytes_per_texel(&self) -> u32 {
+        match self {
+            Format::R8Unorm => 1,
+            Format::R8G8Unorm | Format::R16Sfloat | Format::D16Unorm => 2,
+            Format::R8G8B8A8Unorm
+            | Format::R8G8B8A8Srgb
+            | Format::B8G8R8A8Unorm
+            | Format::B8G8R8A8Srgb
+            | Format::R16G16Sfloat
+            | Format::R32Sfloat
+            | Format::R32Uint
+            | Format::R32Sint
+            | Format::D32Sfloat
+            | Format::D24UnormS8Uint => 4,
+            Format::R16G16B16A16Sfloat
+            | Format::R32G32Sfloat
+            | Format::R32G32Uint
+            | Format::D32SfloatS8Uint => 8,
+            Format::R32G32B32A32Sfloat | Format::R32G32B32A32Uint => 16,
+        }
+    }
+
+    /// The glsl format qualifier and image type prefix (`""`, `"u"` or `"i"`) storage images
+    /// of this format are declared with, `None` for formats that can't be used as storage
+    /// images. The preamble declares a `get_storage_image_<qualifier>(id)` accessor for each.
+    pub fn storage_image_qualifier(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            Format::R8G8B8A8Unorm => Some(("rgba8", "")),
+            Format::R8Unorm => Some(("r8", "")),
+            Format::R8G8Unorm => Some(("rg8", "")),
+            Format::R16Sfloat => Some(("r16f", "")),
+            Format::R16G16Sfloat => Some(("rg16f", "")),
+            Format::R16G16B16A16Sfloat => Some(("rgba16f", "")),
+            Format::R32Sfloat => Some(("r32f", "")),
+            Format::R32G32Sfloat => Some(("rg32f", "")),
+            Format::R32G32B32A32Sfloat => Some(("rgba32f", "")),
+            Format::R32Uint => Some(("r32ui", "u")),
+            Format::R32G32Uint => Some(("rg32ui", "u")),
+            Format::R32G32B32A32Uint => Some(("rgba32ui", "u")),
+            Format::R32Sint => Some(("r32i", "i")),
+            Format::R8G8B8A8Srgb
+            | Format::B8G8R8A8Unorm
+            | Format::B8G8R8A8Srgb
+            | Format::D16Unorm
+            | Format::D32Sfloat
+            | Format::D24UnormS8Uint
+            | Format::D32SfloatS8Uint => None,
+        }
+    }
+
+    pub fn has_depth(&self) -> bool {
+        matches!(
+            self,
+            Format::D16Unorm | Format::D32Sfloat | Format::D24UnormS8Uint | Format::D32SfloatS8Uint
+        )
+    }
+
+    pub fn has_stencil(&self) -> bool {
+        matches!(self, Format::D24UnormS8Uint | Format::D32SfloatS8Uint)
+    }
+
+    /// Every aspect of the format, barriers and attachment views cover all of them.
+    pub(crate) fn aspect_mask(&self) -> vk::ImageAspectFlags {
+        let mut aspect_mask = vk::ImageAspectFlags::empty();
+        if self.has_depth() {
+            aspect_mask |= vk::ImageAspectFlags::DEPTH;
+        }
+        if self.has_stencil() {
+            aspect_mask |= vk::ImageAspectFlags::STENCIL;
+        }
+        if aspect_mask.is_empty() {
+            aspect_mask = vk::ImageAspectFlags::COLOR;
+        }
+        aspect_mask
+    }
 }
 
 impl Into<vk::Format> for Format {
@@ -19,19 +142,57 @@ impl Into<vk::Format> for Format {
             Format::R8G8B8A8Srgb => vk::Format::R8G8B8A8_SRGB,
             Format::B8G8R8A8Unorm => vk::Format::B8G8R8A8_UNORM,
             Format::B8G8R8A8Srgb => vk::Format::B8G8R8A8_SRGB,
+            Format::R8Unorm => vk::Format::R8_UNORM,
+            Format::R8G8Unorm => vk::Format::R8G8_UNORM,
+            Format::R16Sfloat => vk::Format::R16_SFLOAT,
+            Format::R16G16Sfloat => vk::Format::R16G16_SFLOAT,
+            Format::R16G16B16A16Sfloat => vk::Format::R16G16B16A16_SFLOAT,
+            Format::R32Sfloat => vk::Format::R32_SFLOAT,
+            Format::R32G32Sfloat => vk::Format::R32G32_SFLOAT,
+            Format::R32G32B32A32Sfloat => vk::Format::R32G32B32A32_SFLOAT,
+            Format::R32Uint => vk::Format::R32_UINT,
+            Format::R32G32Uint => vk::Format::R32G32_UINT,
+            Format::R32G32B32A32Uint => vk::Format::R32G32B32A32_UINT,
+            Format::R32Sint => vk::Format::R32_SINT,
+            Format::D16Unorm => vk::Format::D16_UNORM,
+            Format::D32Sfloat => vk::Format::D32_SFLOAT,
+            Format::D24UnormS8Uint => vk::Format::D24_UNORM_S8_UINT,
+            Format::D32SfloatS8Uint => vk::Format::D32_SFLOAT_S8_UINT,
         }
     }
 }
 
-impl From<vk::Format> for Format {
-    fn from(format: vk::Format) -> Self {
-        match format {
+impl TryFrom<vk::Format> for Format {
+    type Error = Error;
+
+    fn try_from(format: vk::Format) -> Result<Self> {
+        Ok(match format {
             vk::Format::R8G8B8A8_UNORM => Format::R8G8B8A8Unorm,
             vk::Format::R8G8B8A8_SRGB => Format::R8G8B8A8Srgb,
             vk::Format::B8G8R8A8_UNORM => Format::B8G8R8A8Unorm,
             vk::Format::B8G8R8A8_SRGB => Format::B8G8R8A8Srgb,
-            _ => unimplemented!(),
-        }
+            vk::Format::R8_UNORM => Format::R8Unorm,
+            vk::Format::R8G8_UNORM => Format::R8G8Unorm,
+            vk::Format::R16_SFLOAT => Format::R16Sfloat,
+            vk::Format::R16G16_SFLOAT => Format::R16G16Sfloat,
+            vk::Format::R16G16B16A16_SFLOAT => Format::R16G16B16A16Sfloat,
+            vk::Format::R32_SFLOAT => Format::R32Sfloat,
+            vk::Format::R32G32_SFLOAT => Format::R32G32Sfloat,
+            vk::Format::R32G32B32A32_SFLOAT => Format::R32G32B32A32Sfloat,
+            vk::Format::R32_UINT => Format::R32Uint,
+            vk::Format::R32G32_UINT => Format::R32G32Uint,
+            vk::Format::R32G32B32A32_UINT => Format::R32G32B32A32Uint,
+            vk::Format::R32_SINT => Format::R32Sint,
+            vk::Format::D16_UNORM => Format::D16Unorm,
+            vk::Format::D32_SFLOAT => Format::D32Sfloat,
+            vk::Format::D24_UNORM_S8_UINT => Format::D24UnormS8Uint,
+            vk::Format::D32_SFLOAT_S8_UINT => Format::D32SfloatS8Uint,
+            _ => {
+                return Err(Error::InvalidUsage(format!(
+                    "unsupported vulkan format {format:?}"
+                )))
+            }
+        })
     }
 }
 
@@ -50,6 +211,77 @@ impl Into<vk::ImageTiling> for ImageTiling {
     }
 }
 
+/// How shaders see an image. Each view type has its own bindless arrays, accessed with the
+/// matching preamble accessor, e.g. `get_storage_image_3d(id)` or `sample_texture_2d_array`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageViewType {
+    Type1D,
+    Type2D,
+    Type2DArray,
+    Type3D,
+    /// Six layers of a `cube_compatible` image.
+    Cube,
+}
+
+impl ImageViewType {
+    /// The view type images with `dimensions` get unless `ImageInfo::view_type` is set.
+    pub fn from_dimensions(dimensions: u32) -> Option<Self> {
+        match dimensions {
+            1 => Some(ImageViewType::Type1D),
+            2 => Some(ImageViewType::Type2D),
+            3 => Some(ImageViewType::Type3D),
+            _ => None,
+        }
+    }
+
+    /// The image dimensions the view type can be created for.
+    pub fn dimensions(&self) -> u32 {
+        match self {
+            ImageViewType::Type1D => 1,
+            ImageViewType::Type2D | ImageViewType::Type2DArray | ImageViewType::Cube => 2,
+            ImageViewType::Type3D => 3,
+        }
+    }
+}
+
+impl Into<vk::ImageViewType> for ImageViewType {
+    fn into(self) -> vk::ImageViewType {
+        match self {
+            ImageViewType::Type1D => vk::ImageViewType::TYPE_1D,
+            ImageViewType::Type2D => vk::ImageViewType::TYPE_2D,
+            ImageViewType::Type2DArray => vk::ImageViewType::TYPE_2D_ARRAY,
+            ImageViewType::Type3D => vk::ImageViewType::TYPE_3D,
+            ImageViewType::Cube => vk::ImageViewType::CUBE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum SampleCount {
+    #[default]
+    Type1,
+    Type2,
+    Type4,
+    Type8,
+    Type16,
+    Type32,
+    Type64,
+}
+
+impl Into<vk::SampleCountFlags> for SampleCount {
+    fn into(self) -> vk::SampleCountFlags {
+        match self {
+            SampleCount::Type1 => vk::SampleCountFlags::TYPE_1,
+            SampleCount::Type2 => vk::SampleCountFlags::TYPE_2,
+            SampleCount::Type4 => vk::SampleCountFlags::TYPE_4,
+            SampleCount::Type8 => vk::SampleCountFlags::TYPE_8,
+            SampleCount::Type16 => vk::SampleCountFlags::TYPE_16,
+            SampleCount::Type32 => vk::SampleCountFlags::TYPE_32,
+            SampleCount::Type64 => vk::SampleCountFlags::TYPE_64,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Extent2D {
     pub width: u32,
@@ -226,6 +458,32 @@ impl Into<vk::ImageLayout> for ImageLayout {
     }
 }
 
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct PipelineStageFlags: u32 {
+        const TOP_OF_PIPE = vk::PipelineStageFlags::TOP_OF_PIPE.as_raw();
+        const DRAW_INDIRECT = vk::PipelineStageFlags::DRAW_INDIRECT.as_raw();
+        const VERTEX_INPUT = vk::PipelineStageFlags::VERTEX_INPUT.as_raw();
+        const VERTEX_SHADER = vk::PipelineStageFlags::VERTEX_SHADER.as_raw();
+        const FRAGMENT_SHADER = vk::PipelineStageFlags::FRAGMENT_SHADER.as_raw();
+        const EARLY_FRAGMENT_TESTS = vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS.as_raw();
+        const LATE_FRAGMENT_TESTS = vk::PipelineStageFlags::LATE_FRAGMENT_TESTS.as_raw();
+        const COLOR_ATTACHMENT_OUTPUT = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT.as_raw();
+        const COMPUTE_SHADER = vk::PipelineStageFlags::COMPUTE_SHADER.as_raw();
+        const TRANSFER = vk::PipelineStageFlags::TRANSFER.as_raw();
+        const BOTTOM_OF_PIPE = vk::PipelineStageFlags::BOTTOM_OF_PIPE.as_raw();
+        const HOST = vk::PipelineStageFlags::HOST.as_raw();
+        const ALL_GRAPHICS = vk::PipelineStageFlags::ALL_GRAPHICS.as_raw();
+        const ALL_COMMANDS = vk::PipelineStageFlags::ALL_COMMANDS.as_raw();
+    }
+}
+
+impl Into<vk::PipelineStageFlags> for PipelineStageFlags {
+    fn into(self) -> vk::PipelineStageFlags {
+        vk::PipelineStageFlags::from_raw(self.bits())
+    }
+}
+
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
     pub struct AccessFlags: u32 {
@@ -308,18 +566,256 @@ impl Into<vk::AccessFlags> for AccessFlags {
     }
 }
 
+bitflags! {
+    /// Stages for `CommandRecorder::pipeline_barrier`, the empty set is `VK_PIPELINE_STAGE_2_NONE`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct PipelineStageFlags2: u64 {
+        const TOP_OF_PIPE = vk::PipelineStageFlags2::TOP_OF_PIPE.as_raw();
+        const DRAW_INDIRECT = vk::PipelineStageFlags2::DRAW_INDIRECT.as_raw();
+        const VERTEX_INPUT = vk::PipelineStageFlags2::VERTEX_INPUT.as_raw();
+        const INDEX_INPUT = vk::PipelineStageFlags2::INDEX_INPUT.as_raw();
+        const VERTEX_ATTRIBUTE_INPUT = vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT.as_raw();
+        const VERTEX_SHADER = vk::PipelineStageFlags2::VERTEX_SHADER.as_raw();
+        const TESSELLATION_CONTROL_SHADER = vk::PipelineStageFlags2::TESSELLATION_CONTROL_SHADER.as_raw();
+        const TESSELLATION_EVALUATION_SHADER = vk::PipelineStageFlags2::TESSELLATION_EVALUATION_SHADER.as_raw();
+        const GEOMETRY_SHADER = vk::PipelineStageFlags2::GEOMETRY_SHADER.as_raw();
+        const PRE_RASTERIZATION_SHADERS = vk::PipelineStageFlags2::PRE_RASTERIZATION_SHADERS.as_raw();
+        const FRAGMENT_SHADER = vk::PipelineStageFlags2::FRAGMENT_SHADER.as_raw();
+        const EARLY_FRAGMENT_TESTS = vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS.as_raw();
+        const LATE_FRAGMENT_TESTS = vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS.as_raw();
+        const COLOR_ATTACHMENT_OUTPUT = vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT.as_raw();
+        const COMPUTE_SHADER = vk::PipelineStageFlags2::COMPUTE_SHADER.as_raw();
+        const COPY = vk::PipelineStageFlags2::COPY.as_raw();
+        const RESOLVE = vk::PipelineStageFlags2::RESOLVE.as_raw();
+        const BLIT = vk::PipelineStageFlags2::BLIT.as_raw();
+        const CLEAR = vk::PipelineStageFlags2::CLEAR.as_raw();
+        const ALL_TRANSFER = vk::PipelineStageFlags2::ALL_TRANSFER.as_raw();
+        const BOTTOM_OF_PIPE = vk::PipelineStageFlags2::BOTTOM_OF_PIPE.as_raw();
+        const HOST = vk::PipelineStageFlags2::HOST.as_raw();
+        const ALL_GRAPHICS = vk::PipelineStageFlags2::ALL_GRAPHICS.as_raw();
+        const ALL_COMMANDS = vk::PipelineStageFlags2::ALL_COMMANDS.as_raw();
+    }
+}
+
+impl Into<vk::PipelineStageFlags2> for PipelineStageFlags2 {
+    fn into(self) -> vk::PipelineStageFlags2 {
+        vk::PipelineStageFlags2::from_raw(self.bits())
+    }
+}
+
+bitflags! {
+    /// Accesses for `CommandRecorder::pipeline_barrier`, the empty set is `VK_ACCESS_2_NONE`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct AccessFlags2: u64 {
+        const INDIRECT_COMMAND_READ = vk::AccessFlags2::INDIRECT_COMMAND_READ.as_raw();
+        const INDEX_READ = vk::AccessFlags2::INDEX_READ.as_raw();
+        const VERTEX_ATTRIBUTE_READ = vk::AccessFlags2::VERTEX_ATTRIBUTE_READ.as_raw();
+        const UNIFORM_READ = vk::AccessFlags2::UNIFORM_READ.as_raw();
+        const INPUT_ATTACHMENT_READ = vk::AccessFlags2::INPUT_ATTACHMENT_READ.as_raw();
+        const SHADER_READ = vk::AccessFlags2::SHADER_READ.as_raw();
+        const SHADER_WRITE = vk::AccessFlags2::SHADER_WRITE.as_raw();
+        const SHADER_SAMPLED_READ = vk::AccessFlags2::SHADER_SAMPLED_READ.as_raw();
+        const SHADER_STORAGE_READ = vk::AccessFlags2::SHADER_STORAGE_READ.as_raw();
+        const SHADER_STORAGE_WRITE = vk::AccessFlags2::SHADER_STORAGE_WRITE.as_raw();
+        const COLOR_ATTACHMENT_READ = vk::AccessFlags2::COLOR_ATTACHMENT_READ.as_raw();
+        const COLOR_ATTACHMENT_WRITE = vk::AccessFlags2::COLOR_ATTACHMENT_WRITE.as_raw();
+        const DEPTH_STENCIL_ATTACHMENT_READ = vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ.as_raw();
+        const DEPTH_STENCIL_ATTACHMENT_WRITE = vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw();
+        const TRANSFER_READ = vk::AccessFlags2::TRANSFER_READ.as_raw();
+        const TRANSFER_WRITE = vk::AccessFlags2::TRANSFER_WRITE.as_raw();
+        const HOST_READ = vk::AccessFlags2::HOST_READ.as_raw();
+        const HOST_WRITE = vk::AccessFlags2::HOST_WRITE.as_raw();
+        const MEMORY_READ = vk::AccessFlags2::MEMORY_READ.as_raw();
+        const MEMORY_WRITE = vk::AccessFlags2::MEMORY_WRITE.as_raw();
+    }
+}
+
+impl Into<vk::AccessFlags2> for AccessFlags2 {
+    fn into(self) -> vk::AccessFlags2 {
+        vk::AccessFlags2::from_raw(self.bits())
+    }
+}
+
+/// Moves a resource between queue families. The same transition has to be recorded on a
+/// recorder of the source queue (release) and one of the destination queue (acquire), with the
+/// acquire submitted after the release has been signalled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueOwnershipTransfer {
+    pub src_queue: QueueType,
+    pub dst_queue: QueueType,
+}
+
 pub struct ImageTransition {
     pub image: ImageId,
     pub src_layout: ImageLayout,
     pub dst_layout: ImageLayout,
     pub src_access: AccessFlags,
     pub dst_access: AccessFlags,
+    pub subresource_range: ImageSubresourceRange,
+    pub ownership_transfer: Option<QueueOwnershipTransfer>,
 }
 
 pub struct BufferTransition {
     pub buffer: BufferId,
     pub src_access: AccessFlags,
     pub dst_access: AccessFlags,
+    pub ownership_transfer: Option<QueueOwnershipTransfer>,
+}
+
+/// The mip levels and array layers of an image a barrier applies to. The default covers the
+/// whole image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageSubresourceRange {
+    pub base_mip_level: u32,
+    /// `ImageSubresourceRange::REMAINING` covers every level from `base_mip_level` on.
+    pub level_count: u32,
+    pub base_array_layer: u32,
+    /// `ImageSubresourceRange::REMAINING` covers every layer from `base_array_layer` on.
+    pub layer_count: u32,
+}
+
+impl ImageSubresourceRange {
+    pub const REMAINING: u32 = vk::REMAINING_MIP_LEVELS;
+
+    pub fn new(
+        base_mip_level: u32,
+        level_count: u32,
+        base_array_layer: u32,
+        layer_count: u32,
+    ) -> Self {
+        Self {
+            base_mip_level,
+            level_count,
+            base_array_layer,
+            layer_count,
+        }
+    }
+
+    /// A single mip level of every layer.
+    pub fn mip(level: u32) -> Self {
+        Self::new(level, 1, 0, Self::REMAINING)
+    }
+
+    /// Replaces `REMAINING` counts with the levels and layers left in an image with
+    /// `mip_levels` and `array_layers`.
+    pub fn resolve(self, mip_levels: u32, array_layers: u32) -> Self {
+        let level_count = match self.level_count {
+            Self::REMAINING => mip_levels.saturating_sub(self.base_mip_level),
+            level_count => level_count,
+        };
+        let layer_count = match self.layer_count {
+            Self::REMAINING => array_layers.saturating_sub(self.base_array_layer),
+            layer_count => layer_count,
+        };
+
+        Self::new(
+            self.base_mip_level,
+            level_count,
+            self.base_array_layer,
+            layer_count,
+        )
+    }
+
+    /// Whether the range lies within an image with `mip_levels` and `array_layers`.
+    pub fn fits(&self, mip_levels: u32, array_layers: u32) -> bool {
+        let range = self.resolve(mip_levels, array_layers);
+        range.level_count > 0
+            && range.layer_count > 0
+            && range.base_mip_level as u64 + range.level_count as u64 <= mip_levels as u64
+            && range.base_array_layer as u64 + range.layer_count as u64 <= array_layers as u64
+    }
+
+    pub(crate) fn to_vk(self, aspect_mask: vk::ImageAspectFlags) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: self.base_mip_level,
+            level_count: self.level_count,
+            base_array_layer: self.base_array_layer,
+            layer_count: self.layer_count,
+        }
+    }
+}
+
+impl Default for ImageSubresourceRange {
+    fn default() -> Self {
+        Self::new(0, Self::REMAINING, 0, Self::REMAINING)
+    }
+}
+
+/// A single mip level and a range of array layers of an image, used by copies and blits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageSubresourceLayers {
+    pub mip_level: u32,
+    pub base_array_layer: u32,
+    /// `ImageSubresourceRange::REMAINING` covers every layer from `base_array_layer` on.
+    pub layer_count: u32,
+}
+
+impl ImageSubresourceLayers {
+    pub fn new(mip_level: u32, base_array_layer: u32, layer_count: u32) -> Self {
+        Self {
+            mip_level,
+            base_array_layer,
+            layer_count,
+        }
+    }
+
+    /// A single mip level of every layer.
+    pub fn mip(level: u32) -> Self {
+        Self::new(level, 0, ImageSubresourceRange::REMAINING)
+    }
+}
+
+impl Default for ImageSubresourceLayers {
+    fn default() -> Self {
+        Self::mip(0)
+    }
+}
+
+/// Orders every access in `src_stages` before the ones in `dst_stages`, regardless of the
+/// resource.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryBarrier {
+    pub src_stages: PipelineStageFlags2,
+    pub src_access: AccessFlags2,
+    pub dst_stages: PipelineStageFlags2,
+    pub dst_access: AccessFlags2,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BufferBarrier {
+    pub buffer: BufferId,
+    pub src_stages: PipelineStageFlags2,
+    pub src_access: AccessFlags2,
+    pub dst_stages: PipelineStageFlags2,
+    pub dst_access: AccessFlags2,
+    pub offset: u64,
+    /// `WHOLE_SIZE` covers the buffer from `offset` to its end.
+    pub size: u64,
+    pub ownership_transfer: Option<QueueOwnershipTransfer>,
+}
+
+pub const WHOLE_SIZE: u64 = vk::WHOLE_SIZE;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ImageBarrier {
+    pub image: ImageId,
+    pub src_stages: PipelineStageFlags2,
+    pub src_access: AccessFlags2,
+    pub dst_stages: PipelineStageFlags2,
+    pub dst_access: AccessFlags2,
+    pub src_layout: ImageLayout,
+    pub dst_layout: ImageLayout,
+    pub subresource_range: ImageSubresourceRange,
+    pub ownership_transfer: Option<QueueOwnershipTransfer>,
+}
+
+/// Barriers recorded together with a single `vkCmdPipelineBarrier2`.
+#[derive(Debug, Clone, Default)]
+pub struct BarrierInfo {
+    pub memory_barriers: Vec<MemoryBarrier>,
+    pub buffer_barriers: Vec<BufferBarrier>,
+    pub image_barriers: Vec<ImageBarrier>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -374,6 +870,7 @@ pub enum ClearValue {
     None,
     Color(f32, f32, f32),
     Depth(f32),
+    DepthStencil(f32, u32),
 }
 
 impl Into<vk::ClearValue> for ClearValue {
@@ -387,6 +884,9 @@ impl Into<vk::ClearValue> for ClearValue {
             ClearValue::Depth(depth) => vk::ClearValue {
                 depth_stencil: vk::ClearDepthStencilValue { depth, stencil: 0 },
             },
+            ClearValue::DepthStencil(depth, stencil) => vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth, stencil },
+            },
             ClearValue::None => vk::ClearValue::default(),
         }
     }
@@ -404,3 +904,162 @@ impl Into<vk::PrimitiveTopology> for Topology {
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+impl Into<vk::Filter> for Filter {
+    fn into(self) -> vk::Filter {
+        match self {
+            Filter::Nearest => vk::Filter::NEAREST,
+            Filter::Linear => vk::Filter::LINEAR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SamplerMipmapMode {
+    Nearest,
+    Linear,
+}
+
+impl Into<vk::SamplerMipmapMode> for SamplerMipmapMode {
+    fn into(self) -> vk::SamplerMipmapMode {
+        match self {
+            SamplerMipmapMode::Nearest => vk::SamplerMipmapMode::NEAREST,
+            SamplerMipmapMode::Linear => vk::SamplerMipmapMode::LINEAR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SamplerAddressMode {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+    ClampToBorder,
+}
+
+impl Into<vk::SamplerAddressMode> for SamplerAddressMode {
+    fn into(self) -> vk::SamplerAddressMode {
+        match self {
+            SamplerAddressMode::Repeat => vk::SamplerAddressMode::REPEAT,
+            SamplerAddressMode::MirroredRepeat => vk::SamplerAddressMode::MIRRORED_REPEAT,
+            SamplerAddressMode::ClampToEdge => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            SamplerAddressMode::ClampToBorder => vk::SamplerAddressMode::CLAMP_TO_BORDER,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BorderColor {
+    TransparentBlack,
+    OpaqueBlack,
+    OpaqueWhite,
+}
+
+impl Into<vk::BorderColor> for BorderColor {
+    fn into(self) -> vk::BorderColor {
+        match self {
+            BorderColor::TransparentBlack => vk::BorderColor::FLOAT_TRANSPARENT_BLACK,
+            BorderColor::OpaqueBlack => vk::BorderColor::FLOAT_OPAQUE_BLACK,
+            BorderColor::OpaqueWhite => vk::BorderColor::FLOAT_OPAQUE_WHITE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompareOp {
+    Never,
+    Less,
+    Equal,
+    LessOrEqual,
+    Greater,
+    NotEqual,
+    GreaterOrEqual,
+    Always,
+}
+
+impl Into<vk::CompareOp> for CompareOp {
+    fn into(self) -> vk::CompareOp {
+        match self {
+            CompareOp::Never => vk::CompareOp::NEVER,
+            CompareOp::Less => vk::CompareOp::LESS,
+            CompareOp::Equal => vk::CompareOp::EQUAL,
+            CompareOp::LessOrEqual => vk::CompareOp::LESS_OR_EQUAL,
+            CompareOp::Greater => vk::CompareOp::GREATER,
+            CompareOp::NotEqual => vk::CompareOp::NOT_EQUAL,
+            CompareOp::GreaterOrEqual => vk::CompareOp::GREATER_OR_EQUAL,
+            CompareOp::Always => vk::CompareOp::ALWAYS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    Replace,
+    IncrementAndClamp,
+    DecrementAndClamp,
+    Invert,
+    IncrementAndWrap,
+    DecrementAndWrap,
+}
+
+impl Into<vk::StencilOp> for StencilOp {
+    fn into(self) -> vk::StencilOp {
+        match self {
+            StencilOp::Keep => vk::StencilOp::KEEP,
+            StencilOp::Zero => vk::StencilOp::ZERO,
+            StencilOp::Replace => vk::StencilOp::REPLACE,
+            StencilOp::IncrementAndClamp => vk::StencilOp::INCREMENT_AND_CLAMP,
+            StencilOp::DecrementAndClamp => vk::StencilOp::DECREMENT_AND_CLAMP,
+            StencilOp::Invert => vk::StencilOp::INVERT,
+            StencilOp::IncrementAndWrap => vk::StencilOp::INCREMENT_AND_WRAP,
+            StencilOp::DecrementAndWrap => vk::StencilOp::DECREMENT_AND_WRAP,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_formats_have_depth_and_stencil_aspects() {
+        assert_eq!(Format::D16Unorm.aspect_mask(), vk::ImageAspectFlags::DEPTH);
+        assert_eq!(Format::D32Sfloat.aspect_mask(), vk::ImageAspectFlags::DEPTH);
+        for format in [Format::D24UnormS8Uint, Format::D32SfloatS8Uint] {
+            assert!(format.has_depth() && format.has_stencil());
+            assert_eq!(
+                format.aspect_mask(),
+                vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+            );
+        }
+    }
+
+    #[test]
+    fn color_formats_have_a_color_aspect() {
+        for format in Format::ALL.iter().filter(|format| !format.has_depth()) {
+            assert!(!format.has_stencil());
+            assert_eq!(format.aspect_mask(), vk::ImageAspectFlags::COLOR);
+        }
+    }
+
+    #[test]
+    fn formats_round_trip_through_vk() {
+        for format in Format::ALL {
+            let vk_format: vk::Format = (*format).into();
+            assert_eq!(Format::try_from(vk_format).unwrap(), *format);
+        }
+    }
+
+    #[test]
+    fn unsupported_vk_format_is_rejected() {
+        assert!(Format::try_from(vk::Format::A2B10G10R10_UNORM_PACK32).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use std::{ffi::CStr, sync::Mutex};
+
+use ash::vk;
+use bitflags::bitflags;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct DebugMessageSeverity: u32 {
+        const VERBOSE = vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE.as_raw();
+        const INFO = vk::DebugUtilsMessageSeverityFlagsEXT::INFO.as_raw();
+        const WARNING = vk::DebugUtilsMessageSeverityFlagsEXT::WARNING.as_raw();
+        const ERROR = vk::DebugUtilsMessageSeverityFlagsEXT::ERROR.as_raw();
+    }
+}
+
+impl Into<vk::DebugUtilsMessageSeverityFlagsEXT> for DebugMessageSeverity {
+    fn into(self) -> vk::DebugUtilsMessageSeverityFlagsEXT {
+        vk::DebugUtilsMessageSeverityFlagsEXT::from_raw(self.bits())
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct DebugMessageType: u32 {
+        const GENERAL = vk::DebugUtilsMessageTypeFlagsEXT::GENERAL.as_raw();
+        const VALIDATION = vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION.as_raw();
+        const PERFORMANCE = vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE.as_raw();
+    }
+}
+
+impl Into<vk::DebugUtilsMessageTypeFlagsEXT> for DebugMessageType {
+    fn into(self) -> vk::DebugUtilsMessageTypeFlagsEXT {
+        vk::DebugUtilsMessageTypeFlagsEXT::from_raw(self.bits())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DebugMessage {
+    pub severity: DebugMessageSeverity,
+    pub ty: DebugMessageType,
+    pub id_name: Option<String>,
+    pub message: String,
+}
+
+impl DebugMessage {
+    fn vk_severity(&self) -> vk::DebugUtilsMessageSeverityFlagsEXT {
+        self.severity.into()
+    }
+
+    fn vk_type(&self) -> vk::DebugUtilsMessageTypeFlagsEXT {
+        self.ty.into()
+    }
+}
+
+/// Where messages coming from the debug messenger are sent to.
+pub enum DebugMessageSink {
+    /// Prints every message to stdout.
+    Stdout,
+    /// Forwards messages to the `log` crate under the `paya` target.
+    #[cfg(feature = "log")]
+    Log,
+    /// Forwards messages to the `tracing` crate under the `paya` target.
+    #[cfg(feature = "tracing")]
+    Tracing,
+    /// Calls the closure with every message, it may be called from any thread.
+    Callback(Box<dyn Fn(&DebugMessage) + Send + Sync>),
+    /// Drops every message, useful when only collecting validation errors.
+    Discard,
+}
+
+pub struct DebugMessengerInfo {
+    pub severity: DebugMessageSeverity,
+    pub message_types: DebugMessageType,
+    pub sink: DebugMessageSink,
+    /// Collects validation errors regardless of the severity filter so they can be queried with
+    /// `Instance::validation_errors`.
+    pub collect_validation_errors: bool,
+}
+
+impl DebugMessengerInfo {
+    pub fn severity(mut self, severity: DebugMessageSeverity) -> Self {
+        self.severity = severity;
+        self
+    }
+
+    pub fn message_types(mut self, message_types: DebugMessageType) -> Self {
+        self.message_types = message_types;
+        self
+    }
+
+    pub fn sink(mut self, sink: DebugMessageSink) -> Self {
+        self.sink = sink;
+        self
+    }
+
+    pub fn collect_validation_errors(mut self, collect_validation_errors: bool) -> Self {
+        self.collect_validation_errors = collect_validation_errors;
+        self
+    }
+}
+
+impl Default for DebugMessengerInfo {
+    fn default() -> Self {
+        DebugMessengerInfo {
+            severity: DebugMessageSeverity::WARNING | DebugMessageSeverity::ERROR,
+            message_types: DebugMessageType::all(),
+            sink: DebugMessageSink::Stdout,
+            collect_validation_errors: false,
+        }
+    }
+}
+
+pub(crate) struct DebugMessenger {
+    info: DebugMessengerInfo,
+    validation_errors: Mutex<Vec<DebugMessage>>,
+}
+
+impl DebugMessenger {
+    pub(crate) fn new(info: DebugMessengerInfo) -> Self {
+        DebugMessenger {
+            info,
+            validation_errors: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn vk_create_info(&self) -> vk::DebugUtilsMessengerCreateInfoEXT<'static> {
+        let mut severity = self.info.severity;
+        let mut message_types = self.info.message_types;
+        if self.info.collect_validation_errors {
+            severity |= DebugMessageSeverity::ERROR;
+            message_types |= DebugMessageType::VALIDATION;
+        }
+
+        let mut create_info = vk::DebugUtilsMessengerCreateInfoEXT::default()
+            .message_severity(severity.into())
+            .message_type(message_types.into())
+            .pfn_user_callback(Some(debug_utils_callback));
+        create_info.p_user_data = self as *const Self as *mut std::ffi::c_void;
+        create_info
+    }
+
+    /// Sends a message that didn't originate from the driver through the sink.
+    pub(crate) fn emit(&self, severity: DebugMessageSeverity, message: impl Into<String>) {
+        self.handle_message(DebugMessage {
+            severity,
+            ty: DebugMessageType::GENERAL,
+            id_name: None,
+            message: message.into(),
+        });
+    }
+
+    fn handle_message(&self, message: DebugMessage) {
+        if self.info.collect_validation_errors
+            && message.severity.contains(DebugMessageSeverity::ERROR)
+            && message.ty.contains(DebugMessageType::VALIDATION)
+        {
+            self.validation_errors.lock().unwrap().push(message.clone());
+        }
+
+        if !self.info.severity.intersects(message.severity)
+            || !self.info.message_types.intersects(message.ty)
+        {
+            return;
+        }
+
+        match &self.info.sink {
+            DebugMessageSink::Stdout => {
+                let severity = format!("{:?}", message.vk_severity()).to_lowercase();
+                let ty = format!("{:?}", message.vk_type()).to_lowercase();
+                println!("[Debug][{}][{}] {:?}", severity, ty, message.message);
+            }
+            #[cfg(feature = "log")]
+            DebugMessageSink::Log => {
+                let level = if message.severity.contains(DebugMessageSeverity::ERROR) {
+                    log::Level::Error
+                } else if message.severity.contains(DebugMessageSeverity::WARNING) {
+                    log::Level::Warn
+                } else if message.severity.contains(DebugMessageSeverity::INFO) {
+                    log::Level::Info
+                } else {
+                    log::Level::Trace
+                };
+                log::log!(target: "paya", level, "[{:?}] {}", message.vk_type(), message.message);
+            }
+            #[cfg(feature = "tracing")]
+            DebugMessageSink::Tracing => {
+                let ty = format!("{:?}", message.vk_type());
+                if message.severity.contains(DebugMessageSeverity::ERROR) {
+                    tracing::error!(target: "paya", ty = ty.as_str(), "{}", message.message);
+                } else if message.severity.contains(DebugMessageSeverity::WARNING) {
+                    tracing::warn!(target: "paya", ty = ty.as_str(), "{}", message.message);
+                } else if message.severity.contains(DebugMessageSeverity::INFO) {
+                    tracing::info!(target: "paya", ty = ty.as_str(), "{}", message.message);
+                } else {
+                    tracing::trace!(target: "paya", ty = ty.as_str(), "{}", message.message);
+                }
+            }
+            DebugMessageSink::Callback(callback) => callback(&message),
+            DebugMessageSink::Discard => {}
+        }
+    }
+
+    pub(crate) fn validation_errors(&self) -> Vec<DebugMessage> {
+        self.validation_errors.lock().unwrap().clone()
+    }
+
+    pub(crate) fn take_validation_errors(&self) -> Vec<DebugMessage> {
+        std::mem::take(&mut *self.validation_errors.lock().unwrap())
+    }
+}
+
+/// Reports errors that can't be returned to the caller, e.g. while dropping. Goes to the `log`
+/// or `tracing` crate when one of them is enabled and to stderr otherwise.
+pub(crate) fn report_error(message: std::fmt::Arguments) {
+    #[cfg(feature = "log")]
+    log::error!(target: "paya", "{}", message);
+    #[cfg(all(feature = "tracing", not(feature = "log")))]
+    tracing::error!(target: "paya", "{}", message);
+    #[cfg(not(any(feature = "log", feature = "tracing")))]
+    eprintln!("[paya] {}", message);
+}
+
+unsafe extern "system" fn debug_utils_callback(
+    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+    p_user_data: *mut std::ffi::c_void,
+) -> vk::Bool32 {
+    // Safety: the user data points to the messenger owned by the instance which outlives the
+    // debug utils messenger and instance handles.
+    let messenger = &*(p_user_data as *const DebugMessenger);
+    let callback_data = &*p_callback_data;
+
+    let id_name = (!callback_data.p_message_id_name.is_null()).then(|| {
+        CStr::from_ptr(callback_data.p_message_id_name)
+            .to_string_lossy()
+            .into_owned()
+    });
+    let message = if callback_data.p_message.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(callback_data.p_message)
+            .to_string_lossy()
+            .into_owned()
+    };
+
+    messenger.handle_message(DebugMessage {
+        severity: DebugMessageSeverity::from_bits_truncate(message_severity.as_raw()),
+        ty: DebugMessageType::from_bits_truncate(message_type.as_raw()),
+        id_name,
+        message,
+    });
+
+    vk::FALSE
+}
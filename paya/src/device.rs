@@ -1,106 +1,279 @@
 use std::{
-    collections::HashMap,
-    ffi::{c_void, CString},
-    sync::Arc,
+    ffi::CString,
+    ops::Range,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
 
 use ash::vk;
 use slotmap::{new_key_type, SlotMap};
 
+pub use crate::physical_device::DeviceType;
+
 use crate::{
-    allocator::{Allocation, GpuAllocator},
-    command_recorder::{CommandList, CommandRecorder, CommandRecorderId, CommandRecorderPool},
-    common::{Extent3D, Format, ImageUsageFlags},
+    allocator::{Allocation, GpuAllocator, MemoryHeapReport, MemoryReport},
+    command_recorder::{CommandList, CommandRecorder, CommandRecorderPool},
+    common::{
+        Extent3D, Format, ImageLayout, ImageSubresourceRange, ImageUsageFlags, ImageViewType,
+        PipelineStageFlags, SampleCount,
+    },
+    error::{Error, Result},
+    garbage::{Garbage, GarbageQueue},
     gpu_resources::{
-        Buffer, BufferId, BufferInfo, GpuResourceId, GpuResourcePool, GpuResourceType, ImageId,
+        BindlessCapacities, Buffer, BufferId, BufferInfo, GpuResourceId, GpuResourcePool,
+        GpuResourceType, ImageId, ResourceRef, Sampler, SamplerId, SamplerInfo,
     },
     instance::{Instance, InstanceInner},
+    mipmap::{self, MipReduce},
+    physical_device::{
+        DeviceFeatures, FormatFeatureFlags, PhysicalDeviceInfo, VkDeviceFeatures,
+        DEVICE_OVERRIDE_ENV,
+    },
     pipeline::{
-        ComputePipeline, ComputePipelineInfo, PipelineInner, RasterPipeline, RasterPipelineInfo,
+        ComputePipeline, ComputePipelineInfo, DepthStencilInfo, Pipeline, PipelineCache,
+        PipelineInner, RasterPipeline, RasterPipelineInfo,
     },
+    query::{QueryPool, QueryPoolInfo},
+    queue::{Queue, QueueFamilies, QueueType},
+    readback,
+    shader::ShaderCompiler,
     swapchain::{Swapchain, SwapchainCreateInfo},
-    sync::{BinarySemaphore, TimelineSemaphore},
+    sync::{self, BinarySemaphore, TimelineSemaphore},
+    upload::Uploader,
 };
 
-pub struct DeviceProperties {
-    pub device_type: DeviceType,
-    pub device_name: String,
-}
-
-impl From<vk::PhysicalDeviceProperties> for DeviceProperties {
-    fn from(properties: vk::PhysicalDeviceProperties) -> Self {
-        DeviceProperties {
-            device_type: match properties.device_type {
-                vk::PhysicalDeviceType::INTEGRATED_GPU => DeviceType::Integrated,
-                vk::PhysicalDeviceType::DISCRETE_GPU => DeviceType::Discrete,
-                _ => DeviceType::Other,
-            },
-            device_name: unsafe { std::ffi::CStr::from_ptr(properties.device_name.as_ptr()) }
-                .to_str()
-                .expect("Failed to convert device name to string")
-                .to_owned(),
-        }
-    }
-}
-
-pub enum DeviceType {
-    Integrated,
-    Discrete,
-    Other,
-}
-
 #[derive(Clone)]
 pub struct DeviceInner {
     pub(crate) instance_dep: Arc<InstanceInner>,
     pub(crate) device: ash::Device,
-    pub(crate) main_queue_family_index: u32,
+    pub(crate) queue_families: QueueFamilies,
     pub(crate) physical_device: vk::PhysicalDevice,
     pub(crate) physical_device_properties: vk::PhysicalDeviceProperties,
     pub(crate) physical_device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+    pub(crate) physical_device_info: PhysicalDeviceInfo,
+    pub(crate) enabled_features: DeviceFeatures,
+    pub(crate) enabled_extensions: Vec<String>,
     pub(crate) dynamic_rendering_loader: ash::khr::dynamic_rendering::Device,
-    pub(crate) debug_utils: ash::ext::debug_utils::Device,
+    pub(crate) synchronization2_loader: ash::khr::synchronization2::Device,
+    pub(crate) debug_utils: Option<ash::ext::debug_utils::Device>,
+    pub(crate) headless: bool,
 }
 
+/// The device is `Send + Sync` and every method takes `&self`, so worker threads can create
+/// resources, record command lists and submit them through a shared reference.
 pub struct Device {
     // We need to keep a reference to the instance to ensure it is not dropped before the device
     inner: Arc<DeviceInner>,
 
-    main_queue: vk::Queue,
-
+    queues: [Queue; 3],
+    // Queue types that share a queue share its lock, vulkan requires submits and presents on a
+    // queue to be externally synchronized. Indexed by `QueueType`.
+    queue_locks: [Arc<Mutex<()>>; 3],
     pub(crate) gpu_resources: GpuResourcePool,
     command_recorder_pool: CommandRecorderPool,
 
-    deferred_destruct_recorders: HashMap<u64, Vec<CommandRecorderId>>,
-    deferred_destruct_buffers: HashMap<u64, Vec<BufferId>>,
-    deferred_destruct_images: HashMap<u64, Vec<ImageId>>,
+    // One timeline per queue type since signals from different queues can complete out of
+    // order, indexed by `QueueType`. Declared after the recorder pool which waits for the
+    // device to be idle when dropped.
+    queue_timelines: [TimelineSemaphore; 3],
+    // Only written while holding the queue's lock.
+    queue_timeline_values: [AtomicU64; 3],
 
-    frame_index: u64,
+    garbage: Mutex<GarbageQueue>,
+    pipeline_cache: PipelineCache,
+    uploader: Mutex<Uploader>,
+
+    // Incremented on every present.
+    frame_index: AtomicU64,
+}
+
+// Recording and submitting from worker threads relies on these.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Device>();
+    assert_send_sync::<CommandRecorder>();
+    assert_send_sync::<CommandList>();
+};
+
+/// Selects a physical device by score, `None` rejects the device.
+pub type DeviceSelector<'a> = &'a dyn Fn(&PhysicalDeviceInfo) -> Option<i32>;
+
+pub struct DeviceCreateInfo<'a> {
+    /// Devices are created on the highest scoring physical device. Setting `PAYA_DEVICE` to an
+    /// index or name overrides the selector.
+    pub selector: DeviceSelector<'a>,
+    /// Features device creation fails without, on top of `DeviceFeatures::PAYA_REQUIRED`.
+    pub required_features: DeviceFeatures,
+    /// Features that are enabled when the device supports them.
+    pub optional_features: DeviceFeatures,
+    /// Device extension names device creation fails without, e.g. `"VK_EXT_mesh_shader"`.
+    pub required_extensions: Vec<&'a str>,
+    /// Device extensions that are enabled when the device supports them.
+    pub optional_extensions: Vec<&'a str>,
+    /// Loads a pipeline cache saved with `PipelineCache::save`, a missing or mismatching file
+    /// starts with an empty cache.
+    pub pipeline_cache_path: Option<&'a Path>,
+    /// Sizes of the bindless tables, clamped to the device's descriptor indexing limits.
+    /// Shaders have to be compiled against the clamped `Device::bindless_capacities`.
+    pub bindless_capacities: BindlessCapacities,
+}
+
+impl<'a> DeviceCreateInfo<'a> {
+    pub fn selector(mut self, selector: DeviceSelector<'a>) -> Self {
+        self.selector = selector;
+        self
+    }
+
+    pub fn required_features(mut self, features: DeviceFeatures) -> Self {
+        self.required_features |= features;
+        self
+    }
+
+    pub fn optional_features(mut self, features: DeviceFeatures) -> Self {
+        self.optional_features |= features;
+        self
+    }
+
+    pub fn required_extension(mut self, name: &'a str) -> Self {
+        self.required_extensions.push(name);
+        self
+    }
+
+    pub fn optional_extension(mut self, name: &'a str) -> Self {
+        self.optional_extensions.push(name);
+        self
+    }
+
+    pub fn pipeline_cache_path(mut self, path: &'a Path) -> Self {
+        self.pipeline_cache_path = Some(path);
+        self
+    }
+
+    pub fn bindless_capacities(mut self, capacities: BindlessCapacities) -> Self {
+        self.bindless_capacities = capacities;
+        self
+    }
+
+    fn all_required_features(&self) -> DeviceFeatures {
+        (self.required_features | DeviceFeatures::PAYA_REQUIRED).with_dependencies()
+    }
+
+    /// Describes everything required that the physical device doesn't support.
+    fn missing_requirements(&self, info: &PhysicalDeviceInfo) -> Option<Error> {
+        let missing_features = self.all_required_features() - info.features;
+        if !missing_features.is_empty() {
+            return Some(Error::MissingFeature(format!(
+                "{:?} on {}",
+                missing_features, info.name
+            )));
+        }
+
+        let missing_extensions = self
+            .required_extensions
+            .iter()
+            .filter(|name| !info.supports_extension(name))
+            .copied()
+            .collect::<Vec<_>>();
+        if !missing_extensions.is_empty() {
+            return Some(Error::MissingExtension(format!(
+                "{} on {}",
+                missing_extensions.join(", "),
+                info.name
+            )));
+        }
+
+        None
+    }
+}
+
+impl Default for DeviceCreateInfo<'_> {
+    fn default() -> Self {
+        DeviceCreateInfo {
+            selector: &prefer_discrete,
+            required_features: DeviceFeatures::empty(),
+            optional_features: DeviceFeatures::empty(),
+            required_extensions: Vec::new(),
+            optional_extensions: Vec::new(),
+            pipeline_cache_path: None,
+            bindless_capacities: BindlessCapacities::default(),
+        }
+    }
+}
+
+fn prefer_discrete(info: &PhysicalDeviceInfo) -> Option<i32> {
+    match info.device_type {
+        DeviceType::Discrete => Some(100),
+        DeviceType::Integrated => Some(50),
+        _ => Some(0),
+    }
 }
 
 impl Device {
-    pub fn new<Selector>(instance: &Instance, selector: Selector) -> Self
-    where
-        Selector: Fn(&DeviceProperties) -> i32,
-    {
-        let physical_devices = unsafe {
-            instance
-                .handle()
-                .enumerate_physical_devices()
-                .expect("Failed to enumerate physical devices")
+    /// Creates a device on the selected physical device with the required features and
+    /// extensions enabled, plus whichever optional ones are supported. Devices that support
+    /// every requirement are preferred over higher scoring ones that don't.
+    pub fn new(instance: &Instance, create_info: DeviceCreateInfo<'_>) -> Result<Self> {
+        let physical_devices = instance.enumerate_physical_devices()?;
+
+        let physical_device_info = match std::env::var(DEVICE_OVERRIDE_ENV) {
+            Ok(value) => physical_devices
+                .into_iter()
+                .find(|info| info.matches_override(&value))
+                .ok_or_else(|| {
+                    Error::NoSuitableDevice(format!(
+                        "{}={} does not match any physical device",
+                        DEVICE_OVERRIDE_ENV, value
+                    ))
+                })?,
+            Err(_) => physical_devices
+                .into_iter()
+                .filter_map(|info| (create_info.selector)(&info).map(|score| (score, info)))
+                .max_by_key(|(score, info)| {
+                    (create_info.missing_requirements(info).is_none(), *score)
+                })
+                .map(|(_, info)| info)
+                .ok_or_else(|| {
+                    Error::NoSuitableDevice("every physical device was rejected".to_owned())
+                })?,
         };
+        if let Some(error) = create_info.missing_requirements(&physical_device_info) {
+            return Err(error);
+        }
+        let physical_device = physical_device_info.handle;
 
-        let physical_device = physical_devices
-            .into_iter()
-            .max_by_key(|physical_device| {
-                let properties = unsafe {
-                    instance
-                        .handle()
-                        .get_physical_device_properties(*physical_device)
-                };
+        let enabled_features = (create_info.all_required_features()
+            | (create_info.optional_features & physical_device_info.features))
+            .with_dependencies();
 
-                selector(&DeviceProperties::from(properties))
-            })
-            .expect("Failed to find suitable physical device");
+        let headless = instance.is_headless();
+        let mut enabled_extensions = enabled_features
+            .extensions()
+            .into_iter()
+            .map(|name| name.to_string_lossy().into_owned())
+            .chain(
+                create_info
+                    .required_extensions
+                    .iter()
+                    .map(|name| name.to_string()),
+            )
+            .chain(
+                create_info
+                    .optional_extensions
+                    .iter()
+                    .chain(&["VK_KHR_shader_non_semantic_info", "VK_EXT_memory_budget"])
+                    .filter(|name| physical_device_info.supports_extension(name))
+                    .map(|name| name.to_string()),
+            )
+            .collect::<Vec<_>>();
+        if !headless {
+            enabled_extensions.push(ash::khr::swapchain::NAME.to_string_lossy().into_owned());
+        }
+        enabled_extensions.sort();
+        enabled_extensions.dedup();
 
         let physical_device_properties = unsafe {
             instance
@@ -113,212 +286,549 @@ impl Device {
                 .get_physical_device_memory_properties(physical_device)
         };
 
-        let queue_create_infos = [vk::DeviceQueueCreateInfo::default()
-            .queue_family_index(0)
-            .queue_priorities(&[1.0])];
-
-        let shader_non_semantic_info_c_string =
-            CString::new("VK_KHR_shader_non_semantic_info").unwrap();
-        let device_extensions = vec![
-            ash::khr::swapchain::NAME.as_ptr(),
-            ash::khr::dynamic_rendering::NAME.as_ptr(),
-            shader_non_semantic_info_c_string.as_ptr(),
-        ];
+        let queue_families = QueueFamilies::select(&physical_device_info)?;
+        let queue_create_infos = queue_families
+            .unique_family_indices()
+            .into_iter()
+            .map(|family_index| {
+                vk::DeviceQueueCreateInfo::default()
+                    .queue_family_index(family_index)
+                    .queue_priorities(&[1.0])
+            })
+            .collect::<Vec<_>>();
 
-        let mut dynamic_rendering_features =
-            vk::PhysicalDeviceDynamicRenderingFeaturesKHR::default().dynamic_rendering(true);
-        let mut descriptor_indexing_features =
-            vk::PhysicalDeviceDescriptorIndexingFeatures::default();
-        descriptor_indexing_features.p_next =
-            &mut dynamic_rendering_features as *mut _ as *mut c_void;
-        let mut timeline_semaphore_features =
-            vk::PhysicalDeviceTimelineSemaphoreFeatures::default().timeline_semaphore(true);
-        timeline_semaphore_features.p_next =
-            &mut descriptor_indexing_features as *mut _ as *mut c_void;
-        let mut buffer_device_address_features =
-            vk::PhysicalDeviceBufferDeviceAddressFeatures::default().buffer_device_address(true);
-        buffer_device_address_features.p_next =
-            &mut timeline_semaphore_features as *mut _ as *mut c_void;
-
-        let mut device_features =
-            vk::PhysicalDeviceFeatures2::default().push_next(&mut buffer_device_address_features);
+        let c_enabled_extensions = enabled_extensions
+            .iter()
+            .map(|name| CString::new(name.as_str()).unwrap())
+            .collect::<Vec<_>>();
+        let c_ptr_enabled_extensions = c_enabled_extensions
+            .iter()
+            .map(|name| name.as_ptr())
+            .collect::<Vec<_>>();
 
-        unsafe {
-            instance
-                .handle()
-                .get_physical_device_features2(physical_device, &mut device_features);
+        let mut vk_features = VkDeviceFeatures::new(enabled_features);
+        let mut device_features = vk::PhysicalDeviceFeatures2::default()
+            .features(vk_features.core)
+            .push_next(&mut vk_features.vulkan11)
+            .push_next(&mut vk_features.vulkan12);
+        // Extension structures can only be chained when the extension is enabled.
+        if enabled_features.contains(DeviceFeatures::DYNAMIC_RENDERING) {
+            device_features = device_features.push_next(&mut vk_features.dynamic_rendering);
+        }
+        if enabled_features.contains(DeviceFeatures::SYNCHRONIZATION_2) {
+            device_features = device_features.push_next(&mut vk_features.synchronization2);
+        }
+        if enabled_features.contains(DeviceFeatures::ROBUSTNESS_2) {
+            device_features = device_features.push_next(&mut vk_features.robustness2);
         }
 
         let device_create_info = vk::DeviceCreateInfo::default()
             .push_next(&mut device_features)
             .queue_create_infos(&queue_create_infos)
-            .enabled_extension_names(&device_extensions);
-
+            .enabled_extension_names(&c_ptr_enabled_extensions);
         let device = unsafe {
             instance
                 .handle()
                 .create_device(physical_device, &device_create_info, None)
-                .expect("Failed to create logical device")
-        };
+        }?;
 
         let dynamic_rendering_loader =
             ash::khr::dynamic_rendering::Device::new(unsafe { instance.handle() }, &device);
-
-        let debug_utils = ash::ext::debug_utils::Device::new(unsafe { instance.handle() }, &device);
-
-        let main_queue = unsafe { device.get_device_queue(0, 0) };
-        let main_queue_family_index = 0;
+        let synchronization2_loader =
+            ash::khr::synchronization2::Device::new(unsafe { instance.handle() }, &device);
+
+        let debug_utils = instance
+            .create_dep()
+            .debug_utils
+            .is_some()
+            .then(|| ash::ext::debug_utils::Device::new(unsafe { instance.handle() }, &device));
+
+        // Queue types that fell back to the same family share its queue.
+        let queues = QueueType::ALL.map(|queue_type| {
+            let family_index = queue_families.family_index(queue_type);
+            Queue {
+                handle: unsafe { device.get_device_queue(family_index, 0) },
+                family_index,
+            }
+        });
+        let mut queue_locks: Vec<Arc<Mutex<()>>> = Vec::new();
+        for queue in &queues {
+            let lock = queues
+                .iter()
+                .zip(&queue_locks)
+                .find(|(other, _)| other.handle == queue.handle)
+                .map(|(_, lock)| lock.clone())
+                .unwrap_or_default();
+            queue_locks.push(lock);
+        }
+        let queue_locks: [Arc<Mutex<()>>; 3] = queue_locks.try_into().unwrap();
 
         let inner_device = DeviceInner {
             instance_dep: instance.create_dep(),
             device,
-            main_queue_family_index,
+            queue_families,
             physical_device,
             physical_device_properties,
             physical_device_memory_properties,
+            physical_device_info,
+            enabled_features,
+            enabled_extensions,
             dynamic_rendering_loader,
+            synchronization2_loader,
             debug_utils,
+            headless,
         };
 
-        let deferred_destruct_recorders = HashMap::new();
-
         let device_dep = Arc::new(inner_device);
-        let gpu_resources = GpuResourcePool::new(device_dep.clone());
+        let gpu_resources =
+            GpuResourcePool::new(device_dep.clone(), create_info.bindless_capacities)?;
+
+        let mut pipeline_cache = PipelineCache::new(device_dep.clone())?;
+        if let Some(path) = create_info.pipeline_cache_path {
+            pipeline_cache.load(path)?;
+        }
+        let queue_timelines = [
+            TimelineSemaphore::from_dep(device_dep.clone(), 0, "paya graphics timeline")?,
+            TimelineSemaphore::from_dep(device_dep.clone(), 0, "paya compute timeline")?,
+            TimelineSemaphore::from_dep(device_dep.clone(), 0, "paya transfer timeline")?,
+        ];
 
-        Device {
+        Ok(Device {
             inner: device_dep.clone(),
-            main_queue,
+            queues,
+            queue_locks,
+            queue_timelines,
+            queue_timeline_values: Default::default(),
             gpu_resources,
             command_recorder_pool: CommandRecorderPool::new(device_dep.clone()),
-            deferred_destruct_recorders,
-            deferred_destruct_buffers: HashMap::new(),
-            deferred_destruct_images: HashMap::new(),
-            frame_index: 0,
-        }
+            garbage: Mutex::new(GarbageQueue::default()),
+            pipeline_cache,
+            uploader: Mutex::new(Uploader::default()),
+            frame_index: AtomicU64::new(0),
+        })
     }
 
-    pub fn create_swapchain(&mut self, create_info: SwapchainCreateInfo<'_>) -> Swapchain {
+    pub fn create_swapchain(&self, create_info: SwapchainCreateInfo<'_>) -> Result<Swapchain> {
+        if self.inner.headless {
+            return Err(Error::InvalidUsage(
+                "Cannot create a swapchain on a headless device".to_owned(),
+            ));
+        }
         Swapchain::new(self, create_info)
     }
 
     pub(crate) fn create_swapchain_image(
-        &mut self,
+        &self,
         image_handle: vk::Image,
         info: &ImageInfo,
-    ) -> ImageId {
+    ) -> Result<ImageId> {
         self.gpu_resources.create_image(Some(image_handle), info)
     }
 
-    pub fn create_image(&mut self, info: ImageInfo) -> ImageId {
+    /// The sizes of the bindless tables after clamping them to the device limits.
+    pub fn bindless_capacities(&self) -> BindlessCapacities {
+        self.gpu_resources.capacities()
+    }
+
+    /// A compiler whose preamble matches the bindless tables of this device.
+    pub fn create_shader_compiler(&self) -> Result<ShaderCompiler> {
+        Ok(ShaderCompiler::with_bindless_capacities(
+            self.bindless_capacities(),
+        )?)
+    }
+
+    pub fn create_image(&self, info: ImageInfo) -> Result<ImageId> {
         self.gpu_resources.create_image(None, &info)
     }
 
-    pub fn get_image(&self, id: ImageId) -> &Image {
+    pub fn get_image(&self, id: ImageId) -> Result<ResourceRef<'_, Image>> {
         self.gpu_resources.get_image(id)
     }
 
-    pub fn destroy_image(&mut self, id: ImageId) {
-        self.gpu_resources.destroy_image(id);
+    /// Registers a view of part of an image in the bindless tables. Views have to be
+    /// destroyed with `destroy_image` before the image they view.
+    pub fn create_image_view(&self, info: ImageViewInfo) -> Result<ImageId> {
+        self.gpu_resources.create_image_view(&info)
+    }
+
+    /// The id shaders write a mip level of a storage image through, storage images can only
+    /// access a single mip level per view. Mip level 0 is the image itself.
+    pub fn image_mip_view(&self, id: ImageId, mip_level: u32) -> Result<ImageId> {
+        let image = self.get_image(id)?;
+        if mip_level == 0 {
+            return Ok(id);
+        }
+        image
+            .mip_views
+            .get(mip_level as usize - 1)
+            .copied()
+            .ok_or_else(|| {
+                Error::InvalidUsage(format!(
+                    "Image {} has no storage view of mip level {}",
+                    image.info.name, mip_level
+                ))
+            })
     }
 
-    pub fn destroy_image_deferred(&mut self, id: ImageId) {
-        self.deferred_destruct_images
-            .entry(self.frame_index + 1)
-            .or_default()
-            .push(id);
+    pub fn destroy_image(&self, id: ImageId) -> Result<()> {
+        self.gpu_resources.destroy_image(id)
     }
 
-    pub fn create_buffer(&mut self, info: BufferInfo) -> BufferId {
+    /// Destroys the image once every submission made so far has completed. A image used by
+    /// commands that haven't been submitted yet goes through
+    /// `CommandRecorder::destroy_image_deferred` instead.
+    pub fn destroy_image_deferred(&self, id: ImageId) {
+        self.push_garbage(Garbage::Image(id));
+    }
+
+    pub fn create_buffer(&self, info: BufferInfo) -> Result<BufferId> {
         self.gpu_resources.create_buffer(&info)
     }
 
-    pub fn get_buffer(&self, id: BufferId) -> &Buffer {
+    pub fn get_buffer(&self, id: BufferId) -> Result<ResourceRef<'_, Buffer>> {
         self.gpu_resources.get_buffer(id)
     }
 
-    pub fn destroy_buffer(&mut self, id: BufferId) {
-        self.gpu_resources.destroy_buffer(id);
+    pub fn destroy_buffer(&self, id: BufferId) -> Result<()> {
+        self.gpu_resources.destroy_buffer(id)
     }
 
-    pub fn destroy_buffer_deferred(&mut self, id: BufferId) {
-        self.deferred_destruct_buffers
-            .entry(self.frame_index + 1)
-            .or_default()
-            .push(id);
+    /// Destroys the buffer once every submission made so far has completed. A buffer used by
+    /// commands that haven't been submitted yet goes through
+    /// `CommandRecorder::destroy_buffer_deferred` instead.
+    pub fn destroy_buffer_deferred(&self, id: BufferId) {
+        self.push_garbage(Garbage::Buffer(id));
     }
 
-    pub fn map_buffer_typed<T>(&self, id: BufferId) -> *mut T {
-        let buffer = self.gpu_resources.get_buffer(id);
-        buffer
-            .allocation
-            .allocation
-            .mapped_ptr()
-            .expect("Could not map buffer")
-            .as_ptr() as *mut T
+    pub fn create_sampler(&self, info: SamplerInfo) -> Result<SamplerId> {
+        self.gpu_resources.create_sampler(&info)
     }
 
-    pub fn create_command_recorder(&mut self) -> CommandRecorder {
-        self.command_recorder_pool.create_command_recorder()
+    pub fn get_sampler(&self, id: SamplerId) -> Result<ResourceRef<'_, Sampler>> {
+        self.gpu_resources.get_sampler(id)
     }
 
-    pub fn submit(&mut self, info: SubmitInfo) {
-        let wait_semaphores = info
-            .wait_semaphores
+    pub fn destroy_sampler(&self, id: SamplerId) -> Result<()> {
+        self.gpu_resources.destroy_sampler(id)
+    }
+
+    /// Destroys the sampler once every submission made so far has completed. A sampler used by
+    /// commands that haven't been submitted yet goes through
+    /// `CommandRecorder::destroy_sampler_deferred` instead.
+    pub fn destroy_sampler_deferred(&self, id: SamplerId) {
+        self.push_garbage(Garbage::Sampler(id));
+    }
+
+    /// Destroys the pipeline once every submission made so far has completed. A pipeline used by
+    /// commands that haven't been submitted yet goes through
+    /// `CommandRecorder::destroy_pipeline_deferred` instead.
+    pub fn destroy_pipeline_deferred(&self, pipeline: impl Pipeline + 'static) {
+        self.push_garbage(Garbage::Pipeline(Box::new(pipeline)));
+    }
+
+    /// Queues garbage behind every submission made before the call.
+    fn push_garbage(&self, garbage: Garbage) {
+        let mut garbage_queue = self.garbage.lock().unwrap();
+        let timeline_values = QueueType::ALL.map(|queue_type| {
+            self.queue_timeline_values[queue_type as usize].load(Ordering::Acquire)
+        });
+        garbage_queue.push(timeline_values, garbage);
+    }
+
+    pub fn map_buffer_typed<T>(&self, id: BufferId) -> Result<*mut T> {
+        let buffer = self.gpu_resources.get_buffer(id)?;
+        let ptr = buffer.allocation.allocation.mapped_ptr().ok_or_else(|| {
+            Error::InvalidUsage(format!("Buffer {} is not host visible", buffer.info.name))
+        })?;
+
+        Ok(ptr.as_ptr() as *mut T)
+    }
+
+    /// Copies `data` into the buffer at `offset` bytes through a staging buffer, so buffers in
+    /// `MemoryLocation::GpuOnly` memory can be filled. The buffer needs `TRANSFER_DST` usage.
+    /// Copies are batched until `Device::flush_uploads` submits them. Typed data is passed as
+    /// bytes, e.g. with `bytemuck::cast_slice`, so padding is never read.
+    pub fn upload_buffer(&self, id: BufferId, offset: u64, data: &[u8]) -> Result<()> {
+        self.uploader
+            .lock()
+            .unwrap()
+            .upload_buffer(self, id, offset, data)
+    }
+
+    /// Submits the uploads made so far, returns `None` when there were none. Work submitted to
+    /// the graphics queue afterwards sees the uploaded data, other queues have to wait on the
+    /// returned submission.
+    pub fn flush_uploads(&self) -> Result<Option<Submission>> {
+        self.uploader.lock().unwrap().flush(self)
+    }
+
+    /// Copies `range` bytes of the buffer back to the cpu, blocking until every submission made
+    /// so far to the graphics queue and the copy have completed. The buffer needs
+    /// `TRANSFER_SRC` usage. Typed data is read from the bytes, e.g. with
+    /// `bytemuck::pod_collect_to_vec`, like `Device::upload_buffer` takes it.
+    pub fn read_buffer(&self, id: BufferId, range: Range<u64>) -> Result<Vec<u8>> {
+        readback::read_buffer(self, id, range)
+    }
+
+    /// Copies a mip level and array layer of the image back to the cpu as tightly packed rows,
+    /// blocking like `Device::read_buffer`. `layout` is the layout the image is in, it's
+    /// restored after the copy. The image needs `TRANSFER_SRC` usage.
+    pub fn read_image(
+        &self,
+        id: ImageId,
+        mip: u32,
+        layer: u32,
+        layout: ImageLayout,
+    ) -> Result<Vec<u8>> {
+        readback::read_image(self, id, mip, layer, layout)
+    }
+
+    /// Creates a recorder whose commands can only be submitted to queues of the same type.
+    /// Recorders can be sent to and recorded on other threads, their command lists can then be
+    /// submitted together from any thread. Recorders and command lists that are dropped
+    /// without being submitted go back to the pool.
+    pub fn create_command_recorder(
+        &self,
+        queue_type: QueueType,
+        name: &str,
+    ) -> Result<CommandRecorder> {
+        self.command_recorder_pool
+            .create_command_recorder(queue_type, name)
+    }
+
+    /// Submits the command lists, the returned handle can be waited on with `Device::wait`.
+    pub fn submit(&self, info: SubmitInfo) -> Result<Submission> {
+        self.submit_batches(vec![info])
+    }
+
+    /// Submits several batches to the same queue with a single `vkQueueSubmit`. The returned
+    /// handle completes once every batch has finished.
+    pub fn submit_batches(&self, batches: Vec<SubmitInfo>) -> Result<Submission> {
+        let queue_type = batches
+            .first()
+            .ok_or_else(|| Error::InvalidUsage("submitted without any batches".to_owned()))?
+            .queue;
+        let queue = self.queue(queue_type);
+        if let Err(error) = self.validate_batches(&batches, queue_type, queue) {
+            self.discard_batches(batches);
+            return Err(error);
+        }
+
+        // Held until the new timeline value is published so values are signalled in order.
+        let _queue_lock = self.queue_locks[queue_type as usize].lock().unwrap();
+        let timeline_value =
+            self.queue_timeline_values[queue_type as usize].load(Ordering::Acquire) + 1;
+        let last_batch = batches.len() - 1;
+        let vk_batches = batches
             .iter()
-            .map(|semaphore| semaphore.handle())
+            .enumerate()
+            .map(|(index, batch)| {
+                // Signal operations happen in submission order so signalling the queue timeline
+                // in the last batch also covers the earlier ones.
+                let queue_timeline_signal = (index == last_batch).then(|| {
+                    (
+                        self.queue_timelines[queue_type as usize].handle(),
+                        timeline_value,
+                    )
+                });
+                VkSubmitBatch::new(batch, queue_timeline_signal)
+            })
             .collect::<Vec<_>>();
 
-        let (signal_semaphores, signal_values): (Vec<vk::Semaphore>, Vec<u64>) = info
-            .signal_semaphores
+        let mut timeline_submit_infos = vk_batches
             .iter()
-            .map(|semaphore| (semaphore.handle(), 0))
-            .chain(
-                info.signal_timeline_semaphores
-                    .iter()
-                    .map(|(semaphore, value)| (semaphore.handle(), *value)),
-            )
-            .unzip();
+            .map(|batch| {
+                vk::TimelineSemaphoreSubmitInfo::default()
+                    .wait_semaphore_values(&batch.wait_values)
+                    .signal_semaphore_values(&batch.signal_values)
+            })
+            .collect::<Vec<_>>();
 
-        let command_buffers = info
-            .commands
+        let submit_infos = vk_batches
             .iter()
-            .map(|command_list| command_list.handle())
+            .zip(timeline_submit_infos.iter_mut())
+            .map(|(batch, timeline_submit_info)| {
+                vk::SubmitInfo::default()
+                    .push_next(timeline_submit_info)
+                    .wait_semaphores(&batch.wait_semaphores)
+                    .wait_dst_stage_mask(&batch.wait_stages)
+                    .command_buffers(&batch.command_buffers)
+                    .signal_semaphores(&batch.signal_semaphores)
+            })
             .collect::<Vec<_>>();
 
-        let mut timeline_submit_info =
-            vk::TimelineSemaphoreSubmitInfo::default().signal_semaphore_values(&signal_values);
+        let result = unsafe {
+            self.handle()
+                .queue_submit(queue.handle, &submit_infos, vk::Fence::null())
+        };
+        if let Err(error) = result {
+            self.discard_batches(batches);
+            return Err(error.into());
+        }
 
-        let submit_info = vk::SubmitInfo::default()
-            .push_next(&mut timeline_submit_info)
-            .wait_semaphores(&wait_semaphores)
-            .wait_dst_stage_mask(&[vk::PipelineStageFlags::BOTTOM_OF_PIPE])
-            .command_buffers(&command_buffers)
-            .signal_semaphores(&signal_semaphores);
-
-        self.deferred_destruct_recorders
-            .entry(self.frame_index + 1)
-            .or_default()
-            .extend(info.commands.iter().map(|list| list.id));
-
-        self.deferred_destruct_buffers
-            .entry(self.frame_index + 1)
-            .or_default()
-            .extend(
-                info.commands
-                    .iter()
-                    .flat_map(|list| list.deferred_delete_buffers.clone()),
-            );
+        // Recorders and the resources they destroy are only used by this submission. The value
+        // is published under the garbage lock so `push_garbage` never sees a submission without
+        // its garbage.
+        let mut garbage = self.garbage.lock().unwrap();
+        for list in batches.into_iter().flat_map(|batch| batch.commands) {
+            let (id, deferred_garbage) = list.into_submitted();
+            garbage.push_for_submission(queue_type, timeline_value, Garbage::Recorder(id));
+            for deferred in deferred_garbage {
+                garbage.push_for_submission(queue_type, timeline_value, deferred);
+            }
+        }
+        self.queue_timeline_values[queue_type as usize].store(timeline_value, Ordering::Release);
+        drop(garbage);
 
-        unsafe {
-            self.handle()
-                .queue_submit(self.main_queue, &[submit_info], vk::Fence::null())
-                .expect("Failed to submit queue");
+        Ok(Submission {
+            queue: queue_type,
+            value: timeline_value,
+        })
+    }
+
+    fn validate_batches(
+        &self,
+        batches: &[SubmitInfo],
+        queue_type: QueueType,
+        queue: Queue,
+    ) -> Result<()> {
+        for batch in batches {
+            if batch.queue != queue_type {
+                return Err(Error::InvalidUsage(format!(
+                    "batches for the {:?} and {:?} queues were submitted together",
+                    queue_type, batch.queue
+                )));
+            }
+
+            if let Some(list) = batch.commands.iter().find(|list| {
+                self.inner.queue_families.family_index(list.queue_type) != queue.family_index
+            }) {
+                return Err(Error::InvalidUsage(format!(
+                    "command list recorded for the {:?} queue can't be submitted to the {:?} queue",
+                    list.queue_type, queue_type
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Cleans up after batches that failed to submit. Dropping the lists returns their
+    /// recorders to the pool, the resources they were going to destroy are destroyed once the
+    /// work submitted so far has completed.
+    fn discard_batches(&self, batches: Vec<SubmitInfo>) {
+        for list in batches.into_iter().flat_map(|batch| batch.commands) {
+            self.discard_command_list(list);
+        }
+    }
+
+    fn discard_command_list(&self, mut list: CommandList) {
+        for deferred in std::mem::take(&mut list.deferred_garbage) {
+            self.push_garbage(deferred);
         }
+    }
+
+    /// Records commands with `record` and submits them to the graphics queue, blocking until
+    /// they have executed. Meant for setup work, the recorder and the buffers it destroyed are
+    /// recycled before returning.
+    pub fn immediate<R>(
+        &self,
+        record: impl FnOnce(&mut CommandRecorder) -> Result<R>,
+    ) -> Result<R> {
+        let (submission, value) = self.submit_immediate(record)?;
+        self.wait(submission, None)?;
+        self.collect_garbage()?;
+
+        Ok(value)
+    }
+
+    /// Like `Device::immediate` without blocking, the recorder is recycled by
+    /// `Device::collect_garbage` once the returned submission has completed.
+    pub fn immediate_async(
+        &self,
+        record: impl FnOnce(&mut CommandRecorder) -> Result<()>,
+    ) -> Result<Submission> {
+        Ok(self.submit_immediate(record)?.0)
+    }
+
+    fn submit_immediate<R>(
+        &self,
+        record: impl FnOnce(&mut CommandRecorder) -> Result<R>,
+    ) -> Result<(Submission, R)> {
+        let mut recorder = self.create_command_recorder(QueueType::Graphics, "paya immediate")?;
+        let value = match record(&mut recorder) {
+            Ok(value) => value,
+            Err(error) => {
+                // Never submitted, the recorder can be reused right away.
+                self.discard_command_list(recorder.finish(self)?);
+                return Err(error);
+            }
+        };
+
+        let submission = self.submit(SubmitInfo {
+            queue: QueueType::Graphics,
+            commands: vec![recorder.finish(self)?],
+            wait_semaphores: vec![],
+            wait_timeline_semaphores: vec![],
+            signal_semaphores: vec![],
+            signal_timeline_semaphores: vec![],
+        })?;
+
+        Ok((submission, value))
+    }
+
+    /// Blocks until the submission has finished executing, `None` waits forever. Returns
+    /// `false` when the timeout elapsed first.
+    pub fn wait(&self, submission: Submission, timeout: Option<Duration>) -> Result<bool> {
+        self.queue_timelines[submission.queue as usize].wait(submission.value, timeout)
+    }
+
+    /// Blocks until all of the submissions have finished executing.
+    pub fn wait_all(&self, submissions: &[Submission], timeout: Option<Duration>) -> Result<bool> {
+        let (semaphores, values): (Vec<_>, Vec<_>) = submissions
+            .iter()
+            .map(|submission| {
+                (
+                    self.queue_timelines[submission.queue as usize].handle(),
+                    submission.value,
+                )
+            })
+            .unzip();
+
+        sync::wait_semaphores(&self.inner, &semaphores, &values, timeout)
+    }
+
+    pub fn is_complete(&self, submission: Submission) -> Result<bool> {
+        Ok(self.queue_timelines[submission.queue as usize].value()? >= submission.value)
+    }
 
-        self.frame_index += 1;
+    /// Blocks until every queue is idle.
+    pub fn wait_idle(&self) -> Result<()> {
+        Ok(unsafe { self.handle().device_wait_idle() }?)
     }
 
-    pub fn present(&self, info: PresentInfo) {
+    /// The timeline semaphore signalled by every submission to `queue_type`.
+    pub fn queue_timeline(&self, queue_type: QueueType) -> &TimelineSemaphore {
+        &self.queue_timelines[queue_type as usize]
+    }
+
+    pub fn create_binary_semaphore(&self, name: &str) -> Result<BinarySemaphore> {
+        BinarySemaphore::new(self, name)
+    }
+
+    pub fn create_timeline_semaphore(
+        &self,
+        initial_value: u64,
+        name: &str,
+    ) -> Result<TimelineSemaphore> {
+        TimelineSemaphore::new(self, initial_value, name)
+    }
+
+    pub fn create_query_pool(&self, info: QueryPoolInfo) -> Result<QueryPool> {
+        QueryPool::new(self, info)
+    }
+
+    pub fn present(&self, info: PresentInfo) -> Result<()> {
         let wait_semaphores = info
             .wait_semaphores
             .iter()
@@ -326,65 +836,134 @@ impl Device {
             .collect::<Vec<_>>();
 
         let swapchains = [info.swapchain.handle()];
-        let image_indices = [info.swapchain.last_aquired_image_index().unwrap()];
+        let image_indices = [info
+            .swapchain
+            .last_aquired_image_index()
+            .ok_or_else(|| Error::InvalidUsage("No swapchain image was acquired".to_owned()))?];
 
         let present_info = vk::PresentInfoKHR::default()
             .wait_semaphores(&wait_semaphores)
             .swapchains(&swapchains)
             .image_indices(&image_indices);
 
-        unsafe {
-            info.swapchain
-                .loader()
-                .queue_present(self.main_queue, &present_info)
-                .expect("Failed to present queue");
+        let result = {
+            let _queue_lock = self.queue_locks[QueueType::Graphics as usize]
+                .lock()
+                .unwrap();
+            unsafe {
+                info.swapchain
+                    .loader()
+                    .queue_present(self.queue(QueueType::Graphics).handle, &present_info)
+            }
+        };
+
+        // The frame still counts when the swapchain went out of date, its submission already
+        // signalled the frame's semaphores. The next acquire reports it so it can be resized.
+        self.frame_index.fetch_add(1, Ordering::Relaxed);
+        match result {
+            Ok(_) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {}
+            Err(result) => return Err(result.into()),
         }
+
+        Ok(())
     }
 
-    pub fn collect_garbage(&mut self, timeline_semaphore: &TimelineSemaphore) {
-        let gpu_count = unsafe {
-            self.handle()
-                .get_semaphore_counter_value(timeline_semaphore.handle())
-        }
-        .expect("Couldn't get semaphore value");
-
-        // TODO Fix this later
-        for i in 0..(3) {
-            let index = (gpu_count as i64 - i).max(0) as u64;
-
-            for recorder_id in self
-                .deferred_destruct_recorders
-                .get_mut(&index)
-                .unwrap_or(&mut Vec::new())
-                .drain(0..)
-            {
-                self.command_recorder_pool
-                    .free_command_recorder(recorder_id);
-            }
+    /// Destroys every deferred resource whose submissions have completed on the gpu. Doesn't
+    /// block, call it once per frame or after waiting on a submission. Returns the first error
+    /// after every completed resource has been destroyed.
+    pub fn collect_garbage(&self) -> Result<()> {
+        let mut completed_values = [0; 3];
+        for queue_type in QueueType::ALL {
+            completed_values[queue_type as usize] =
+                self.queue_timelines[queue_type as usize].value()?;
+        }
 
-            for buffer_id in self
-                .deferred_destruct_buffers
-                .get_mut(&index)
-                .unwrap_or(&mut Vec::new())
-                .drain(0..)
-                .collect::<Vec<_>>()
-            {
-                self.destroy_buffer(buffer_id);
+        // Taken out first so the lock isn't held while destroying.
+        let completed = self
+            .garbage
+            .lock()
+            .unwrap()
+            .take_completed(completed_values);
+        // Every entry is destroyed even when one fails, they're no longer in the queue.
+        let mut first_error = None;
+        for garbage in completed {
+            let result = match garbage {
+                Garbage::Buffer(id) => self.destroy_buffer(id),
+                Garbage::Image(id) => self.destroy_image(id),
+                Garbage::Sampler(id) => self.destroy_sampler(id),
+                Garbage::Recorder(id) => self.command_recorder_pool.free_command_recorder(id),
+                Garbage::Pipeline(pipeline) => {
+                    drop(pipeline);
+                    Ok(())
+                }
+            };
+            if let Err(error) = result {
+                first_error.get_or_insert(error);
             }
+        }
 
-            for image_id in self
-                .deferred_destruct_images
-                .get_mut(&index)
-                .unwrap_or(&mut Vec::new())
-                .drain(0..)
-                .collect::<Vec<_>>()
-            {
-                self.destroy_image(image_id);
-            }
+        match first_error {
+            Some(error) => Err(error),
+            None => Ok(()),
         }
     }
 
-    pub fn create_raster_pipeline(&self, info: RasterPipelineInfo) -> RasterPipeline {
+    /// The cache used by every pipeline created on this device, save it before shutting down
+    /// to speed up the next launch.
+    pub fn pipeline_cache(&self) -> &PipelineCache {
+        &self.pipeline_cache
+    }
+
+    /// Per heap budget and usage plus every live allocation. Budget and usage come from
+    /// `VK_EXT_memory_budget` and are left empty when the device doesn't support it.
+    pub fn memory_report(&self) -> MemoryReport {
+        let budget_enabled = self.is_extension_enabled("VK_EXT_memory_budget");
+
+        let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut properties = vk::PhysicalDeviceMemoryProperties2::default();
+        if budget_enabled {
+            properties = properties.push_next(&mut budget_properties);
+        }
+        unsafe {
+            self.instance()
+                .instance
+                .get_physical_device_memory_properties2(self.inner.physical_device, &mut properties)
+        };
+        let memory_properties = properties.memory_properties;
+
+        let heaps = memory_properties
+            .memory_heaps_as_slice()
+            .iter()
+            .enumerate()
+            .map(|(index, heap)| MemoryHeapReport {
+                index: index as u32,
+                size: heap.size,
+                device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+                budget: budget_enabled.then(|| budget_properties.heap_budget[index]),
+                usage: budget_enabled.then(|| budget_properties.heap_usage[index]),
+            })
+            .collect();
+
+        let allocations = self.gpu_resources.allocation_reports();
+        let allocated_bytes = allocations.iter().map(|allocation| allocation.size).sum();
+
+        MemoryReport {
+            heaps,
+            allocations,
+            allocated_bytes,
+        }
+    }
+
+    /// The number of deferred resources still waiting on the gpu.
+    pub fn pending_garbage(&self) -> usize {
+        self.garbage.lock().unwrap().len()
+    }
+
+    pub fn create_raster_pipeline(&self, info: RasterPipelineInfo) -> Result<RasterPipeline> {
+        if let Some(depth_stencil) = &info.depth_stencil {
+            self.validate_depth_stencil(&info.name, depth_stencil)?;
+        }
+
         let vertex_shader_module_create_info =
             vk::ShaderModuleCreateInfo::default().code(info.vertex_shader.byte_code.as_slice());
         let fragment_shader_module_create_info =
@@ -393,13 +972,21 @@ impl Device {
         let vertex_shader_module = unsafe {
             self.handle()
                 .create_shader_module(&vertex_shader_module_create_info, None)
-        }
-        .unwrap();
+        }?;
         let fragment_shader_module = unsafe {
             self.handle()
                 .create_shader_module(&fragment_shader_module_create_info, None)
-        }
-        .unwrap();
+        };
+        let fragment_shader_module = match fragment_shader_module {
+            Ok(module) => module,
+            Err(error) => {
+                unsafe {
+                    self.handle()
+                        .destroy_shader_module(vertex_shader_module, None)
+                };
+                return Err(error.into());
+            }
+        };
 
         let push_constant_ranges = if info.push_constant_size > 0 {
             vec![vk::PushConstantRange::default()
@@ -418,15 +1005,24 @@ impl Device {
         let pipeline_layout = unsafe {
             self.handle()
                 .create_pipeline_layout(&pipeline_layout_create_info, None)
-        }
-        .unwrap();
+        };
+        let pipeline_layout = match pipeline_layout {
+            Ok(pipeline_layout) => pipeline_layout,
+            Err(error) => {
+                unsafe {
+                    self.handle()
+                        .destroy_shader_module(vertex_shader_module, None);
+                    self.handle()
+                        .destroy_shader_module(fragment_shader_module, None);
+                }
+                return Err(error.into());
+            }
+        };
 
         let vertex_shader_entry_cstring =
-            std::ffi::CString::new(info.vertex_shader.entry_point.as_str())
-                .expect("Failed to convert entry point to CString");
+            std::ffi::CString::new(info.vertex_shader.entry_point.as_str()).unwrap_or_default();
         let fragment_shader_entry_cstring =
-            std::ffi::CString::new(info.fragment_shader.entry_point.as_str())
-                .expect("Failed to convert entry point to CString");
+            std::ffi::CString::new(info.fragment_shader.entry_point.as_str()).unwrap_or_default();
         let color_attachment_formats = info
             .color_attachments
             .iter()
@@ -434,6 +1030,24 @@ impl Device {
             .collect::<Vec<_>>();
         let mut pipeline_rendering_create_info = vk::PipelineRenderingCreateInfo::default()
             .color_attachment_formats(&color_attachment_formats);
+        let mut depth_stencil_create_info = vk::PipelineDepthStencilStateCreateInfo::default();
+        if let Some(depth_stencil) = info.depth_stencil {
+            pipeline_rendering_create_info =
+                pipeline_rendering_create_info.depth_attachment_format(depth_stencil.format.into());
+            if depth_stencil.format.has_stencil() {
+                pipeline_rendering_create_info = pipeline_rendering_create_info
+                    .stencil_attachment_format(depth_stencil.format.into());
+            }
+            depth_stencil_create_info = depth_stencil_create_info
+                .depth_test_enable(depth_stencil.depth_test)
+                .depth_write_enable(depth_stencil.depth_write)
+                .depth_compare_op(depth_stencil.depth_compare_op.into())
+                .stencil_test_enable(depth_stencil.stencil_test)
+                .front(depth_stencil.stencil_front.into())
+                .back(depth_stencil.stencil_back.into())
+                .min_depth_bounds(0.0)
+                .max_depth_bounds(1.0);
+        }
 
         let shader_stages = [
             vk::PipelineShaderStageCreateInfo::default()
@@ -517,6 +1131,7 @@ impl Device {
             .stages(&shader_stages)
             .rasterization_state(&rasterization_create_info)
             .color_blend_state(&color_blend_create_info)
+            .depth_stencil_state(&depth_stencil_create_info)
             .multisample_state(&multisample_create_info)
             .input_assembly_state(&input_assembly_create_info)
             .viewport_state(&viewport_create_info)
@@ -524,11 +1139,10 @@ impl Device {
             .vertex_input_state(&vertex_input_state_create_info)
             .layout(pipeline_layout)];
 
-        let pipeline = unsafe {
+        let pipelines = unsafe {
             self.handle()
-                .create_graphics_pipelines(vk::PipelineCache::null(), &create_infos, None)
-        }
-        .unwrap()[0];
+                .create_graphics_pipelines(self.pipeline_cache.handle, &create_infos, None)
+        };
 
         unsafe {
             self.handle()
@@ -537,24 +1151,60 @@ impl Device {
                 .destroy_shader_module(fragment_shader_module, None);
         }
 
-        RasterPipeline {
+        let pipeline = match pipelines {
+            Ok(pipelines) => pipelines[0],
+            Err((_, error)) => {
+                unsafe { self.handle().destroy_pipeline_layout(pipeline_layout, None) };
+                return Err(error.into());
+            }
+        };
+        self.inner.set_debug_name(pipeline, &info.name);
+        self.inner.set_debug_name(pipeline_layout, &info.name);
+
+        Ok(RasterPipeline {
             inner: PipelineInner {
                 device_dep: self.create_dep(),
                 pipeline,
                 pipeline_layout,
             },
+        })
+    }
+
+    fn validate_depth_stencil(&self, name: &str, depth_stencil: &DepthStencilInfo) -> Result<()> {
+        depth_stencil.validate(name)?;
+        let format = depth_stencil.format;
+        if !self
+            .physical_device_info()
+            .format_support(format)
+            .optimal_tiling
+            .contains(FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+        {
+            return Err(Error::InvalidUsage(format!(
+                "Raster pipeline {} has a {:?} depth attachment, which the device doesn't support",
+                name, format
+            )));
         }
+        Ok(())
     }
 
-    pub fn create_compute_pipeline(&self, info: ComputePipelineInfo) -> ComputePipeline {
+    /// Compiles the built-in compute reducer `CommandRecorder::generate_mipmaps_compute` runs
+    /// for storage images of `format`.
+    pub fn create_mip_reduce_pipeline(
+        &self,
+        format: Format,
+        reduce: MipReduce,
+    ) -> Result<ComputePipeline> {
+        mipmap::create_reduce_pipeline(self, format, reduce)
+    }
+
+    pub fn create_compute_pipeline(&self, info: ComputePipelineInfo) -> Result<ComputePipeline> {
         let shader_module_create_info =
             vk::ShaderModuleCreateInfo::default().code(info.shader.byte_code.as_slice());
 
         let shader_module = unsafe {
             self.handle()
                 .create_shader_module(&shader_module_create_info, None)
-        }
-        .unwrap();
+        }?;
 
         let push_constant_ranges = if info.push_constant_size > 0 {
             vec![vk::PushConstantRange::default()
@@ -573,11 +1223,17 @@ impl Device {
         let pipeline_layout = unsafe {
             self.handle()
                 .create_pipeline_layout(&pipeline_layout_create_info, None)
-        }
-        .unwrap();
+        };
+        let pipeline_layout = match pipeline_layout {
+            Ok(pipeline_layout) => pipeline_layout,
+            Err(error) => {
+                unsafe { self.handle().destroy_shader_module(shader_module, None) };
+                return Err(error.into());
+            }
+        };
 
-        let shader_entry_cstring = std::ffi::CString::new(info.shader.entry_point.as_str())
-            .expect("Failed to convert entry point to CString");
+        let shader_entry_cstring =
+            std::ffi::CString::new(info.shader.entry_point.as_str()).unwrap_or_default();
 
         let compute_pipeline_create_info = vk::ComputePipelineCreateInfo::default()
             .stage(
@@ -588,30 +1244,39 @@ impl Device {
             )
             .layout(pipeline_layout);
 
-        let pipeline = unsafe {
+        let pipelines = unsafe {
             self.handle().create_compute_pipelines(
-                vk::PipelineCache::null(),
+                self.pipeline_cache.handle,
                 &[compute_pipeline_create_info],
                 None,
             )
-        }
-        .unwrap()[0];
+        };
 
         unsafe {
             self.handle().destroy_shader_module(shader_module, None);
         }
 
-        ComputePipeline {
+        let pipeline = match pipelines {
+            Ok(pipelines) => pipelines[0],
+            Err((_, error)) => {
+                unsafe { self.handle().destroy_pipeline_layout(pipeline_layout, None) };
+                return Err(error.into());
+            }
+        };
+        self.inner.set_debug_name(pipeline, &info.name);
+        self.inner.set_debug_name(pipeline_layout, &info.name);
+
+        Ok(ComputePipeline {
             inner: PipelineInner {
                 device_dep: self.create_dep(),
                 pipeline,
                 pipeline_layout,
             },
-        }
+        })
     }
 
     pub fn cpu_frame_index(&self) -> u64 {
-        self.frame_index
+        self.frame_index.load(Ordering::Relaxed)
     }
 
     pub fn create_dep(&self) -> Arc<DeviceInner> {
@@ -630,86 +1295,76 @@ impl Device {
         &self.inner.device
     }
 
-    pub fn main_queue(&self) -> vk::Queue {
-        self.main_queue
+    pub fn physical_device_info(&self) -> &PhysicalDeviceInfo {
+        &self.inner.physical_device_info
     }
 
-    pub fn main_queue_family_index(&self) -> u32 {
-        self.inner.main_queue_family_index
+    pub fn is_headless(&self) -> bool {
+        self.inner.headless
     }
 
-    fn create_descriptor_pool(device_inner: &DeviceInner) -> vk::DescriptorPool {
-        let pool_sizes = [
-            vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::STORAGE_BUFFER,
-                descriptor_count: 100,
-            },
-            vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-                descriptor_count: 100,
-            },
-        ];
+    /// The features that were actually enabled, including the supported optional ones.
+    pub fn enabled_features(&self) -> DeviceFeatures {
+        self.inner.enabled_features
+    }
 
-        let create_info = vk::DescriptorPoolCreateInfo::default()
-            .pool_sizes(&pool_sizes)
-            .max_sets(100)
-            .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND);
+    pub fn is_feature_enabled(&self, features: DeviceFeatures) -> bool {
+        self.inner.enabled_features.contains(features)
+    }
 
-        unsafe {
-            device_inner
-                .device
-                .create_descriptor_pool(&create_info, None)
-                .expect("Failed to create descriptor pool")
-        }
-    }
-
-    fn create_bindless_descriptor_set_layout(
-        device_inner: &DeviceInner,
-    ) -> vk::DescriptorSetLayout {
-        let result = [
-            vk::DescriptorType::STORAGE_BUFFER,
-            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-        ]
-        .into_iter()
-        .enumerate()
-        .map(|(i, ty)| {
-            let binding = vk::DescriptorSetLayoutBinding::default()
-                .binding(i as u32)
-                .descriptor_type(ty)
-                .descriptor_count(1000)
-                .stage_flags(vk::ShaderStageFlags::ALL);
-
-            let flags = vk::DescriptorBindingFlags::PARTIALLY_BOUND
-                | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND;
-
-            (binding, flags)
-        })
-        .collect::<Vec<_>>();
+    pub fn enabled_extensions(&self) -> &[String] {
+        &self.inner.enabled_extensions
+    }
 
-        let bindings = result
+    pub fn is_extension_enabled(&self, name: &str) -> bool {
+        self.inner
+            .enabled_extensions
             .iter()
-            .map(|(binding, _)| binding.clone())
-            .collect::<Vec<_>>();
-        let binding_flags = result
-            .iter()
-            .map(|(_, flags)| flags.clone())
-            .collect::<Vec<_>>();
+            .any(|extension| extension == name)
+    }
 
-        let mut flags_create_info =
-            vk::DescriptorSetLayoutBindingFlagsCreateInfo::default().binding_flags(&binding_flags);
+    pub fn queue(&self, queue_type: QueueType) -> Queue {
+        self.queues[queue_type as usize]
+    }
 
-        let create_info = vk::DescriptorSetLayoutCreateInfo::default()
-            .bindings(&bindings)
-            .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
-            .push_next(&mut flags_create_info);
+    /// Whether the queue type has its own queue family, otherwise its work is submitted to the
+    /// graphics queue and won't overlap with it.
+    pub fn has_dedicated_queue(&self, queue_type: QueueType) -> bool {
+        queue_type == QueueType::Graphics
+            || self.inner.queue_families.family_index(queue_type)
+                != self.inner.queue_families.family_index(QueueType::Graphics)
+    }
+}
 
+impl DeviceInner {
+    /// Names the object in captures and validation messages, does nothing when debug utils
+    /// aren't enabled.
+    pub(crate) fn set_debug_name(&self, handle: impl vk::Handle, name: &str) {
+        let Some(debug_utils) = &self.debug_utils else {
+            return;
+        };
+        let Ok(c_name) = CString::new(name) else {
+            return;
+        };
+
+        let name_info = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(handle)
+            .object_name(&c_name);
         unsafe {
-            device_inner
-                .device
-                .create_descriptor_set_layout(&create_info, None)
-                .expect("Failed to create descriptor set layout")
+            let _ = debug_utils.set_debug_utils_object_name(&name_info);
         }
     }
+
+    /// The number of meaningful bits in timestamps written on the queue type, 0 if its family
+    /// doesn't support timestamps.
+    pub(crate) fn timestamp_valid_bits(&self, queue_type: QueueType) -> u32 {
+        let family_index = self.queue_families.family_index(queue_type);
+        self.physical_device_info
+            .queue_families
+            .iter()
+            .find(|family| family.index == family_index)
+            .map_or(0, |family| family.timestamp_valid_bits)
+    }
 }
 
 impl Drop for DeviceInner {
@@ -722,13 +1377,30 @@ impl Drop for DeviceInner {
 
 #[derive(Debug, Clone)]
 pub struct ImageInfo {
+    /// Shown in captures, validation messages and memory reports.
+    pub name: String,
     pub dimensions: u32,
     pub extent: Extent3D,
     pub format: Format,
     pub usage: ImageUsageFlags,
+    pub mip_levels: u32,
+    pub array_layers: u32,
+    /// Multisampled images can only be used as attachments, blit sources and resolve sources.
+    pub samples: SampleCount,
+    /// Allows `ImageViewType::Cube` views, needs a square 2D image with at least six layers.
+    pub cube_compatible: bool,
+    /// The view the image is registered in the bindless tables with, covering every mip level
+    /// and layer. Defaults to the one matching `dimensions`, or `Type2DArray` for 2D images
+    /// with several layers.
+    pub view_type: Option<ImageViewType>,
 }
 
 impl ImageInfo {
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
     pub fn dimensions(mut self, dimensions: u32) -> Self {
         self.dimensions = dimensions;
         self
@@ -748,34 +1420,182 @@ impl ImageInfo {
         self.usage = usage;
         self
     }
+
+    pub fn mip_levels(mut self, mip_levels: u32) -> Self {
+        self.mip_levels = mip_levels;
+        self
+    }
+
+    /// The number of mip levels of a full mip chain down to a single texel.
+    pub fn full_mip_chain(mut self) -> Self {
+        let largest = self
+            .extent
+            .width
+            .max(self.extent.height)
+            .max(self.extent.depth)
+            .max(1);
+        self.mip_levels = u32::BITS - largest.leading_zeros();
+        self
+    }
+
+    pub fn array_layers(mut self, array_layers: u32) -> Self {
+        self.array_layers = array_layers;
+        self
+    }
+
+    pub fn samples(mut self, samples: SampleCount) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn cube_compatible(mut self, cube_compatible: bool) -> Self {
+        self.cube_compatible = cube_compatible;
+        self
+    }
+
+    pub fn view_type(mut self, view_type: ImageViewType) -> Self {
+        self.view_type = Some(view_type);
+        self
+    }
+
+    /// The view type the image is created with, `None` for invalid dimensions.
+    pub fn resolved_view_type(&self) -> Option<ImageViewType> {
+        if self.view_type.is_none() && self.dimensions == 2 && self.array_layers > 1 {
+            return Some(ImageViewType::Type2DArray);
+        }
+        self.view_type
+            .or_else(|| ImageViewType::from_dimensions(self.dimensions))
+    }
+
+    /// The extent of a mip level.
+    pub fn mip_extent(&self, mip_level: u32) -> Extent3D {
+        Extent3D::new(
+            (self.extent.width >> mip_level).max(1),
+            (self.extent.height >> mip_level).max(1),
+            (self.extent.depth >> mip_level).max(1),
+        )
+    }
 }
 
 impl Default for ImageInfo {
     fn default() -> Self {
         ImageInfo {
+            name: "image".to_owned(),
             dimensions: 2,
             extent: Extent3D::new(0, 0, 0),
             format: Format::R8G8B8A8Unorm,
             usage: ImageUsageFlags::empty(),
+            mip_levels: 1,
+            array_layers: 1,
+            samples: SampleCount::Type1,
+            cube_compatible: false,
+            view_type: None,
         }
     }
 }
 
+/// A view of part of an image, it gets an id of its own in the bindless tables. Barriers and
+/// copies still go through the image it views.
+#[derive(Debug, Clone)]
+pub struct ImageViewInfo {
+    pub name: String,
+    pub image: ImageId,
+    /// Defaults to the view type of the image.
+    pub view_type: Option<ImageViewType>,
+    pub subresource_range: ImageSubresourceRange,
+}
+
 pub struct Image {
     pub handle: vk::Image,
+    /// Covers `subresource_range`.
     pub view: Option<vk::ImageView>,
+    /// A view of the first mip level in `subresource_range`, registered as the storage image
+    /// when `view` covers more than one level.
+    pub storage_view: Option<vk::ImageView>,
+    /// A depth only view registered as the sampled image of formats with depth and stencil,
+    /// sampled views can only cover a single aspect.
+    pub sampled_view: Option<vk::ImageView>,
     pub info: ImageInfo,
+    pub subresource_range: ImageSubresourceRange,
     pub allocation: Option<Allocation>,
     pub is_swapchain_image: bool,
+    /// The image a view created with `Device::create_image_view` views, views don't own the
+    /// image handle.
+    pub view_of: Option<ImageId>,
+    /// Storage views of every mip level after the first, see `Device::image_mip_view`.
+    pub mip_views: Vec<ImageId>,
 }
 
 pub struct SubmitInfo<'a> {
+    pub queue: QueueType,
     pub commands: Vec<CommandList>,
-    pub wait_semaphores: Vec<&'a BinarySemaphore>,
+    /// Semaphores with the stages that wait on them, e.g. `COLOR_ATTACHMENT_OUTPUT` for a
+    /// swapchain acquire.
+    pub wait_semaphores: Vec<(&'a BinarySemaphore, PipelineStageFlags)>,
+    /// Timeline semaphores with the value to wait for. Waiting on another queue's submission
+    /// uses `Device::queue_timeline` and `Submission::value`.
+    pub wait_timeline_semaphores: Vec<(&'a TimelineSemaphore, u64, PipelineStageFlags)>,
     pub signal_semaphores: Vec<&'a BinarySemaphore>,
     pub signal_timeline_semaphores: Vec<(&'a TimelineSemaphore, u64)>,
 }
 
+/// The vulkan handles of a `SubmitInfo`, kept alive until the submit call.
+struct VkSubmitBatch {
+    wait_semaphores: Vec<vk::Semaphore>,
+    wait_values: Vec<u64>,
+    wait_stages: Vec<vk::PipelineStageFlags>,
+    command_buffers: Vec<vk::CommandBuffer>,
+    signal_semaphores: Vec<vk::Semaphore>,
+    signal_values: Vec<u64>,
+}
+
+impl VkSubmitBatch {
+    fn new(info: &SubmitInfo, extra_signal: Option<(vk::Semaphore, u64)>) -> Self {
+        // Binary semaphores ignore their value but need one when timeline values are passed.
+        let mut wait_semaphores = Vec::new();
+        let mut wait_values = Vec::new();
+        let mut wait_stages = Vec::new();
+        for (semaphore, stages) in &info.wait_semaphores {
+            wait_semaphores.push(semaphore.handle());
+            wait_values.push(0);
+            wait_stages.push((*stages).into());
+        }
+        for (semaphore, value, stages) in &info.wait_timeline_semaphores {
+            wait_semaphores.push(semaphore.handle());
+            wait_values.push(*value);
+            wait_stages.push((*stages).into());
+        }
+
+        let (signal_semaphores, signal_values) = info
+            .signal_semaphores
+            .iter()
+            .map(|semaphore| (semaphore.handle(), 0))
+            .chain(
+                info.signal_timeline_semaphores
+                    .iter()
+                    .map(|(semaphore, value)| (semaphore.handle(), *value)),
+            )
+            .chain(extra_signal)
+            .unzip();
+
+        VkSubmitBatch {
+            wait_semaphores,
+            wait_values,
+            wait_stages,
+            command_buffers: info.commands.iter().map(|list| list.handle()).collect(),
+            signal_semaphores,
+            signal_values,
+        }
+    }
+}
+
+/// Identifies a submission on its queue's timeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Submission {
+    pub queue: QueueType,
+    pub value: u64,
+}
+
 pub struct PresentInfo<'a> {
     pub swapchain: &'a Swapchain,
     pub wait_semaphores: Vec<&'a BinarySemaphore>,
@@ -801,20 +1621,19 @@ impl<'a, T> MappedPtr<'a, T> {
         memory: vk::DeviceMemory,
         offset: u64,
         size: u64,
-    ) -> Self {
+    ) -> Result<Self> {
         let ptr = unsafe {
             device
                 .handle()
                 .map_memory(memory, offset, size, vk::MemoryMapFlags::empty())
-        }
-        .expect("Failed to map memory") as *mut T;
+        }? as *mut T;
 
-        MappedPtr {
+        Ok(MappedPtr {
             ptr,
             did_unmap: false,
             memory,
             device,
-        }
+        })
     }
 
     pub fn unmap(&mut self) {
@@ -835,3 +1654,31 @@ impl<T> Drop for MappedPtr<'_, T> {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_mip_chain_ends_at_a_single_texel() {
+        let info = ImageInfo::default()
+            .extent(Extent3D::new(256, 64, 1))
+            .full_mip_chain();
+        assert_eq!(info.mip_levels, 9);
+        assert_eq!(info.mip_extent(info.mip_levels - 1), Extent3D::new(1, 1, 1));
+
+        let info = ImageInfo::default()
+            .extent(Extent3D::new(5, 3, 1))
+            .full_mip_chain();
+        assert_eq!(info.mip_levels, 3);
+    }
+
+    #[test]
+    fn mip_extents_halve_and_clamp_to_one() {
+        let info = ImageInfo::default().extent(Extent3D::new(100, 7, 1));
+        assert_eq!(info.mip_extent(0), Extent3D::new(100, 7, 1));
+        assert_eq!(info.mip_extent(1), Extent3D::new(50, 3, 1));
+        assert_eq!(info.mip_extent(3), Extent3D::new(12, 1, 1));
+        assert_eq!(info.mip_extent(7), Extent3D::new(1, 1, 1));
+    }
+}
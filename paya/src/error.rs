@@ -0,0 +1,104 @@
+use std::fmt;
+
+use ash::vk;
+
+use crate::shader::CompilationError;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    OutOfHostMemory,
+    OutOfDeviceMemory,
+    DeviceLost,
+    SurfaceLost,
+    /// The swapchain no longer matches its surface and has to be resized.
+    OutOfDate,
+    /// The id refers to a resource that has been destroyed or whose slot was reused.
+    StaleHandle,
+    MissingFeature(String),
+    MissingExtension(String),
+    NoSuitableDevice(String),
+    /// The vulkan library could not be loaded.
+    Loading(String),
+    WindowHandle(String),
+    /// The api was used in a way that is not supported, e.g. mapping a gpu only buffer.
+    InvalidUsage(String),
+    /// Every slot of a bindless table is in use, see `DeviceCreateInfo::bindless_capacities`.
+    BindlessTableFull(String),
+    Allocation(String),
+    Shader(CompilationError),
+    Io(std::io::Error),
+    /// Any other vulkan error that doesn't have a dedicated variant.
+    Vulkan(vk::Result),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::OutOfHostMemory => write!(f, "out of host memory"),
+            Error::OutOfDeviceMemory => write!(f, "out of device memory"),
+            Error::DeviceLost => write!(f, "device lost"),
+            Error::SurfaceLost => write!(f, "surface lost"),
+            Error::OutOfDate => write!(f, "swapchain is out of date"),
+            Error::StaleHandle => write!(f, "resource handle is stale"),
+            Error::MissingFeature(feature) => write!(f, "missing device feature {}", feature),
+            Error::MissingExtension(extension) => write!(f, "missing extension {}", extension),
+            Error::NoSuitableDevice(reason) => write!(f, "no suitable device: {}", reason),
+            Error::Loading(message) => write!(f, "failed to load vulkan: {}", message),
+            Error::WindowHandle(message) => write!(f, "invalid window handle: {}", message),
+            Error::InvalidUsage(message) => write!(f, "invalid usage: {}", message),
+            Error::BindlessTableFull(message) => write!(f, "bindless table is full: {}", message),
+            Error::Allocation(message) => write!(f, "allocation failed: {}", message),
+            Error::Shader(error) => write!(f, "shader error: {:?}", error),
+            Error::Io(error) => write!(f, "io error: {}", error),
+            Error::Vulkan(result) => write!(f, "vulkan error: {}", result),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<vk::Result> for Error {
+    fn from(result: vk::Result) -> Self {
+        match result {
+            vk::Result::ERROR_OUT_OF_HOST_MEMORY => Error::OutOfHostMemory,
+            vk::Result::ERROR_OUT_OF_DEVICE_MEMORY => Error::OutOfDeviceMemory,
+            vk::Result::ERROR_DEVICE_LOST => Error::DeviceLost,
+            vk::Result::ERROR_SURFACE_LOST_KHR => Error::SurfaceLost,
+            vk::Result::ERROR_OUT_OF_DATE_KHR => Error::OutOfDate,
+            vk::Result::ERROR_EXTENSION_NOT_PRESENT => {
+                Error::MissingExtension("unknown".to_owned())
+            }
+            vk::Result::ERROR_FEATURE_NOT_PRESENT => Error::MissingFeature("unknown".to_owned()),
+            result => Error::Vulkan(result),
+        }
+    }
+}
+
+impl From<gpu_allocator::AllocationError> for Error {
+    fn from(error: gpu_allocator::AllocationError) -> Self {
+        match error {
+            gpu_allocator::AllocationError::OutOfMemory => Error::OutOfDeviceMemory,
+            error => Error::Allocation(error.to_string()),
+        }
+    }
+}
+
+impl From<CompilationError> for Error {
+    fn from(error: CompilationError) -> Self {
+        Error::Shader(error)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<raw_window_handle::HandleError> for Error {
+    fn from(error: raw_window_handle::HandleError) -> Self {
+        Error::WindowHandle(error.to_string())
+    }
+}
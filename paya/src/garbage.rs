@@ -0,0 +1,91 @@
+use crate::{
+    command_recorder::CommandRecorderId,
+    gpu_resources::{BufferId, ImageId, SamplerId},
+    pipeline::Pipeline,
+    queue::QueueType,
+};
+
+pub(crate) enum Garbage {
+    Buffer(BufferId),
+    Image(ImageId),
+    Sampler(SamplerId),
+    Recorder(CommandRecorderId),
+    // Dropping the pipeline destroys it.
+    Pipeline(Box<dyn Pipeline>),
+}
+
+/// Resources waiting for the gpu to pass the timeline values of the submissions that could
+/// still be using them.
+#[derive(Default)]
+pub(crate) struct GarbageQueue {
+    // Each entry holds the queue timeline values, indexed by `QueueType`, that have to be
+    // reached before the resource can be destroyed.
+    entries: Vec<([u64; 3], Garbage)>,
+}
+
+impl GarbageQueue {
+    pub(crate) fn push(&mut self, timeline_values: [u64; 3], garbage: Garbage) {
+        self.entries.push((timeline_values, garbage));
+    }
+
+    /// Queues garbage that only waits on a single submission.
+    pub(crate) fn push_for_submission(&mut self, queue: QueueType, value: u64, garbage: Garbage) {
+        let mut timeline_values = [0; 3];
+        timeline_values[queue as usize] = value;
+        self.push(timeline_values, garbage);
+    }
+
+    /// Removes and returns the garbage whose submissions have all completed, each entry is
+    /// only ever returned once.
+    pub(crate) fn take_completed(&mut self, completed_values: [u64; 3]) -> Vec<Garbage> {
+        let (completed, pending) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(timeline_values, _)| {
+                timeline_values
+                    .iter()
+                    .zip(completed_values)
+                    .all(|(value, completed)| *value <= completed)
+            });
+        self.entries = pending;
+
+        completed.into_iter().map(|(_, garbage)| garbage).collect()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu_resources::GpuResourceId;
+
+    fn buffer(index: u32) -> Garbage {
+        Garbage::Buffer(BufferId(GpuResourceId { index, version: 0 }))
+    }
+
+    #[test]
+    fn takes_garbage_once_every_queue_has_passed() {
+        let mut queue = GarbageQueue::default();
+        queue.push([1, 2, 0], buffer(0));
+        queue.push_for_submission(QueueType::Graphics, 3, buffer(1));
+
+        assert!(queue.take_completed([1, 1, 0]).is_empty());
+        assert_eq!(queue.len(), 2);
+
+        let completed = queue.take_completed([2, 2, 0]);
+        assert!(matches!(completed[..], [Garbage::Buffer(BufferId(id))] if id.index == 0));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn takes_garbage_only_once() {
+        let mut queue = GarbageQueue::default();
+        queue.push_for_submission(QueueType::Graphics, 1, buffer(0));
+
+        assert_eq!(queue.take_completed([5, 5, 5]).len(), 1);
+        assert!(queue.take_completed([5, 5, 5]).is_empty());
+        assert_eq!(queue.len(), 0);
+    }
+}
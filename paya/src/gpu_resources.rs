@@ -1,20 +1,31 @@
-use std::{ffi::CString, sync::Arc, time::Instant};
+use std::{
+    ops::Deref,
+    sync::{Arc, Mutex, RwLock, RwLockReadGuard},
+    time::Instant,
+};
 
 use ash::vk::{self};
 
 use crate::{
-    allocator::{Allocation, GpuAllocator, MemoryFlags, MemoryLocation, MemoryType},
-    common::{BufferUsageFlags, ImageUsageFlags},
-    device::{DeviceInner, Image, ImageInfo},
+    allocator::{
+        Allocation, AllocationReport, GpuAllocator, MemoryFlags, MemoryLocation, MemoryType,
+    },
+    common::{
+        BorderColor, BufferUsageFlags, CompareOp, Filter, ImageSubresourceRange, ImageUsageFlags,
+        ImageViewType, SampleCount, SamplerAddressMode, SamplerMipmapMode,
+    },
+    debug::report_error,
+    device::{DeviceInner, Image, ImageInfo, ImageViewInfo},
+    error::{Error, Result},
+    physical_device::{DeviceFeatures, DeviceLimits, FormatFeatureFlags},
 };
 
-pub const MAX_BUFFERS: u64 = 1000;
-pub const MAX_IMAGES: u64 = 1000;
-
 pub const BUFFER_ADDRESSES_BINDING: u32 = 0;
 pub const STORAGE_IMAGE_BINDING: u32 = 1;
+pub const SAMPLED_IMAGE_BINDING: u32 = 2;
+pub const SAMPLER_BINDING: u32 = 3;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct ImageId(pub(crate) GpuResourceId);
 
 impl ImageId {
@@ -23,7 +34,7 @@ impl ImageId {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct BufferId(pub(crate) GpuResourceId);
 
 impl BufferId {
@@ -32,10 +43,19 @@ impl BufferId {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerId(pub(crate) GpuResourceId);
+
+impl SamplerId {
+    pub fn pack(&self) -> PackedGpuResourceId {
+        PackedGpuResourceId::new(self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct GpuResourceId {
-    index: u32,
-    version: u16,
+    pub(crate) index: u32,
+    pub(crate) version: u16,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -51,6 +71,8 @@ pub enum GpuResourceType {
     Undefined = 0,
     StorageImage = 1,
     Buffer = 2,
+    SampledImage = 3,
+    Sampler = 4,
 }
 
 pub enum ResourceEntry<T> {
@@ -66,30 +88,37 @@ pub struct ResourceVersionEntry<T> {
 pub struct ResourceSlot<T> {
     entries: Vec<ResourceVersionEntry<T>>,
     free_head: usize,
+    // The number of entries the bindless table has room for.
+    capacity: usize,
 }
 
 impl<T> ResourceSlot<T> {
-    fn new() -> Self {
+    fn new(capacity: u32) -> Self {
         Self {
             entries: vec![ResourceVersionEntry {
                 entry: ResourceEntry::Free(usize::MAX),
                 version: 0,
             }],
             free_head: 0,
+            capacity: capacity as usize,
         }
     }
 
-    fn insert_resource(&mut self, resource: T) -> GpuResourceId {
+    /// Hands the resource back when every entry is in use.
+    fn insert_resource(&mut self, resource: T) -> std::result::Result<GpuResourceId, T> {
         if self.free_head == usize::MAX {
+            if self.entries.len() >= self.capacity {
+                return Err(resource);
+            }
             self.entries.push(ResourceVersionEntry {
                 entry: ResourceEntry::Occupied(resource),
                 version: 0,
             });
 
-            return GpuResourceId {
+            return Ok(GpuResourceId {
                 index: self.entries.len() as u32 - 1,
                 version: 0,
-            };
+            });
         }
 
         let free_entry = &mut self.entries[self.free_head];
@@ -101,39 +130,59 @@ impl<T> ResourceSlot<T> {
         let index = self.free_head as u32;
         self.free_head = next_free as usize;
 
-        GpuResourceId {
+        Ok(GpuResourceId {
             index,
             version: free_entry.version,
+        })
+    }
+
+    fn get_resource_mut(&mut self, id: GpuResourceId) -> Result<&mut T> {
+        let Some(versioned_entry) = self.entries.get_mut(id.index as usize) else {
+            return Err(Error::StaleHandle);
+        };
+
+        if versioned_entry.version != id.version {
+            return Err(Error::StaleHandle);
         }
+
+        let ResourceEntry::Occupied(resource) = &mut versioned_entry.entry else {
+            return Err(Error::StaleHandle);
+        };
+
+        Ok(resource)
     }
 
-    fn get_resource(&self, id: GpuResourceId) -> &T {
+    fn get_resource(&self, id: GpuResourceId) -> Result<&T> {
         let Some(versioned_entry) = self.entries.get(id.index as usize) else {
-            panic!("Could not get resource by id")
+            return Err(Error::StaleHandle);
         };
 
         if versioned_entry.version != id.version {
-            panic!("Version does not match")
+            return Err(Error::StaleHandle);
         }
 
         let ResourceEntry::Occupied(resource) = &versioned_entry.entry else {
-            panic!("Resource does not exist")
+            return Err(Error::StaleHandle);
         };
 
-        return resource;
+        Ok(resource)
     }
 
-    fn remove_resource(&mut self, id: GpuResourceId) -> T {
+    fn remove_resource(&mut self, id: GpuResourceId) -> Result<T> {
         let Some(versioned_entry) = self.entries.get_mut(id.index as usize) else {
-            panic!("Could not get resource by id")
+            return Err(Error::StaleHandle);
         };
 
         if versioned_entry.version != id.version {
-            panic!("Version does not match")
+            return Err(Error::StaleHandle);
+        }
+
+        if let ResourceEntry::Free(_) = versioned_entry.entry {
+            return Err(Error::StaleHandle);
         }
 
         match std::mem::replace(&mut versioned_entry.entry, ResourceEntry::Free(usize::MAX)) {
-            ResourceEntry::Free(_) => panic!(""),
+            ResourceEntry::Free(_) => unreachable!(),
             ResourceEntry::Occupied(resource) => {
                 if self.free_head > id.index as usize {
                     self.entries[id.index as usize].entry = ResourceEntry::Free(self.free_head);
@@ -155,7 +204,7 @@ impl<T> ResourceSlot<T> {
                     }
                 }
 
-                resource
+                Ok(resource)
             }
         }
     }
@@ -171,29 +220,129 @@ impl<T> ResourceSlot<T> {
     }
 }
 
-/// This will hold all the resources that we will use in the renderer.
+/// Read access to a resource in the pool. Resources of the same type can't be created or
+/// destroyed while it is held, so don't keep it around across other calls into the device.
+pub struct ResourceRef<'a, T> {
+    slot: RwLockReadGuard<'a, ResourceSlot<T>>,
+    index: usize,
+}
+
+impl<T> Deref for ResourceRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match &self.slot.entries[self.index].entry {
+            ResourceEntry::Occupied(resource) => resource,
+            ResourceEntry::Free(_) => unreachable!("the entry was checked when locking"),
+        }
+    }
+}
+
+impl<'a, T> ResourceRef<'a, T> {
+    fn new(slot: &'a RwLock<ResourceSlot<T>>, id: GpuResourceId) -> Result<Self> {
+        let slot = slot.read().unwrap();
+        slot.get_resource(id)?;
+
+        Ok(ResourceRef {
+            slot,
+            index: id.index as usize,
+        })
+    }
+}
+
+/// The number of buffers, images and samplers the bindless tables have room for. Image
+/// capacity applies to the storage and sampled image tables alike.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BindlessCapacities {
+    pub buffers: u32,
+    pub images: u32,
+    pub samplers: u32,
+}
+
+impl BindlessCapacities {
+    /// Clamps the capacities to what the device supports in a single update after bind
+    /// descriptor set that is visible to every shader stage. Capacities of 0 are raised to 1,
+    /// fails when the device can't hold a single resource of a kind.
+    pub fn clamped(self, limits: &DeviceLimits) -> Result<Self> {
+        let clamp = |kind: &str, requested: u32, max: u32| {
+            if max == 0 {
+                return Err(Error::NoSuitableDevice(format!(
+                    "the device's limits leave no room for a single bindless {}",
+                    kind
+                )));
+            }
+            Ok(requested.clamp(1, max))
+        };
+
+        // Buffer addresses are read from a single storage buffer.
+        let max_buffers = limits.max_storage_buffer_range / std::mem::size_of::<u64>() as u32;
+        let buffers = clamp("buffer", self.buffers, max_buffers)?;
+
+        // Each image takes a storage and a sampled image descriptor, the buffer addresses
+        // buffer takes one more.
+        let resources = limits.max_per_stage_update_after_bind_resources.max(1) - 1;
+        let max_samplers = (resources / 3)
+            .min(limits.max_descriptor_set_update_after_bind_samplers)
+            .min(limits.max_per_stage_descriptor_update_after_bind_samplers)
+            .min(limits.max_sampler_allocation_count);
+        let samplers = clamp("sampler", self.samplers, max_samplers)?;
+        let max_images = ((resources - samplers) / 2)
+            .min(limits.max_descriptor_set_update_after_bind_storage_images)
+            .min(limits.max_descriptor_set_update_after_bind_sampled_images)
+            .min(limits.max_per_stage_descriptor_update_after_bind_storage_images)
+            .min(limits.max_per_stage_descriptor_update_after_bind_sampled_images);
+        let images = clamp("image", self.images, max_images)?;
+
+        Ok(BindlessCapacities {
+            buffers,
+            images,
+            samplers,
+        })
+    }
+}
+
+impl Default for BindlessCapacities {
+    fn default() -> Self {
+        BindlessCapacities {
+            buffers: 1000,
+            images: 1000,
+            samplers: 1000,
+        }
+    }
+}
+
+/// This will hold all the resources that we will use in the renderer. Every method takes
+/// `&self` so resources can be created and destroyed from any thread, each resource type and
+/// the allocator are locked separately.
 pub struct GpuResourcePool {
     device_dep: Arc<DeviceInner>,
-    allocator: GpuAllocator,
+    allocator: Mutex<GpuAllocator>,
     descriptor_pool: vk::DescriptorPool,
 
     pub(crate) bindless_descriptor_set_layout: vk::DescriptorSetLayout,
     pub(crate) descriptor_set: vk::DescriptorSet,
+    capacities: BindlessCapacities,
     buffer_addresses_buffer: Option<Buffer>,
     buffer_addresses_buffer_ptr: BufferAddressPtr,
 
-    images: ResourceSlot<Image>,
-    buffers: ResourceSlot<Buffer>,
+    // The images lock also guards writes to the descriptor set.
+    images: RwLock<ResourceSlot<Image>>,
+    buffers: RwLock<ResourceSlot<Buffer>>,
+    samplers: RwLock<ResourceSlot<Sampler>>,
 }
 
 impl GpuResourcePool {
-    pub fn new(device_dep: Arc<DeviceInner>) -> Self {
+    pub fn new(device_dep: Arc<DeviceInner>, capacities: BindlessCapacities) -> Result<Self> {
         let device_inner = &device_dep;
+        let capacities = capacities.clamped(&device_inner.physical_device_info.limits)?;
 
-        let descriptor_pool = Self::create_descriptor_pool(device_inner);
+        let descriptor_pool = Self::create_descriptor_pool(device_inner, &capacities)?;
 
-        let descriptor_set_layout =
-            Self::create_bindless_descriptor_set_layout(device_inner, vk::ShaderStageFlags::ALL);
+        let descriptor_set_layout = Self::create_bindless_descriptor_set_layout(
+            device_inner,
+            &capacities,
+            vk::ShaderStageFlags::ALL,
+        )?;
 
         let descriptor_set = unsafe {
             device_inner.device.allocate_descriptor_sets(
@@ -201,15 +350,18 @@ impl GpuResourcePool {
                     .descriptor_pool(descriptor_pool)
                     .set_layouts(&[descriptor_set_layout]),
             )
-        }
-        .unwrap()[0];
+        }?[0];
 
-        let mut allocator = GpuAllocator::new(device_dep.clone());
+        device_dep.set_debug_name(descriptor_pool, "paya bindless descriptor pool");
+        device_dep.set_debug_name(descriptor_set_layout, "paya bindless set layout");
+        device_dep.set_debug_name(descriptor_set, "paya bindless set");
+
+        let mut allocator = GpuAllocator::new(device_dep.clone())?;
 
         let buffer_addresses_buffer = {
             let info = BufferInfo {
                 name: "paya_buffer_addresses_buffer".to_owned(),
-                size: MAX_BUFFERS * std::mem::size_of::<u64>() as u64,
+                size: capacities.buffers as u64 * std::mem::size_of::<u64>() as u64,
                 memory_location: MemoryLocation::CpuToGpu,
                 usage: BufferUsageFlags::STORAGE,
             };
@@ -218,8 +370,8 @@ impl GpuResourcePool {
                 .sharing_mode(vk::SharingMode::EXCLUSIVE)
                 .usage(info.usage.into());
 
-            let buffer = unsafe { device_dep.device.create_buffer(&create_info, None) }
-                .expect("Failed to make the buffer lol");
+            let buffer = unsafe { device_dep.device.create_buffer(&create_info, None) }?;
+            device_dep.set_debug_name(buffer, &info.name);
 
             let memory_requirements =
                 unsafe { device_dep.device.get_buffer_memory_requirements(buffer) };
@@ -230,7 +382,7 @@ impl GpuResourcePool {
                 info.memory_location,
                 MemoryType::DedicatedBuffer(buffer),
                 memory_requirements,
-            );
+            )?;
 
             unsafe {
                 device_dep.device.bind_buffer_memory(
@@ -238,8 +390,7 @@ impl GpuResourcePool {
                     allocation.memory(),
                     allocation.offset(),
                 )
-            }
-            .expect("failed to bind memory to buffer");
+            }?;
 
             Buffer {
                 allocation,
@@ -257,9 +408,7 @@ impl GpuResourcePool {
                 buffer_addresses_buffer.info.size,
                 vk::MemoryMapFlags::empty(),
             )
-        }
-        .expect("Failed to map buffer address ptr")
-            as *mut u64;
+        }? as *mut u64;
 
         let buffer_write_info = [vk::DescriptorBufferInfo::default()
             .buffer(buffer_addresses_buffer.handle)
@@ -276,32 +425,46 @@ impl GpuResourcePool {
             device_dep.device.update_descriptor_sets(&writes, &[]);
         }
 
-        GpuResourcePool {
+        Ok(GpuResourcePool {
             device_dep,
-            allocator,
+            allocator: Mutex::new(allocator),
             descriptor_pool,
             bindless_descriptor_set_layout: descriptor_set_layout,
             descriptor_set,
+            capacities,
             buffer_addresses_buffer: Some(buffer_addresses_buffer),
             buffer_addresses_buffer_ptr: BufferAddressPtr(buffer_addresses_buffer_ptr),
-            images: ResourceSlot::new(),
-            buffers: ResourceSlot::new(),
-        }
+            images: RwLock::new(ResourceSlot::new(capacities.images)),
+            buffers: RwLock::new(ResourceSlot::new(capacities.buffers)),
+            samplers: RwLock::new(ResourceSlot::new(capacities.samplers)),
+        })
+    }
+
+    /// The capacities after clamping them to the device's limits.
+    pub fn capacities(&self) -> BindlessCapacities {
+        self.capacities
     }
 
-    fn create_descriptor_pool(device_inner: &DeviceInner) -> vk::DescriptorPool {
+    fn create_descriptor_pool(
+        device_inner: &DeviceInner,
+        capacities: &BindlessCapacities,
+    ) -> Result<vk::DescriptorPool> {
         let pool_sizes = [
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::STORAGE_BUFFER,
                 descriptor_count: 1,
             },
             vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-                descriptor_count: 1000,
+                ty: vk::DescriptorType::STORAGE_IMAGE,
+                descriptor_count: capacities.images,
             },
             vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::STORAGE_IMAGE,
-                descriptor_count: 1000,
+                ty: vk::DescriptorType::SAMPLED_IMAGE,
+                descriptor_count: capacities.images,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLER,
+                descriptor_count: capacities.samplers,
             },
         ];
 
@@ -310,18 +473,18 @@ impl GpuResourcePool {
             .max_sets(1)
             .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND);
 
-        unsafe {
+        Ok(unsafe {
             device_inner
                 .device
                 .create_descriptor_pool(&create_info, None)
-                .expect("Failed to create descriptor pool")
-        }
+        }?)
     }
 
     fn create_bindless_descriptor_set_layout(
         device_inner: &DeviceInner,
+        capacities: &BindlessCapacities,
         stage_flags: vk::ShaderStageFlags,
-    ) -> vk::DescriptorSetLayout {
+    ) -> Result<vk::DescriptorSetLayout> {
         let bindings = vec![
             vk::DescriptorSetLayoutBinding::default()
                 .binding(BUFFER_ADDRESSES_BINDING)
@@ -331,7 +494,17 @@ impl GpuResourcePool {
             vk::DescriptorSetLayoutBinding::default()
                 .binding(STORAGE_IMAGE_BINDING)
                 .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
-                .descriptor_count(MAX_IMAGES as u32)
+                .descriptor_count(capacities.images)
+                .stage_flags(stage_flags),
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(SAMPLED_IMAGE_BINDING)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(capacities.images)
+                .stage_flags(stage_flags),
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(SAMPLER_BINDING)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .descriptor_count(capacities.samplers)
                 .stage_flags(stage_flags),
         ];
         let binding_flags = bindings
@@ -350,47 +523,71 @@ impl GpuResourcePool {
             .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
             .push_next(&mut flags_create_info);
 
-        unsafe {
+        Ok(unsafe {
             device_inner
                 .device
                 .create_descriptor_set_layout(&create_info, None)
-                .expect("Failed to create descriptor set layout")
-        }
-    }
-
-    pub fn create_image(&mut self, existing_image: Option<vk::Image>, info: &ImageInfo) -> ImageId {
-        let handle = existing_image.unwrap_or_else(|| {
-            let vk_create_info = vk::ImageCreateInfo::default()
-                .image_type(match info.dimensions {
-                    1 => vk::ImageType::TYPE_1D,
-                    2 => vk::ImageType::TYPE_2D,
-                    3 => vk::ImageType::TYPE_3D,
-                    _ => panic!("Invalid image dimensions, must be 1, 2, or 3"),
-                })
-                .format(info.format.into())
-                .extent(info.extent.into())
-                .mip_levels(1)
-                .array_layers(1)
-                .samples(vk::SampleCountFlags::TYPE_1)
-                .tiling(vk::ImageTiling::OPTIMAL)
-                .usage(info.usage.into())
-                .sharing_mode(vk::SharingMode::EXCLUSIVE)
-                .initial_layout(vk::ImageLayout::UNDEFINED);
+        }?)
+    }
 
-            unsafe { self.device_dep.device.create_image(&vk_create_info, None) }
-                .expect("Failed to create image")
-        });
+    pub fn create_image(
+        &self,
+        existing_image: Option<vk::Image>,
+        info: &ImageInfo,
+    ) -> Result<ImageId> {
+        let image_type = match info.dimensions {
+            1 => vk::ImageType::TYPE_1D,
+            2 => vk::ImageType::TYPE_2D,
+            3 => vk::ImageType::TYPE_3D,
+            _ => {
+                return Err(Error::InvalidUsage(
+                    "Invalid image dimensions, must be 1, 2, or 3".to_owned(),
+                ))
+            }
+        };
+        let view_type = info.resolved_view_type().unwrap();
+        // Swapchain images are created by the presentation engine, their format is whatever
+        // the surface supports.
+        if existing_image.is_none() {
+            self.validate_image_info(info, view_type)?;
+        }
+
+        let handle = match existing_image {
+            Some(image) => image,
+            None => {
+                let mut flags = vk::ImageCreateFlags::empty();
+                if info.cube_compatible {
+                    flags |= vk::ImageCreateFlags::CUBE_COMPATIBLE;
+                }
+                let vk_create_info = vk::ImageCreateInfo::default()
+                    .flags(flags)
+                    .image_type(image_type)
+                    .format(info.format.into())
+                    .extent(info.extent.into())
+                    .mip_levels(info.mip_levels)
+                    .array_layers(info.array_layers)
+                    .samples(info.samples.into())
+                    .tiling(vk::ImageTiling::OPTIMAL)
+                    .usage(info.usage.into())
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED);
+
+                unsafe { self.device_dep.device.create_image(&vk_create_info, None) }?
+            }
+        };
+
+        self.device_dep.set_debug_name(handle, &info.name);
 
         let allocation = if existing_image.is_none() {
             let memory_requirements =
                 unsafe { self.device_dep.device.get_image_memory_requirements(handle) };
-            let allocation = self.allocator.allocate_memory(
-                "image",
+            let allocation = self.allocator.lock().unwrap().allocate_memory(
+                info.name.clone(),
                 false,
                 MemoryLocation::GpuOnly,
                 MemoryType::DedicatedImage(handle),
                 memory_requirements,
-            );
+            )?;
 
             unsafe {
                 self.device_dep.device.bind_image_memory(
@@ -398,56 +595,173 @@ impl GpuResourcePool {
                     allocation.memory(),
                     allocation.offset(),
                 )
-            }
-            .expect("Failed to bind image memory");
+            }?;
 
             Some(allocation)
         } else {
             None
         };
 
-        let view = info.usage.needs_view().then(|| {
-            let vk_image_view_create_info = vk::ImageViewCreateInfo::default()
-                .image(handle)
-                .view_type(match info.dimensions {
-                    1 => vk::ImageViewType::TYPE_1D,
-                    2 => vk::ImageViewType::TYPE_2D,
-                    3 => vk::ImageViewType::TYPE_3D,
-                    _ => panic!("Invalid image dimensions, must be 1, 2, or 3"),
-                })
-                .format(info.format.into())
-                .components(vk::ComponentMapping::default())
-                .subresource_range(vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::COLOR,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1,
+        let id = self.register_image(
+            Image {
+                handle,
+                view: None,
+                storage_view: None,
+                sampled_view: None,
+                info: info.clone(),
+                subresource_range: ImageSubresourceRange::default()
+                    .resolve(info.mip_levels, info.array_layers),
+                allocation,
+                is_swapchain_image: existing_image.is_some(),
+                view_of: None,
+                mip_views: Vec::new(),
+            },
+            view_type,
+        )?;
+
+        // Storage image views can only access a single mip level, every level after the first
+        // gets a view of its own.
+        if info.usage.contains(ImageUsageFlags::STORAGE) && info.mip_levels > 1 {
+            let mut mip_views = Vec::new();
+            for mip_level in 1..info.mip_levels {
+                let view = self.create_image_view(&ImageViewInfo {
+                    name: format!("{} mip {}", info.name, mip_level),
+                    image: id,
+                    view_type: Some(view_type),
+                    subresource_range: ImageSubresourceRange::mip(mip_level),
                 });
+                match view {
+                    Ok(view) => mip_views.push(view),
+                    Err(error) => {
+                        for view in mip_views {
+                            self.destroy_image(view)?;
+                        }
+                        self.destroy_image(id)?;
+                        return Err(error);
+                    }
+                }
+            }
+            self.images
+                .write()
+                .unwrap()
+                .get_resource_mut(id.0)?
+                .mip_views = mip_views;
+        }
 
-            unsafe {
-                self.device_dep
-                    .device
-                    .create_image_view(&vk_image_view_create_info, None)
+        Ok(id)
+    }
+
+    pub fn create_image_view(&self, info: &ImageViewInfo) -> Result<ImageId> {
+        let (handle, image_info, image) = {
+            let image = self.get_image(info.image)?;
+            (
+                image.handle,
+                image.info.clone(),
+                image.view_of.unwrap_or(info.image),
+            )
+        };
+        if !image_info.usage.needs_view() {
+            return Err(Error::InvalidUsage(format!(
+                "Image {} has no usage that views can be created for",
+                image_info.name
+            )));
+        }
+
+        let view_type = info
+            .view_type
+            .or_else(|| image_info.resolved_view_type())
+            .unwrap();
+        let subresource_range = info
+            .subresource_range
+            .resolve(image_info.mip_levels, image_info.array_layers);
+        Self::validate_view(&image_info, view_type, info.subresource_range)?;
+
+        self.register_image(
+            Image {
+                handle,
+                view: None,
+                storage_view: None,
+                sampled_view: None,
+                info: ImageInfo {
+                    name: info.name.clone(),
+                    view_type: Some(view_type),
+                    ..image_info
+                },
+                subresource_range,
+                allocation: None,
+                is_swapchain_image: false,
+                view_of: Some(image),
+                mip_views: Vec::new(),
+            },
+            view_type,
+        )
+    }
+
+    /// Creates the views of the image and writes them to the bindless tables.
+    fn register_image(&self, mut image: Image, view_type: ImageViewType) -> Result<ImageId> {
+        let info = &image.info;
+        let range = image.subresource_range;
+        let storage =
+            info.usage.contains(ImageUsageFlags::STORAGE) && info.samples == SampleCount::Type1;
+        let sampled =
+            info.usage.contains(ImageUsageFlags::SAMPLED) && info.samples == SampleCount::Type1;
+        let aspect_mask = info.format.aspect_mask();
+        let depth_stencil = info.format.has_depth() && info.format.has_stencil();
+
+        if info.usage.needs_view() {
+            image.view = Some(self.create_vk_image_view(&image, view_type, range, aspect_mask)?);
+            if storage && range.level_count > 1 {
+                let base_level = ImageSubresourceRange {
+                    level_count: 1,
+                    ..range
+                };
+                match self.create_vk_image_view(&image, view_type, base_level, aspect_mask) {
+                    Ok(view) => image.storage_view = Some(view),
+                    Err(error) => {
+                        self.destroy_image_raw(image);
+                        return Err(error);
+                    }
+                }
             }
-            .expect("Failed to create image view")
-        });
+            if sampled && depth_stencil {
+                let depth_aspect = vk::ImageAspectFlags::DEPTH;
+                match self.create_vk_image_view(&image, view_type, range, depth_aspect) {
+                    Ok(view) => image.sampled_view = Some(view),
+                    Err(error) => {
+                        self.destroy_image_raw(image);
+                        return Err(error);
+                    }
+                }
+            }
+        }
 
-        let index = self.images.insert_resource(Image {
-            handle,
-            view,
-            info: info.clone(),
-            allocation,
-            is_swapchain_image: existing_image.is_some(),
-        });
+        let mut images = self.images.write().unwrap();
+        let index = match images.insert_resource(image) {
+            Ok(index) => index,
+            Err(image) => {
+                drop(images);
+                let name = image.info.name.clone();
+                self.destroy_image_raw(image);
+                return Err(Error::BindlessTableFull(format!(
+                    "image {} doesn't fit, all {} images are in use",
+                    name, self.capacities.images
+                )));
+            }
+        };
+        let image = images.get_resource(index)?;
 
-        if let Some(view) = view {
+        if let Some(view) = image.view {
             let write_image_info = [vk::DescriptorImageInfo::default()
                 .image_layout(vk::ImageLayout::GENERAL)
-                .image_view(view)
+                .image_view(image.storage_view.unwrap_or(view))
+                .sampler(vk::Sampler::null())];
+            // Sampled images are read in `SHADER_READ_ONLY_OPTIMAL`.
+            let sampled_image_info = [vk::DescriptorImageInfo::default()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(image.sampled_view.unwrap_or(view))
                 .sampler(vk::Sampler::null())];
             let mut writes = vec![];
-            if info.usage.contains(ImageUsageFlags::STORAGE) {
+            if storage {
                 writes.push(
                     vk::WriteDescriptorSet::default()
                         .dst_set(self.descriptor_set)
@@ -457,34 +771,277 @@ impl GpuResourcePool {
                         .image_info(&write_image_info),
                 );
             }
+            if sampled {
+                writes.push(
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(self.descriptor_set)
+                        .dst_binding(SAMPLED_IMAGE_BINDING)
+                        .dst_array_element(index.index)
+                        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                        .image_info(&sampled_image_info),
+                );
+            }
             unsafe { self.device_dep.device.update_descriptor_sets(&writes, &[]) };
         }
 
-        ImageId(index)
+        Ok(ImageId(index))
     }
 
-    pub fn get_image(&self, id: ImageId) -> &Image {
-        self.images.get_resource(id.0)
+    fn create_vk_image_view(
+        &self,
+        image: &Image,
+        view_type: ImageViewType,
+        range: ImageSubresourceRange,
+        aspect_mask: vk::ImageAspectFlags,
+    ) -> Result<vk::ImageView> {
+        let vk_image_view_create_info = vk::ImageViewCreateInfo::default()
+            .image(image.handle)
+            .view_type(view_type.into())
+            .format(image.info.format.into())
+            .components(vk::ComponentMapping::default())
+            .subresource_range(range.to_vk(aspect_mask));
+
+        let view = unsafe {
+            self.device_dep
+                .device
+                .create_image_view(&vk_image_view_create_info, None)
+        }?;
+        self.device_dep.set_debug_name(view, &image.info.name);
+
+        Ok(view)
     }
 
-    pub fn destroy_image(&mut self, id: ImageId) {
-        let image = self.images.remove_resource(id.0);
+    /// Checks that the image can be created and accessed the way its info describes.
+    fn validate_image_info(&self, info: &ImageInfo, view_type: ImageViewType) -> Result<()> {
+        let extent = info.extent;
+        let limits = &self.device_dep.physical_device_info.limits;
+        let (max_extent, unused_extent) = match info.dimensions {
+            1 => (
+                limits.max_image_dimension_1d,
+                extent.height.max(extent.depth),
+            ),
+            2 if info.cube_compatible => (limits.max_image_dimension_cube, extent.depth),
+            2 => (limits.max_image_dimension_2d, extent.depth),
+            _ => (limits.max_image_dimension_3d, 1),
+        };
+        if extent.width == 0 || extent.height == 0 || extent.depth == 0 || unused_extent != 1 {
+            return Err(Error::InvalidUsage(format!(
+                "Image {} has an extent of {:?}, {} dimensional images need a non zero extent \
+                 with the unused dimensions set to 1",
+                info.name, extent, info.dimensions
+            )));
+        }
+        if extent.width.max(extent.height).max(extent.depth) > max_extent {
+            return Err(Error::InvalidUsage(format!(
+                "Image {} has an extent of {:?}, the device supports at most {} texels per \
+                 dimension for {} dimensional images",
+                info.name, extent, max_extent, info.dimensions
+            )));
+        }
+
+        let largest = extent.width.max(extent.height).max(extent.depth);
+        let max_mip_levels = u32::BITS - largest.leading_zeros();
+        if info.mip_levels == 0 || info.mip_levels > max_mip_levels {
+            return Err(Error::InvalidUsage(format!(
+                "Image {} has {} mip levels, an extent of {:?} allows 1 to {}",
+                info.name, info.mip_levels, extent, max_mip_levels
+            )));
+        }
+        let max_array_layers = if info.dimensions == 3 {
+            1
+        } else {
+            limits.max_image_array_layers
+        };
+        if info.array_layers == 0 || info.array_layers > max_array_layers {
+            return Err(Error::InvalidUsage(format!(
+                "Image {} has {} array layers, {} dimensional images can have 1 to {}",
+                info.name, info.array_layers, info.dimensions, max_array_layers
+            )));
+        }
+        if info.cube_compatible
+            && (info.dimensions != 2 || extent.width != extent.height || info.array_layers < 6)
+        {
+            return Err(Error::InvalidUsage(format!(
+                "Image {} is cube compatible, which needs a square 2D image with at least six \
+                 array layers",
+                info.name
+            )));
+        }
+        if info.samples != SampleCount::Type1 {
+            let limits = &self.device_dep.physical_device_properties.limits;
+            let supported_samples = if info.format.has_depth() {
+                limits.framebuffer_depth_sample_counts
+            } else {
+                limits.framebuffer_color_sample_counts
+            };
+            if info.dimensions != 2 || info.mip_levels != 1 || info.cube_compatible {
+                return Err(Error::InvalidUsage(format!(
+                    "Image {} is multisampled, which needs a 2D image with a single mip level",
+                    info.name
+                )));
+            }
+            if info
+                .usage
+                .intersects(ImageUsageFlags::STORAGE | ImageUsageFlags::SAMPLED)
+            {
+                return Err(Error::InvalidUsage(format!(
+                    "Image {} is multisampled, which the bindless tables can't access",
+                    info.name
+                )));
+            }
+            if !supported_samples.contains(info.samples.into()) {
+                return Err(Error::InvalidUsage(format!(
+                    "Image {} uses {:?}, which the device doesn't support",
+                    info.name, info.samples
+                )));
+            }
+        }
+
+        Self::validate_view(info, view_type, ImageSubresourceRange::default())?;
+
+        let format_features = self
+            .device_dep
+            .physical_device_info
+            .format_support(info.format)
+            .optimal_tiling;
+        if info.usage.contains(ImageUsageFlags::STORAGE) {
+            if info.format.storage_image_qualifier().is_none() {
+                return Err(Error::InvalidUsage(format!(
+                    "Image {} has STORAGE usage but {:?} has no storage image accessor in the \
+                     preamble",
+                    info.name, info.format
+                )));
+            }
+            if !format_features.contains(FormatFeatureFlags::STORAGE_IMAGE) {
+                return Err(Error::InvalidUsage(format!(
+                    "Image {} has STORAGE usage but the device doesn't support {:?} storage images",
+                    info.name, info.format
+                )));
+            }
+        }
+        if info.usage.contains(ImageUsageFlags::SAMPLED)
+            && !format_features.contains(FormatFeatureFlags::SAMPLED_IMAGE)
+        {
+            return Err(Error::InvalidUsage(format!(
+                "Image {} has SAMPLED usage but the device doesn't support sampling {:?}",
+                info.name, info.format
+            )));
+        }
+        if info.usage.contains(ImageUsageFlags::COLOR_ATTACHMENT) && info.format.has_depth() {
+            return Err(Error::InvalidUsage(format!(
+                "Image {} has COLOR_ATTACHMENT usage but {:?} is a depth format",
+                info.name, info.format
+            )));
+        }
+        if info
+            .usage
+            .contains(ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
+        {
+            if !info.format.has_depth() {
+                return Err(Error::InvalidUsage(format!(
+                    "Image {} has DEPTH_STENCIL_ATTACHMENT usage but {:?} isn't a depth format",
+                    info.name, info.format
+                )));
+            }
+            if !format_features.contains(FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT) {
+                return Err(Error::InvalidUsage(format!(
+                    "Image {} has DEPTH_STENCIL_ATTACHMENT usage but the device doesn't support \
+                     {:?} depth attachments",
+                    info.name, info.format
+                )));
+            }
+        }
+        if info.format.has_depth() && info.dimensions == 3 {
+            return Err(Error::InvalidUsage(format!(
+                "Image {} is 3 dimensional, which {:?} depth images can't be",
+                info.name, info.format
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Checks that a view of `view_type` can be created for `range` of the image.
+    fn validate_view(
+        info: &ImageInfo,
+        view_type: ImageViewType,
+        range: ImageSubresourceRange,
+    ) -> Result<()> {
+        if view_type.dimensions() != info.dimensions {
+            return Err(Error::InvalidUsage(format!(
+                "Image {} has {} dimensions, which a {:?} view can't be created for",
+                info.name, info.dimensions, view_type
+            )));
+        }
+        if !range.fits(info.mip_levels, info.array_layers) {
+            return Err(Error::InvalidUsage(format!(
+                "{:?} is out of bounds of image {} ({} mip levels, {} array layers)",
+                range, info.name, info.mip_levels, info.array_layers
+            )));
+        }
+
+        let layer_count = range
+            .resolve(info.mip_levels, info.array_layers)
+            .layer_count;
+        let valid_layer_count = match view_type {
+            ImageViewType::Type1D | ImageViewType::Type2D | ImageViewType::Type3D => {
+                layer_count == 1
+            }
+            ImageViewType::Type2DArray => true,
+            ImageViewType::Cube => layer_count == 6 && info.cube_compatible,
+        };
+        if !valid_layer_count {
+            return Err(Error::InvalidUsage(format!(
+                "A {:?} view can't be created for {} array layers of image {}, array layers \
+                 need a Type2DArray view and cube views six layers of a cube compatible image",
+                view_type, layer_count, info.name
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn get_image(&self, id: ImageId) -> Result<ResourceRef<'_, Image>> {
+        ResourceRef::new(&self.images, id.0)
+    }
+
+    /// Destroys the image together with its mip views.
+    pub fn destroy_image(&self, id: ImageId) -> Result<()> {
+        let (image, mip_views) = {
+            let mut images = self.images.write().unwrap();
+            let image = images.remove_resource(id.0)?;
+            let mip_views = image
+                .mip_views
+                .iter()
+                .filter_map(|view| images.remove_resource(view.0).ok())
+                .collect::<Vec<_>>();
+            (image, mip_views)
+        };
+        for view in mip_views {
+            self.destroy_image_raw(view);
+        }
         self.destroy_image_raw(image);
+        Ok(())
     }
 
-    fn destroy_image_raw(&mut self, image: Image) {
-        if let Some(view) = image.view {
+    fn destroy_image_raw(&self, image: Image) {
+        for view in image
+            .view
+            .into_iter()
+            .chain(image.storage_view)
+            .chain(image.sampled_view)
+        {
             unsafe { self.device_dep.device.destroy_image_view(view, None) };
         }
-        if !image.is_swapchain_image {
+        if !image.is_swapchain_image && image.view_of.is_none() {
             unsafe { self.device_dep.device.destroy_image(image.handle, None) };
         }
         if let Some(allocation) = image.allocation {
-            self.allocator.deallocate_memory(allocation);
+            self.allocator.lock().unwrap().deallocate_memory(allocation);
         }
     }
 
-    pub fn create_buffer(&mut self, info: &BufferInfo) -> BufferId {
+    pub fn create_buffer(&self, info: &BufferInfo) -> Result<BufferId> {
         let buffer = {
             let vk_usage: vk::BufferUsageFlags = info.usage.into();
             let create_info = vk::BufferCreateInfo::default()
@@ -493,19 +1050,9 @@ impl GpuResourcePool {
                 .usage(vk_usage | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS);
 
             unsafe { self.device_dep.device.create_buffer(&create_info, None) }
-        }
-        .expect("Failed to make the buffer lol");
+        }?;
 
-        let c_string_name = CString::new(info.name.clone()).unwrap();
-        let name_info = vk::DebugUtilsObjectNameInfoEXT::default()
-            .object_handle(buffer)
-            .object_name(&c_string_name);
-        unsafe {
-            let _ = self
-                .device_dep
-                .debug_utils
-                .set_debug_utils_object_name(&name_info);
-        }
+        self.device_dep.set_debug_name(buffer, &info.name);
 
         let memory_requirements = unsafe {
             self.device_dep
@@ -513,13 +1060,13 @@ impl GpuResourcePool {
                 .get_buffer_memory_requirements(buffer)
         };
 
-        let allocation = self.allocator.allocate_memory(
+        let allocation = self.allocator.lock().unwrap().allocate_memory(
             info.name.clone(),
             true,
             info.memory_location,
             MemoryType::Managed,
             memory_requirements,
-        );
+        )?;
 
         unsafe {
             self.device_dep.device.bind_buffer_memory(
@@ -527,53 +1074,162 @@ impl GpuResourcePool {
                 allocation.memory(),
                 allocation.offset(),
             )
-        }
-        .expect("failed to bind memory to buffer");
+        }?;
 
-        let index = self.buffers.insert_resource(Buffer {
+        let buffer_address = unsafe {
+            self.device_dep
+                .device
+                .get_buffer_device_address(&vk::BufferDeviceAddressInfo::default().buffer(buffer))
+        };
+
+        let mut buffers = self.buffers.write().unwrap();
+        let index = match buffers.insert_resource(Buffer {
             info: info.clone(),
             handle: buffer,
             allocation,
             offset: 0,
             size: info.size,
-        });
-
-        let buffer_address = unsafe {
-            self.device_dep
-                .device
-                .get_buffer_device_address(&vk::BufferDeviceAddressInfo::default().buffer(buffer))
+        }) {
+            Ok(index) => index,
+            Err(buffer) => {
+                drop(buffers);
+                self.destroy_buffer_raw(buffer);
+                return Err(Error::BindlessTableFull(format!(
+                    "buffer {} doesn't fit, all {} buffers are in use",
+                    info.name, self.capacities.buffers
+                )));
+            }
         };
 
         self.buffer_addresses_buffer_ptr
             .write_buffer_address(index.index as usize, buffer_address);
 
-        BufferId(index)
+        Ok(BufferId(index))
     }
 
-    pub fn get_buffer(&self, id: BufferId) -> &Buffer {
-        self.buffers.get_resource(id.0)
+    pub fn get_buffer(&self, id: BufferId) -> Result<ResourceRef<'_, Buffer>> {
+        ResourceRef::new(&self.buffers, id.0)
     }
 
-    pub fn destroy_buffer(&mut self, id: BufferId) {
-        let buffer = self.buffers.remove_resource(id.0);
+    pub fn destroy_buffer(&self, id: BufferId) -> Result<()> {
+        let buffer = self.buffers.write().unwrap().remove_resource(id.0)?;
         self.destroy_buffer_raw(buffer);
+        Ok(())
+    }
+
+    pub fn create_sampler(&self, info: &SamplerInfo) -> Result<SamplerId> {
+        let mut create_info = vk::SamplerCreateInfo::default()
+            .mag_filter(info.mag_filter.into())
+            .min_filter(info.min_filter.into())
+            .mipmap_mode(info.mipmap_mode.into())
+            .address_mode_u(info.address_mode_u.into())
+            .address_mode_v(info.address_mode_v.into())
+            .address_mode_w(info.address_mode_w.into())
+            .mip_lod_bias(info.mip_lod_bias)
+            .min_lod(info.min_lod)
+            .max_lod(info.max_lod)
+            .border_color(info.border_color.into());
+
+        if let Some(max_anisotropy) = info.max_anisotropy {
+            if !self
+                .device_dep
+                .enabled_features
+                .contains(DeviceFeatures::SAMPLER_ANISOTROPY)
+            {
+                return Err(Error::InvalidUsage(format!(
+                    "Sampler {} uses anisotropic filtering without DeviceFeatures::SAMPLER_ANISOTROPY",
+                    info.name
+                )));
+            }
+            let limit = self
+                .device_dep
+                .physical_device_properties
+                .limits
+                .max_sampler_anisotropy;
+            create_info = create_info
+                .anisotropy_enable(true)
+                .max_anisotropy(max_anisotropy.clamp(1.0, limit));
+        }
+        if let Some(compare_op) = info.compare_op {
+            create_info = create_info
+                .compare_enable(true)
+                .compare_op(compare_op.into());
+        }
+
+        let handle = unsafe { self.device_dep.device.create_sampler(&create_info, None) }?;
+        self.device_dep.set_debug_name(handle, &info.name);
+
+        // Descriptor writes are guarded by the images lock.
+        let _images = self.images.write().unwrap();
+        let inserted = self.samplers.write().unwrap().insert_resource(Sampler {
+            handle,
+            info: info.clone(),
+        });
+        let index = match inserted {
+            Ok(index) => index,
+            Err(sampler) => {
+                unsafe { self.device_dep.device.destroy_sampler(sampler.handle, None) };
+                return Err(Error::BindlessTableFull(format!(
+                    "sampler {} doesn't fit, all {} samplers are in use",
+                    info.name, self.capacities.samplers
+                )));
+            }
+        };
+
+        let write_sampler_info = [vk::DescriptorImageInfo::default().sampler(handle)];
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(self.descriptor_set)
+            .dst_binding(SAMPLER_BINDING)
+            .dst_array_element(index.index)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .image_info(&write_sampler_info);
+        unsafe { self.device_dep.device.update_descriptor_sets(&[write], &[]) };
+
+        Ok(SamplerId(index))
+    }
+
+    pub fn get_sampler(&self, id: SamplerId) -> Result<ResourceRef<'_, Sampler>> {
+        ResourceRef::new(&self.samplers, id.0)
     }
 
-    fn destroy_buffer_raw(&mut self, buffer: Buffer) {
+    pub fn destroy_sampler(&self, id: SamplerId) -> Result<()> {
+        let sampler = self.samplers.write().unwrap().remove_resource(id.0)?;
+        unsafe { self.device_dep.device.destroy_sampler(sampler.handle, None) };
+        Ok(())
+    }
+
+    pub(crate) fn allocation_reports(&self) -> Vec<AllocationReport> {
+        self.allocator.lock().unwrap().allocation_reports()
+    }
+
+    fn destroy_buffer_raw(&self, buffer: Buffer) {
         unsafe { self.device_dep.device.destroy_buffer(buffer.handle, None) };
-        self.allocator.deallocate_memory(buffer.allocation)
+        self.allocator
+            .lock()
+            .unwrap()
+            .deallocate_memory(buffer.allocation)
     }
 }
 
 impl Drop for GpuResourcePool {
     fn drop(&mut self) {
-        unsafe { self.device_dep.device.device_wait_idle() }.expect("failed to idle");
-        for image in self.images.collect_existing() {
+        if let Err(error) = unsafe { self.device_dep.device.device_wait_idle() } {
+            report_error(format_args!(
+                "failed to wait for the device before destroying the gpu resources: {error}"
+            ));
+        }
+        let images = self.images.get_mut().unwrap().collect_existing();
+        for image in images {
             self.destroy_image_raw(image);
         }
-        for buffer in self.buffers.collect_existing() {
+        let buffers = self.buffers.get_mut().unwrap().collect_existing();
+        for buffer in buffers {
             self.destroy_buffer_raw(buffer);
         }
+        let samplers = self.samplers.get_mut().unwrap().collect_existing();
+        for sampler in samplers {
+            unsafe { self.device_dep.device.destroy_sampler(sampler.handle, None) };
+        }
         if let Some(buffer_addresses_buffer) = self.buffer_addresses_buffer.take() {
             self.destroy_buffer_raw(buffer_addresses_buffer);
         }
@@ -607,14 +1263,163 @@ pub struct Buffer {
     pub info: BufferInfo,
 }
 
+#[derive(Clone, Debug)]
+pub struct SamplerInfo {
+    pub name: String,
+    pub mag_filter: Filter,
+    pub min_filter: Filter,
+    pub mipmap_mode: SamplerMipmapMode,
+    pub address_mode_u: SamplerAddressMode,
+    pub address_mode_v: SamplerAddressMode,
+    pub address_mode_w: SamplerAddressMode,
+    /// Requires `DeviceFeatures::SAMPLER_ANISOTROPY`, clamped to the device's limit.
+    pub max_anisotropy: Option<f32>,
+    /// Turns the sampler into a comparison sampler for depth textures, see
+    /// `sample_texture_compare` in the preamble.
+    pub compare_op: Option<CompareOp>,
+    pub mip_lod_bias: f32,
+    pub min_lod: f32,
+    pub max_lod: f32,
+    pub border_color: BorderColor,
+}
+
+impl SamplerInfo {
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Sets both the magnification and minification filter.
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.mag_filter = filter;
+        self.min_filter = filter;
+        self
+    }
+
+    pub fn mipmap_mode(mut self, mipmap_mode: SamplerMipmapMode) -> Self {
+        self.mipmap_mode = mipmap_mode;
+        self
+    }
+
+    /// Sets the address mode of every coordinate.
+    pub fn address_mode(mut self, address_mode: SamplerAddressMode) -> Self {
+        self.address_mode_u = address_mode;
+        self.address_mode_v = address_mode;
+        self.address_mode_w = address_mode;
+        self
+    }
+
+    pub fn max_anisotropy(mut self, max_anisotropy: f32) -> Self {
+        self.max_anisotropy = Some(max_anisotropy);
+        self
+    }
+
+    pub fn compare_op(mut self, compare_op: CompareOp) -> Self {
+        self.compare_op = Some(compare_op);
+        self
+    }
+
+    pub fn lod_range(mut self, min_lod: f32, max_lod: f32) -> Self {
+        self.min_lod = min_lod;
+        self.max_lod = max_lod;
+        self
+    }
+
+    pub fn mip_lod_bias(mut self, mip_lod_bias: f32) -> Self {
+        self.mip_lod_bias = mip_lod_bias;
+        self
+    }
+
+    pub fn border_color(mut self, border_color: BorderColor) -> Self {
+        self.border_color = border_color;
+        self
+    }
+}
+
+impl Default for SamplerInfo {
+    fn default() -> Self {
+        SamplerInfo {
+            name: "sampler".to_owned(),
+            mag_filter: Filter::Linear,
+            min_filter: Filter::Linear,
+            mipmap_mode: SamplerMipmapMode::Linear,
+            address_mode_u: SamplerAddressMode::Repeat,
+            address_mode_v: SamplerAddressMode::Repeat,
+            address_mode_w: SamplerAddressMode::Repeat,
+            max_anisotropy: None,
+            compare_op: None,
+            mip_lod_bias: 0.0,
+            min_lod: 0.0,
+            max_lod: vk::LOD_CLAMP_NONE,
+            border_color: BorderColor::TransparentBlack,
+        }
+    }
+}
+
+pub struct Sampler {
+    pub handle: vk::Sampler,
+    pub info: SamplerInfo,
+}
+
 struct BufferAddressPtr(*mut u64);
 
-// Safety: we only mutate this memory and GpuResources follows mutalibity rules i think
+// Safety: addresses are only written while holding the buffers lock and each index belongs to a
+// single live buffer.
 unsafe impl Send for BufferAddressPtr {}
 unsafe impl Sync for BufferAddressPtr {}
 
 impl BufferAddressPtr {
-    fn write_buffer_address(&mut self, index: usize, address: u64) {
+    fn write_buffer_address(&self, index: usize, address: u64) {
         unsafe { self.0.offset(index as isize).write(address) };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(max_resources: u32) -> DeviceLimits {
+        DeviceLimits {
+            max_storage_buffer_range: 8 * 100,
+            max_descriptor_set_update_after_bind_storage_images: u32::MAX,
+            max_descriptor_set_update_after_bind_sampled_images: u32::MAX,
+            max_descriptor_set_update_after_bind_samplers: u32::MAX,
+            max_per_stage_descriptor_update_after_bind_storage_images: u32::MAX,
+            max_per_stage_descriptor_update_after_bind_sampled_images: u32::MAX,
+            max_per_stage_descriptor_update_after_bind_samplers: u32::MAX,
+            max_per_stage_update_after_bind_resources: max_resources,
+            max_sampler_allocation_count: u32::MAX,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn capacities_are_clamped_to_the_limits() {
+        let capacities = BindlessCapacities {
+            buffers: 1000,
+            images: 1000,
+            samplers: 1000,
+        }
+        .clamped(&limits(301))
+        .unwrap();
+        assert_eq!(
+            capacities,
+            BindlessCapacities {
+                buffers: 100,
+                images: 100,
+                samplers: 100,
+            }
+        );
+    }
+
+    #[test]
+    fn capacities_are_never_raised_above_the_limits() {
+        let capacities = BindlessCapacities {
+            buffers: 0,
+            images: 0,
+            samplers: 0,
+        };
+        assert!(capacities.clamped(&limits(4)).is_ok());
+        assert!(capacities.clamped(&limits(3)).is_err());
+    }
+}
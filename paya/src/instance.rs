@@ -1,17 +1,58 @@
-use std::{ffi::CString, sync::Arc};
+use std::{
+    ffi::{CStr, CString},
+    sync::Arc,
+};
 
 use ash::vk;
 
+use crate::{
+    debug::{DebugMessage, DebugMessageSeverity, DebugMessenger, DebugMessengerInfo},
+    error::{Error, Result},
+    physical_device::PhysicalDeviceInfo,
+};
+
+const VALIDATION_LAYER_NAME: &CStr = c"VK_LAYER_KHRONOS_validation";
+
 pub struct InstanceCreateInfo<'a> {
+    /// The display the instance will present to, when `None` the instance is created headless
+    /// and no surface extensions are enabled.
     pub display_handle: Option<&'a dyn raw_window_handle::HasDisplayHandle>,
+    /// Enables `VK_LAYER_KHRONOS_validation` if it is installed.
+    pub validation: bool,
+    /// Creates a debug messenger that routes driver and validation messages, `None` disables it.
+    pub debug_messenger: Option<DebugMessengerInfo>,
+}
+
+impl InstanceCreateInfo<'_> {
+    /// Creates an instance without any surface or swapchain support, useful for compute and
+    /// offscreen rendering on machines without a display.
+    pub fn headless() -> Self {
+        InstanceCreateInfo {
+            display_handle: None,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for InstanceCreateInfo<'_> {
+    fn default() -> Self {
+        InstanceCreateInfo {
+            display_handle: None,
+            validation: cfg!(debug_assertions),
+            debug_messenger: cfg!(debug_assertions).then(DebugMessengerInfo::default),
+        }
+    }
 }
 
-#[derive(Clone)]
 pub struct InstanceInner {
     pub(crate) loader: ash::Entry,
     pub(crate) instance: ash::Instance,
-    pub(crate) debug_utils: ash::ext::debug_utils::Instance,
+    pub(crate) debug_utils: Option<ash::ext::debug_utils::Instance>,
     debug_utils_messenger: vk::DebugUtilsMessengerEXT,
+    // Boxed so the pointer handed to the driver as user data stays stable.
+    debug_messenger: Option<Box<DebugMessenger>>,
+    pub(crate) headless: bool,
+    pub(crate) validation_enabled: bool,
 }
 
 pub struct Instance {
@@ -19,8 +60,9 @@ pub struct Instance {
 }
 
 impl Instance {
-    pub fn new(create_info: InstanceCreateInfo<'_>) -> Self {
-        let loader = unsafe { ash::Entry::load().unwrap() };
+    pub fn new(create_info: InstanceCreateInfo<'_>) -> Result<Self> {
+        let loader =
+            unsafe { ash::Entry::load() }.map_err(|error| Error::Loading(error.to_string()))?;
         let app_name = std::ffi::CString::new("Paya").unwrap();
 
         let app_info = vk::ApplicationInfo::default()
@@ -30,81 +72,91 @@ impl Instance {
             .engine_version(vk::make_api_version(0, 1, 0, 0))
             .api_version(vk::make_api_version(0, 1, 2, 0));
 
-        let c_instance_extensions = vec![
-            #[cfg(debug_assertions)]
-            ash::ext::debug_utils::NAME.as_ref(),
-        ];
+        let debug_messenger = create_info
+            .debug_messenger
+            .map(|info| Box::new(DebugMessenger::new(info)));
+
+        let available_layers = unsafe { loader.enumerate_instance_layer_properties() }?;
+        let available_extensions = unsafe { loader.enumerate_instance_extension_properties(None) }?;
+
+        let validation_enabled = create_info.validation
+            && available_layers
+                .iter()
+                .any(|layer| layer.layer_name_as_c_str() == Ok(VALIDATION_LAYER_NAME));
+        if create_info.validation && !validation_enabled {
+            if let Some(debug_messenger) = &debug_messenger {
+                debug_messenger.emit(
+                    DebugMessageSeverity::WARNING,
+                    "Validation was requested but VK_LAYER_KHRONOS_validation is not installed",
+                );
+            }
+        }
 
-        let c_instance_layers = vec![
-            #[cfg(debug_assertions)]
-            CString::new("VK_LAYER_KHRONOS_validation").unwrap(),
-        ];
+        // Debug utils are also used for object names so enable them whenever they're available
+        // and something asked for debugging.
+        let debug_utils_enabled = (validation_enabled || debug_messenger.is_some())
+            && available_extensions.iter().any(|extension| {
+                extension.extension_name_as_c_str() == Ok(ash::ext::debug_utils::NAME)
+            });
 
-        let mut c_ptr_instance_extensions = c_instance_extensions
-            .iter()
-            .map(|s| s.as_ptr())
-            .collect::<Vec<_>>();
+        let mut c_ptr_instance_extensions = Vec::new();
+        if debug_utils_enabled {
+            c_ptr_instance_extensions.push(ash::ext::debug_utils::NAME.as_ptr());
+        }
+
+        let c_instance_layers = if validation_enabled {
+            vec![CString::from(VALIDATION_LAYER_NAME)]
+        } else {
+            vec![]
+        };
         let c_ptr_instance_layers = c_instance_layers
             .iter()
             .map(|s| s.as_ptr())
             .collect::<Vec<_>>();
 
         if let Some(display_handle) = create_info.display_handle {
-            c_ptr_instance_extensions.extend(
-                ash_window::enumerate_required_extensions(
-                    display_handle.display_handle().unwrap().as_raw(),
-                )
-                .unwrap(),
-            );
+            c_ptr_instance_extensions.extend(ash_window::enumerate_required_extensions(
+                display_handle.display_handle()?.as_raw(),
+            )?);
         }
 
-        let instance_create_info = vk::InstanceCreateInfo::default()
+        let mut instance_create_info = vk::InstanceCreateInfo::default()
             .application_info(&app_info)
             .enabled_extension_names(&c_ptr_instance_extensions)
             .enabled_layer_names(&c_ptr_instance_layers);
+        // Chained so messages from creating and destroying the instance itself, which the
+        // messenger created below can't see, reach the sink as well.
+        let mut instance_messenger_create_info = debug_messenger
+            .as_ref()
+            .filter(|_| debug_utils_enabled)
+            .map(|debug_messenger| debug_messenger.vk_create_info());
+        if let Some(messenger_create_info) = &mut instance_messenger_create_info {
+            instance_create_info = instance_create_info.push_next(messenger_create_info);
+        }
 
-        let instance = unsafe { loader.create_instance(&instance_create_info, None).unwrap() };
-
-        let debug_utils = ash::ext::debug_utils::Instance::new(&loader, &instance);
-        let debug_utils_create_info = vk::DebugUtilsMessengerCreateInfoEXT::default()
-            .message_severity(
-                vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-                    | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
-                    | vk::DebugUtilsMessageSeverityFlagsEXT::INFO
-                    | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
-            )
-            .message_type(
-                vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
-                    | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
-                    | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
-            )
-            .pfn_user_callback(Some(Self::debug_utils_callback));
-
-        let debug_utils_messenger =
-            unsafe { debug_utils.create_debug_utils_messenger(&debug_utils_create_info, None) }
-                .expect("Failed to create debug utils messenger");
-
-        Instance {
+        let instance = unsafe { loader.create_instance(&instance_create_info, None) }?;
+
+        let debug_utils =
+            debug_utils_enabled.then(|| ash::ext::debug_utils::Instance::new(&loader, &instance));
+
+        let debug_utils_messenger = match (&debug_utils, &debug_messenger) {
+            (Some(debug_utils), Some(debug_messenger)) => unsafe {
+                debug_utils.create_debug_utils_messenger(&debug_messenger.vk_create_info(), None)
+            }?,
+            _ => vk::DebugUtilsMessengerEXT::null(),
+        };
+
+        Ok(Instance {
             inner: Arc::new(InstanceInner {
                 loader,
                 instance,
                 debug_utils,
                 debug_utils_messenger,
+                debug_messenger,
+                headless: create_info.display_handle.is_none(),
+                validation_enabled,
             }),
-        }
-    }
-
-    unsafe extern "system" fn debug_utils_callback(
-        message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
-        message_type: vk::DebugUtilsMessageTypeFlagsEXT,
-        p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-        _p_user_data: *mut std::ffi::c_void,
-    ) -> vk::Bool32 {
-        let message = std::ffi::CStr::from_ptr((*p_callback_data).p_message);
-        let severity = format!("{:?}", message_severity).to_lowercase();
-        let ty = format!("{:?}", message_type).to_lowercase();
-        println!("[Debug][{}][{}] {:?}", severity, ty, message);
-        vk::FALSE
+        })
     }
 
     pub fn create_dep(&self) -> Arc<InstanceInner> {
@@ -118,13 +170,55 @@ impl Instance {
     pub unsafe fn handle(&self) -> &ash::Instance {
         &self.inner.instance
     }
+
+    pub fn is_headless(&self) -> bool {
+        self.inner.headless
+    }
+
+    /// Devices below vulkan 1.2 are skipped since paya can't run on them.
+    pub fn enumerate_physical_devices(&self) -> Result<Vec<PhysicalDeviceInfo>> {
+        unsafe { self.inner.instance.enumerate_physical_devices() }?
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, handle)| {
+                PhysicalDeviceInfo::query(&self.inner.instance, index, handle).transpose()
+            })
+            .collect()
+    }
+
+    /// Whether the validation layer was actually enabled, it is skipped when not installed.
+    pub fn validation_enabled(&self) -> bool {
+        self.inner.validation_enabled
+    }
+
+    /// Returns every validation error collected so far, requires
+    /// `DebugMessengerInfo::collect_validation_errors`.
+    pub fn validation_errors(&self) -> Vec<DebugMessage> {
+        self.inner
+            .debug_messenger
+            .as_ref()
+            .map(|messenger| messenger.validation_errors())
+            .unwrap_or_default()
+    }
+
+    /// Returns and clears the collected validation errors, useful for asserting per frame.
+    pub fn take_validation_errors(&self) -> Vec<DebugMessage> {
+        self.inner
+            .debug_messenger
+            .as_ref()
+            .map(|messenger| messenger.take_validation_errors())
+            .unwrap_or_default()
+    }
 }
 
 impl Drop for InstanceInner {
     fn drop(&mut self) {
         unsafe {
-            self.debug_utils
-                .destroy_debug_utils_messenger(self.debug_utils_messenger, None);
+            if let Some(debug_utils) = &self.debug_utils {
+                if self.debug_utils_messenger != vk::DebugUtilsMessengerEXT::null() {
+                    debug_utils.destroy_debug_utils_messenger(self.debug_utils_messenger, None);
+                }
+            }
             self.instance.destroy_instance(None);
         }
     }
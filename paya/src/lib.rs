@@ -1,12 +1,23 @@
 pub mod allocator;
 pub mod command_recorder;
 pub mod common;
+pub mod debug;
 pub mod device;
+pub mod error;
+mod garbage;
 pub mod gpu_resources;
 pub mod instance;
+pub mod mipmap;
+pub mod physical_device;
 pub mod pipeline;
 pub mod preamble;
+pub mod query;
+pub mod queue;
+mod readback;
 pub mod shader;
 pub mod swapchain;
 pub mod sync;
 pub mod task_list;
+mod upload;
+
+pub use error::{Error, Result};
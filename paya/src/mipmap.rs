@@ -0,0 +1,440 @@
+use crate::{
+    command_recorder::CommandRecorder,
+    common::{
+        AccessFlags2, BarrierInfo, Format, ImageBarrier, ImageLayout, ImageSubresourceLayers,
+        ImageSubresourceRange, ImageUsageFlags, ImageViewType, PipelineStageFlags2, SampleCount,
+    },
+    device::{Device, ImageInfo},
+    error::{Error, Result},
+    gpu_resources::{ImageId, PackedGpuResourceId},
+    physical_device::FormatFeatureFlags,
+    pipeline::{ComputePipeline, ComputePipelineInfo},
+    shader::{ShaderInfo, ShaderLoadOptions, ShaderType},
+};
+
+/// Reducer shaders run 8x8 threads per group, one per destination texel.
+const REDUCE_GROUP_SIZE: u32 = 8;
+
+/// How the compute path combines the footprint of a destination texel in the level above.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MipReduce {
+    Average,
+    /// Keeps the nearest depth of a reverse-z depth pyramid.
+    Min,
+    /// Keeps the nearest depth of a depth pyramid with regular depth.
+    Max,
+}
+
+/// Push constants of a reducer pipeline. Custom reducers passed to
+/// `CommandRecorder::generate_mipmaps_compute` declare the same block:
+///
+/// ```glsl
+/// DECL_PUSH_CONSTANTS {
+///   ResourceId src;
+///   ResourceId dst;
+///   u32vec2 src_extent;
+///   u32vec2 dst_extent;
+/// } push_constants;
+/// ```
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct MipReducePushConstants {
+    /// The storage view of the level that's read.
+    pub src: PackedGpuResourceId,
+    /// The storage view of the level that's written.
+    pub dst: PackedGpuResourceId,
+    pub src_extent: [u32; 2],
+    pub dst_extent: [u32; 2],
+}
+
+/// Moves mip level 0 out of `src_layout` and blits it down the chain, every level ends up in
+/// `dst_layout`. The contents of the other levels are discarded.
+pub(crate) fn generate_mipmaps(
+    recorder: &mut CommandRecorder,
+    device: &Device,
+    image: ImageId,
+    src_layout: ImageLayout,
+    dst_layout: ImageLayout,
+) -> Result<()> {
+    let info = device.get_image(image)?.info.clone();
+    if !info
+        .usage
+        .contains(ImageUsageFlags::TRANSFER_SRC | ImageUsageFlags::TRANSFER_DST)
+    {
+        return Err(Error::InvalidUsage(format!(
+            "Image {} needs TRANSFER_SRC and TRANSFER_DST usage to generate mipmaps",
+            info.name
+        )));
+    }
+    if info.samples != SampleCount::Type1 {
+        return Err(Error::InvalidUsage(format!(
+            "Image {} is multisampled and can't have mipmaps",
+            info.name
+        )));
+    }
+    let blit_features = FormatFeatureFlags::BLIT_SRC
+        | FormatFeatureFlags::BLIT_DST
+        | FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+    if !device
+        .physical_device_info()
+        .format_support(info.format)
+        .optimal_tiling
+        .contains(blit_features)
+    {
+        return Err(Error::InvalidUsage(format!(
+            "Format {:?} of image {} doesn't support linear blits, use \
+             generate_mipmaps_compute instead",
+            info.format, info.name
+        )));
+    }
+
+    let mut image_barriers = vec![level_barrier(
+        image,
+        ImageSubresourceRange::mip(0),
+        (
+            PipelineStageFlags2::ALL_COMMANDS,
+            AccessFlags2::MEMORY_WRITE,
+            src_layout,
+        ),
+        (
+            PipelineStageFlags2::BLIT,
+            AccessFlags2::TRANSFER_READ,
+            ImageLayout::TransferSrcOptimal,
+        ),
+    )];
+    if info.mip_levels > 1 {
+        image_barriers.push(level_barrier(
+            image,
+            ImageSubresourceRange::new(
+                1,
+                ImageSubresourceRange::REMAINING,
+                0,
+                ImageSubresourceRange::REMAINING,
+            ),
+            (
+                PipelineStageFlags2::ALL_COMMANDS,
+                AccessFlags2::empty(),
+                ImageLayout::Undefined,
+            ),
+            (
+                PipelineStageFlags2::BLIT,
+                AccessFlags2::TRANSFER_WRITE,
+                ImageLayout::TransferDstOptimal,
+            ),
+        ));
+    }
+    recorder.pipeline_barrier(
+        device,
+        &BarrierInfo {
+            image_barriers,
+            ..Default::default()
+        },
+    )?;
+
+    for level in 1..info.mip_levels {
+        recorder.blit_image_to_image(
+            device,
+            image,
+            ImageSubresourceLayers::mip(level - 1),
+            image,
+            ImageSubresourceLayers::mip(level),
+        )?;
+        // The level is the source of the next blit.
+        recorder.pipeline_barrier(
+            device,
+            &BarrierInfo {
+                image_barriers: vec![level_barrier(
+                    image,
+                    ImageSubresourceRange::mip(level),
+                    (
+                        PipelineStageFlags2::BLIT,
+                        AccessFlags2::TRANSFER_WRITE,
+                        ImageLayout::TransferDstOptimal,
+                    ),
+                    (
+                        PipelineStageFlags2::BLIT,
+                        AccessFlags2::TRANSFER_READ,
+                        ImageLayout::TransferSrcOptimal,
+                    ),
+                )],
+                ..Default::default()
+            },
+        )?;
+    }
+
+    recorder.pipeline_barrier(
+        device,
+        &BarrierInfo {
+            image_barriers: vec![level_barrier(
+                image,
+                ImageSubresourceRange::default(),
+                (
+                    PipelineStageFlags2::BLIT,
+                    AccessFlags2::TRANSFER_WRITE,
+                    ImageLayout::TransferSrcOptimal,
+                ),
+                (
+                    PipelineStageFlags2::ALL_COMMANDS,
+                    AccessFlags2::MEMORY_READ | AccessFlags2::MEMORY_WRITE,
+                    dst_layout,
+                ),
+            )],
+            ..Default::default()
+        },
+    )
+}
+
+/// Like `generate_mipmaps`, but every level is written by dispatching `pipeline` over the
+/// storage views of the image. The pipeline stays bound afterwards.
+pub(crate) fn generate_mipmaps_compute(
+    recorder: &mut CommandRecorder,
+    device: &Device,
+    image: ImageId,
+    pipeline: &ComputePipeline,
+    src_layout: ImageLayout,
+    dst_layout: ImageLayout,
+) -> Result<()> {
+    let info = device.get_image(image)?.info.clone();
+    validate_compute_image(&info)?;
+    let level_views = (0..info.mip_levels)
+        .map(|level| device.image_mip_view(image, level))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut image_barriers = vec![level_barrier(
+        image,
+        ImageSubresourceRange::mip(0),
+        (
+            PipelineStageFlags2::ALL_COMMANDS,
+            AccessFlags2::MEMORY_WRITE,
+            src_layout,
+        ),
+        (
+            PipelineStageFlags2::COMPUTE_SHADER,
+            AccessFlags2::SHADER_STORAGE_READ,
+            ImageLayout::General,
+        ),
+    )];
+    if info.mip_levels > 1 {
+        image_barriers.push(level_barrier(
+            image,
+            ImageSubresourceRange::new(
+                1,
+                ImageSubresourceRange::REMAINING,
+                0,
+                ImageSubresourceRange::REMAINING,
+            ),
+            (
+                PipelineStageFlags2::ALL_COMMANDS,
+                AccessFlags2::empty(),
+                ImageLayout::Undefined,
+            ),
+            (
+                PipelineStageFlags2::COMPUTE_SHADER,
+                AccessFlags2::SHADER_STORAGE_WRITE,
+                ImageLayout::General,
+            ),
+        ));
+    }
+    recorder.pipeline_barrier(
+        device,
+        &BarrierInfo {
+            image_barriers,
+            ..Default::default()
+        },
+    )?;
+
+    recorder.bind_compute_pipeline(device, pipeline);
+    for level in 1..info.mip_levels {
+        let src_extent = info.mip_extent(level - 1);
+        let dst_extent = info.mip_extent(level);
+        recorder.upload_push_constants(
+            device,
+            pipeline,
+            &MipReducePushConstants {
+                src: level_views[level as usize - 1].pack(),
+                dst: level_views[level as usize].pack(),
+                src_extent: [src_extent.width, src_extent.height],
+                dst_extent: [dst_extent.width, dst_extent.height],
+            },
+        );
+        recorder.dispatch(
+            device,
+            dst_extent.width.div_ceil(REDUCE_GROUP_SIZE),
+            dst_extent.height.div_ceil(REDUCE_GROUP_SIZE),
+            1,
+        );
+        // The level is read by the next dispatch.
+        recorder.pipeline_barrier(
+            device,
+            &BarrierInfo {
+                image_barriers: vec![level_barrier(
+                    image,
+                    ImageSubresourceRange::mip(level),
+                    (
+                        PipelineStageFlags2::COMPUTE_SHADER,
+                        AccessFlags2::SHADER_STORAGE_WRITE,
+                        ImageLayout::General,
+                    ),
+                    (
+                        PipelineStageFlags2::COMPUTE_SHADER,
+                        AccessFlags2::SHADER_STORAGE_READ,
+                        ImageLayout::General,
+                    ),
+                )],
+                ..Default::default()
+            },
+        )?;
+    }
+
+    recorder.pipeline_barrier(
+        device,
+        &BarrierInfo {
+            image_barriers: vec![level_barrier(
+                image,
+                ImageSubresourceRange::default(),
+                (
+                    PipelineStageFlags2::COMPUTE_SHADER,
+                    AccessFlags2::SHADER_STORAGE_WRITE,
+                    ImageLayout::General,
+                ),
+                (
+                    PipelineStageFlags2::ALL_COMMANDS,
+                    AccessFlags2::MEMORY_READ | AccessFlags2::MEMORY_WRITE,
+                    dst_layout,
+                ),
+            )],
+            ..Default::default()
+        },
+    )
+}
+
+fn validate_compute_image(info: &ImageInfo) -> Result<()> {
+    if !info.usage.contains(ImageUsageFlags::STORAGE) {
+        return Err(Error::InvalidUsage(format!(
+            "Image {} needs STORAGE usage to generate mipmaps with a compute pipeline",
+            info.name
+        )));
+    }
+    if info.resolved_view_type() != Some(ImageViewType::Type2D) {
+        return Err(Error::InvalidUsage(format!(
+            "Image {} isn't a single layer 2D image, compute mipmaps are only generated for \
+             those",
+            info.name
+        )));
+    }
+    if info.samples != SampleCount::Type1 {
+        return Err(Error::InvalidUsage(format!(
+            "Image {} is multisampled and can't have mipmaps",
+            info.name
+        )));
+    }
+    Ok(())
+}
+
+/// Covers `subresource_range` of `image`, `src` and `dst` are the stages, accesses and layout on
+/// either side.
+fn level_barrier(
+    image: ImageId,
+    subresource_range: ImageSubresourceRange,
+    src: (PipelineStageFlags2, AccessFlags2, ImageLayout),
+    dst: (PipelineStageFlags2, AccessFlags2, ImageLayout),
+) -> ImageBarrier {
+    ImageBarrier {
+        image,
+        src_stages: src.0,
+        src_access: src.1,
+        dst_stages: dst.0,
+        dst_access: dst.1,
+        src_layout: src.2,
+        dst_layout: dst.2,
+        subresource_range,
+        ownership_transfer: None,
+    }
+}
+
+/// Compiles the built-in reducer for storage images of `format`.
+pub(crate) fn create_reduce_pipeline(
+    device: &Device,
+    format: Format,
+    reduce: MipReduce,
+) -> Result<ComputePipeline> {
+    let Some((qualifier, prefix)) = format.storage_image_qualifier() else {
+        return Err(Error::InvalidUsage(format!(
+            "Format {:?} can't be used for storage images, mipmaps can't be reduced in a \
+             compute shader",
+            format
+        )));
+    };
+
+    let name = format!("paya mip reduce {:?} {:?}", reduce, format);
+    let compiler = device.create_shader_compiler()?;
+    let byte_code = compiler.load_string(
+        reduce_shader_glsl(qualifier, prefix, reduce),
+        ShaderLoadOptions::new(ShaderType::Compute, &name),
+    )?;
+
+    device.create_compute_pipeline(ComputePipelineInfo {
+        name,
+        shader: ShaderInfo {
+            byte_code,
+            entry_point: "main".to_owned(),
+        },
+        push_constant_size: std::mem::size_of::<MipReducePushConstants>() as u32,
+    })
+}
+
+/// Every destination texel reduces its 2x2 footprint, the last texel of a row or column also
+/// covers the texel left over by an odd source extent.
+fn reduce_shader_glsl(qualifier: &str, prefix: &str, reduce: MipReduce) -> String {
+    let (combine, finish) = match reduce {
+        MipReduce::Average => (
+            "result + texel".to_owned(),
+            format!("result / {prefix}vec4(count)"),
+        ),
+        MipReduce::Min => ("min(result, texel)".to_owned(), "result".to_owned()),
+        MipReduce::Max => ("max(result, texel)".to_owned(), "result".to_owned()),
+    };
+
+    format!(
+        "\
+layout (local_size_x = {REDUCE_GROUP_SIZE}, local_size_y = {REDUCE_GROUP_SIZE}, local_size_z = 1) in;
+DECL_PUSH_CONSTANTS {{
+  ResourceId src;
+  ResourceId dst;
+  u32vec2 src_extent;
+  u32vec2 dst_extent;
+}} push_constants;
+
+void main() {{
+  ivec2 coord = ivec2(gl_GlobalInvocationID.xy);
+  ivec2 dst_extent = ivec2(push_constants.dst_extent);
+  if (coord.x >= dst_extent.x || coord.y >= dst_extent.y) {{
+    return;
+  }}
+
+  ivec2 last = ivec2(push_constants.src_extent) - 1;
+  ivec2 start = coord * 2;
+  ivec2 end = min(start + 1, last);
+  if (coord.x == dst_extent.x - 1) {{
+    end.x = last.x;
+  }}
+  if (coord.y == dst_extent.y - 1) {{
+    end.y = last.y;
+  }}
+
+  {prefix}vec4 result = imageLoad(get_storage_image_{qualifier}(push_constants.src), start);
+  for (int y = start.y; y <= end.y; y++) {{
+    for (int x = start.x; x <= end.x; x++) {{
+      if (x == start.x && y == start.y) {{
+        continue;
+      }}
+      {prefix}vec4 texel = imageLoad(get_storage_image_{qualifier}(push_constants.src), ivec2(x, y));
+      result = {combine};
+    }}
+  }}
+  int count = (end.x - start.x + 1) * (end.y - start.y + 1);
+  imageStore(get_storage_image_{qualifier}(push_constants.dst), coord, {finish});
+}}
+"
+    )
+}
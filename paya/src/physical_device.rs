@@ -0,0 +1,656 @@
+use std::{collections::HashMap, ffi::CStr};
+
+use ash::vk;
+use bitflags::bitflags;
+
+use crate::{
+    allocator::MemoryFlags,
+    common::{Extent3D, Format},
+    error::Result,
+};
+
+/// Environment variable that forces a physical device by index or (partial) name, overriding the
+/// selector passed to `Device::new`.
+pub const DEVICE_OVERRIDE_ENV: &str = "PAYA_DEVICE";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    Integrated,
+    Discrete,
+    Virtual,
+    Cpu,
+    Other,
+}
+
+impl From<vk::PhysicalDeviceType> for DeviceType {
+    fn from(device_type: vk::PhysicalDeviceType) -> Self {
+        match device_type {
+            vk::PhysicalDeviceType::INTEGRATED_GPU => DeviceType::Integrated,
+            vk::PhysicalDeviceType::DISCRETE_GPU => DeviceType::Discrete,
+            vk::PhysicalDeviceType::VIRTUAL_GPU => DeviceType::Virtual,
+            vk::PhysicalDeviceType::CPU => DeviceType::Cpu,
+            _ => DeviceType::Other,
+        }
+    }
+}
+
+bitflags! {
+    /// Optional device features paya knows how to query and enable.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct DeviceFeatures: u64 {
+        const ROBUST_BUFFER_ACCESS = 1 << 0;
+        const GEOMETRY_SHADER = 1 << 1;
+        const TESSELLATION_SHADER = 1 << 2;
+        const MULTI_DRAW_INDIRECT = 1 << 3;
+        const DEPTH_CLAMP = 1 << 4;
+        const FILL_MODE_NON_SOLID = 1 << 5;
+        const WIDE_LINES = 1 << 6;
+        const SAMPLER_ANISOTROPY = 1 << 7;
+        const PIPELINE_STATISTICS_QUERY = 1 << 8;
+        const SHADER_STORAGE_IMAGE_READ_WITHOUT_FORMAT = 1 << 9;
+        const SHADER_STORAGE_IMAGE_WRITE_WITHOUT_FORMAT = 1 << 10;
+        const SHADER_FLOAT64 = 1 << 11;
+        const SHADER_INT64 = 1 << 12;
+        const SHADER_INT16 = 1 << 13;
+        /// Storage images with two channel, 8 and 16 bit formats, the preamble declares them.
+        const SHADER_STORAGE_IMAGE_EXTENDED_FORMATS = 1 << 14;
+
+        const STORAGE_BUFFER_16BIT_ACCESS = 1 << 16;
+        const SHADER_DRAW_PARAMETERS = 1 << 17;
+
+        const STORAGE_BUFFER_8BIT_ACCESS = 1 << 20;
+        const SHADER_BUFFER_INT64_ATOMICS = 1 << 21;
+        const SHADER_FLOAT16 = 1 << 22;
+        const SHADER_INT8 = 1 << 23;
+        const DESCRIPTOR_INDEXING = 1 << 24;
+        const SCALAR_BLOCK_LAYOUT = 1 << 25;
+        const HOST_QUERY_RESET = 1 << 26;
+        const TIMELINE_SEMAPHORE = 1 << 27;
+        const BUFFER_DEVICE_ADDRESS = 1 << 28;
+
+        const DYNAMIC_RENDERING = 1 << 32;
+        const SYNCHRONIZATION_2 = 1 << 33;
+        const ROBUSTNESS_2 = 1 << 34;
+    }
+}
+
+impl DeviceFeatures {
+    /// Features the bindless resource model is built on, these are always enabled.
+    pub const PAYA_REQUIRED: DeviceFeatures = DeviceFeatures::BUFFER_DEVICE_ADDRESS
+        .union(DeviceFeatures::TIMELINE_SEMAPHORE)
+        .union(DeviceFeatures::DESCRIPTOR_INDEXING)
+        .union(DeviceFeatures::DYNAMIC_RENDERING)
+        .union(DeviceFeatures::SYNCHRONIZATION_2)
+        .union(DeviceFeatures::SHADER_STORAGE_IMAGE_EXTENDED_FORMATS);
+
+    /// Adds the features that vulkan requires to be enabled alongside these.
+    pub fn with_dependencies(self) -> DeviceFeatures {
+        let mut features = self;
+        if features.contains(DeviceFeatures::ROBUSTNESS_2) {
+            features |= DeviceFeatures::ROBUST_BUFFER_ACCESS;
+        }
+        features
+    }
+
+    /// Device extensions that have to be enabled to use these features on vulkan 1.2.
+    pub fn extensions(self) -> Vec<&'static CStr> {
+        let mut extensions = Vec::new();
+        if self.contains(DeviceFeatures::DYNAMIC_RENDERING) {
+            extensions.push(ash::khr::dynamic_rendering::NAME);
+        }
+        if self.contains(DeviceFeatures::SYNCHRONIZATION_2) {
+            extensions.push(ash::khr::synchronization2::NAME);
+        }
+        if self.contains(DeviceFeatures::ROBUSTNESS_2) {
+            extensions.push(ash::ext::robustness2::NAME);
+        }
+        extensions
+    }
+}
+
+/// The vulkan feature structures with exactly the given features enabled, chained into
+/// `vk::DeviceCreateInfo` when creating the device.
+pub(crate) struct VkDeviceFeatures {
+    pub(crate) core: vk::PhysicalDeviceFeatures,
+    pub(crate) vulkan11: vk::PhysicalDeviceVulkan11Features<'static>,
+    pub(crate) vulkan12: vk::PhysicalDeviceVulkan12Features<'static>,
+    pub(crate) dynamic_rendering: vk::PhysicalDeviceDynamicRenderingFeatures<'static>,
+    pub(crate) synchronization2: vk::PhysicalDeviceSynchronization2Features<'static>,
+    pub(crate) robustness2: vk::PhysicalDeviceRobustness2FeaturesEXT<'static>,
+}
+
+impl VkDeviceFeatures {
+    pub(crate) fn new(features: DeviceFeatures) -> Self {
+        let enabled = |feature: DeviceFeatures| features.contains(feature);
+        let descriptor_indexing = enabled(DeviceFeatures::DESCRIPTOR_INDEXING);
+        let robustness2 = enabled(DeviceFeatures::ROBUSTNESS_2);
+
+        VkDeviceFeatures {
+            core: vk::PhysicalDeviceFeatures::default()
+                .robust_buffer_access(enabled(DeviceFeatures::ROBUST_BUFFER_ACCESS))
+                .geometry_shader(enabled(DeviceFeatures::GEOMETRY_SHADER))
+                .tessellation_shader(enabled(DeviceFeatures::TESSELLATION_SHADER))
+                .multi_draw_indirect(enabled(DeviceFeatures::MULTI_DRAW_INDIRECT))
+                .depth_clamp(enabled(DeviceFeatures::DEPTH_CLAMP))
+                .fill_mode_non_solid(enabled(DeviceFeatures::FILL_MODE_NON_SOLID))
+                .wide_lines(enabled(DeviceFeatures::WIDE_LINES))
+                .sampler_anisotropy(enabled(DeviceFeatures::SAMPLER_ANISOTROPY))
+                .pipeline_statistics_query(enabled(DeviceFeatures::PIPELINE_STATISTICS_QUERY))
+                .shader_storage_image_read_without_format(enabled(
+                    DeviceFeatures::SHADER_STORAGE_IMAGE_READ_WITHOUT_FORMAT,
+                ))
+                .shader_storage_image_write_without_format(enabled(
+                    DeviceFeatures::SHADER_STORAGE_IMAGE_WRITE_WITHOUT_FORMAT,
+                ))
+                .shader_float64(enabled(DeviceFeatures::SHADER_FLOAT64))
+                .shader_int64(enabled(DeviceFeatures::SHADER_INT64))
+                .shader_int16(enabled(DeviceFeatures::SHADER_INT16))
+                .shader_storage_image_extended_formats(enabled(
+                    DeviceFeatures::SHADER_STORAGE_IMAGE_EXTENDED_FORMATS,
+                )),
+            vulkan11: vk::PhysicalDeviceVulkan11Features::default()
+                .storage_buffer16_bit_access(enabled(DeviceFeatures::STORAGE_BUFFER_16BIT_ACCESS))
+                .shader_draw_parameters(enabled(DeviceFeatures::SHADER_DRAW_PARAMETERS)),
+            vulkan12: vk::PhysicalDeviceVulkan12Features::default()
+                .storage_buffer8_bit_access(enabled(DeviceFeatures::STORAGE_BUFFER_8BIT_ACCESS))
+                .shader_buffer_int64_atomics(enabled(DeviceFeatures::SHADER_BUFFER_INT64_ATOMICS))
+                .shader_float16(enabled(DeviceFeatures::SHADER_FLOAT16))
+                .shader_int8(enabled(DeviceFeatures::SHADER_INT8))
+                .descriptor_indexing(descriptor_indexing)
+                .runtime_descriptor_array(descriptor_indexing)
+                .descriptor_binding_partially_bound(descriptor_indexing)
+                .descriptor_binding_storage_buffer_update_after_bind(descriptor_indexing)
+                .descriptor_binding_storage_image_update_after_bind(descriptor_indexing)
+                .descriptor_binding_sampled_image_update_after_bind(descriptor_indexing)
+                .shader_storage_image_array_non_uniform_indexing(descriptor_indexing)
+                .shader_sampled_image_array_non_uniform_indexing(descriptor_indexing)
+                .scalar_block_layout(enabled(DeviceFeatures::SCALAR_BLOCK_LAYOUT))
+                .host_query_reset(enabled(DeviceFeatures::HOST_QUERY_RESET))
+                .timeline_semaphore(enabled(DeviceFeatures::TIMELINE_SEMAPHORE))
+                .buffer_device_address(enabled(DeviceFeatures::BUFFER_DEVICE_ADDRESS)),
+            dynamic_rendering: vk::PhysicalDeviceDynamicRenderingFeatures::default()
+                .dynamic_rendering(enabled(DeviceFeatures::DYNAMIC_RENDERING)),
+            synchronization2: vk::PhysicalDeviceSynchronization2Features::default()
+                .synchronization2(enabled(DeviceFeatures::SYNCHRONIZATION_2)),
+            robustness2: vk::PhysicalDeviceRobustness2FeaturesEXT::default()
+                .robust_buffer_access2(robustness2)
+                .robust_image_access2(robustness2),
+        }
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct QueueFlags: u32 {
+        const GRAPHICS = vk::QueueFlags::GRAPHICS.as_raw();
+        const COMPUTE = vk::QueueFlags::COMPUTE.as_raw();
+        const TRANSFER = vk::QueueFlags::TRANSFER.as_raw();
+        const SPARSE_BINDING = vk::QueueFlags::SPARSE_BINDING.as_raw();
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct FormatFeatureFlags: u32 {
+        const SAMPLED_IMAGE = vk::FormatFeatureFlags::SAMPLED_IMAGE.as_raw();
+        const STORAGE_IMAGE = vk::FormatFeatureFlags::STORAGE_IMAGE.as_raw();
+        const STORAGE_IMAGE_ATOMIC = vk::FormatFeatureFlags::STORAGE_IMAGE_ATOMIC.as_raw();
+        const VERTEX_BUFFER = vk::FormatFeatureFlags::VERTEX_BUFFER.as_raw();
+        const COLOR_ATTACHMENT = vk::FormatFeatureFlags::COLOR_ATTACHMENT.as_raw();
+        const COLOR_ATTACHMENT_BLEND = vk::FormatFeatureFlags::COLOR_ATTACHMENT_BLEND.as_raw();
+        const DEPTH_STENCIL_ATTACHMENT = vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT.as_raw();
+        const BLIT_SRC = vk::FormatFeatureFlags::BLIT_SRC.as_raw();
+        const BLIT_DST = vk::FormatFeatureFlags::BLIT_DST.as_raw();
+        const SAMPLED_IMAGE_FILTER_LINEAR = vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR.as_raw();
+        const TRANSFER_SRC = vk::FormatFeatureFlags::TRANSFER_SRC.as_raw();
+        const TRANSFER_DST = vk::FormatFeatureFlags::TRANSFER_DST.as_raw();
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FormatSupport {
+    pub linear_tiling: FormatFeatureFlags,
+    pub optimal_tiling: FormatFeatureFlags,
+    pub buffer: FormatFeatureFlags,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DeviceLimits {
+    pub max_image_dimension_1d: u32,
+    pub max_image_dimension_2d: u32,
+    pub max_image_dimension_3d: u32,
+    pub max_image_dimension_cube: u32,
+    pub max_image_array_layers: u32,
+    pub max_memory_allocation_count: u32,
+    pub max_sampler_allocation_count: u32,
+    pub max_push_constants_size: u32,
+    pub max_storage_buffer_range: u32,
+    pub max_bound_descriptor_sets: u32,
+    pub max_per_stage_descriptor_storage_images: u32,
+    pub max_per_stage_descriptor_sampled_images: u32,
+    pub max_descriptor_set_update_after_bind_storage_images: u32,
+    pub max_descriptor_set_update_after_bind_sampled_images: u32,
+    pub max_descriptor_set_update_after_bind_samplers: u32,
+    pub max_per_stage_descriptor_update_after_bind_storage_images: u32,
+    pub max_per_stage_descriptor_update_after_bind_sampled_images: u32,
+    pub max_per_stage_descriptor_update_after_bind_samplers: u32,
+    pub max_per_stage_update_after_bind_resources: u32,
+    pub max_compute_shared_memory_size: u32,
+    pub max_compute_work_group_count: [u32; 3],
+    pub max_compute_work_group_invocations: u32,
+    pub max_compute_work_group_size: [u32; 3],
+    pub max_color_attachments: u32,
+    pub max_sampler_anisotropy: f32,
+    pub min_storage_buffer_offset_alignment: u64,
+    pub optimal_buffer_copy_offset_alignment: u64,
+    pub non_coherent_atom_size: u64,
+    pub timestamp_compute_and_graphics: bool,
+    /// Nanoseconds per timestamp tick.
+    pub timestamp_period: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryHeapInfo {
+    pub index: u32,
+    pub size: u64,
+    pub device_local: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryTypeInfo {
+    pub index: u32,
+    pub heap_index: u32,
+    pub flags: MemoryFlags,
+}
+
+#[derive(Debug, Clone)]
+pub struct QueueFamilyInfo {
+    pub index: u32,
+    pub flags: QueueFlags,
+    pub queue_count: u32,
+    pub timestamp_valid_bits: u32,
+    pub min_image_transfer_granularity: Extent3D,
+}
+
+/// Everything paya knows about a physical device, handed to the selector in `Device::new`.
+#[derive(Debug, Clone)]
+pub struct PhysicalDeviceInfo {
+    /// Position of the device in the instance's enumeration order.
+    pub index: usize,
+    pub name: String,
+    pub device_type: DeviceType,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    /// The (major, minor, patch) vulkan version supported by the device.
+    pub api_version: (u32, u32, u32),
+    /// Vendor specific encoding of the driver version.
+    pub driver_version: u32,
+    pub driver_name: String,
+    pub driver_info: String,
+    pub device_uuid: [u8; vk::UUID_SIZE],
+    pub pipeline_cache_uuid: [u8; vk::UUID_SIZE],
+    pub limits: DeviceLimits,
+    pub features: DeviceFeatures,
+    pub memory_heaps: Vec<MemoryHeapInfo>,
+    pub memory_types: Vec<MemoryTypeInfo>,
+    pub queue_families: Vec<QueueFamilyInfo>,
+    pub extensions: Vec<String>,
+    pub format_support: HashMap<Format, FormatSupport>,
+    pub(crate) handle: vk::PhysicalDevice,
+}
+
+impl PhysicalDeviceInfo {
+    /// Returns `None` for devices below vulkan 1.2, the structures queried here and the
+    /// features paya builds on are core in 1.2.
+    pub(crate) fn query(
+        instance: &ash::Instance,
+        index: usize,
+        handle: vk::PhysicalDevice,
+    ) -> Result<Option<Self>> {
+        let api_version = unsafe { instance.get_physical_device_properties(handle) }.api_version;
+        if api_version < vk::API_VERSION_1_2 {
+            return Ok(None);
+        }
+
+        let mut driver_properties = vk::PhysicalDeviceDriverProperties::default();
+        let mut id_properties = vk::PhysicalDeviceIDProperties::default();
+        let mut descriptor_indexing_properties =
+            vk::PhysicalDeviceDescriptorIndexingProperties::default();
+        let mut properties2 = vk::PhysicalDeviceProperties2::default()
+            .push_next(&mut driver_properties)
+            .push_next(&mut id_properties)
+            .push_next(&mut descriptor_indexing_properties);
+        unsafe { instance.get_physical_device_properties2(handle, &mut properties2) };
+        let properties = properties2.properties;
+
+        let extensions = unsafe { instance.enumerate_device_extension_properties(handle) }?
+            .iter()
+            .filter_map(|extension| extension.extension_name_as_c_str().ok())
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+
+        let features = Self::query_features(instance, handle, &extensions);
+
+        let memory_properties = unsafe { instance.get_physical_device_memory_properties(handle) };
+        let memory_heaps = memory_properties
+            .memory_heaps_as_slice()
+            .iter()
+            .enumerate()
+            .map(|(index, heap)| MemoryHeapInfo {
+                index: index as u32,
+                size: heap.size,
+                device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+            })
+            .collect();
+        let memory_types = memory_properties
+            .memory_types_as_slice()
+            .iter()
+            .enumerate()
+            .map(|(index, ty)| MemoryTypeInfo {
+                index: index as u32,
+                heap_index: ty.heap_index,
+                flags: MemoryFlags::from_bits_truncate(ty.property_flags.as_raw()),
+            })
+            .collect();
+
+        let queue_families =
+            unsafe { instance.get_physical_device_queue_family_properties(handle) }
+                .iter()
+                .enumerate()
+                .map(|(index, family)| QueueFamilyInfo {
+                    index: index as u32,
+                    flags: QueueFlags::from_bits_truncate(family.queue_flags.as_raw()),
+                    queue_count: family.queue_count,
+                    timestamp_valid_bits: family.timestamp_valid_bits,
+                    min_image_transfer_granularity: Extent3D::new(
+                        family.min_image_transfer_granularity.width,
+                        family.min_image_transfer_granularity.height,
+                        family.min_image_transfer_granularity.depth,
+                    ),
+                })
+                .collect();
+
+        let format_support = Format::ALL
+            .iter()
+            .map(|format| {
+                let properties = unsafe {
+                    instance.get_physical_device_format_properties(handle, (*format).into())
+                };
+                (
+                    *format,
+                    FormatSupport {
+                        linear_tiling: FormatFeatureFlags::from_bits_truncate(
+                            properties.linear_tiling_features.as_raw(),
+                        ),
+                        optimal_tiling: FormatFeatureFlags::from_bits_truncate(
+                            properties.optimal_tiling_features.as_raw(),
+                        ),
+                        buffer: FormatFeatureFlags::from_bits_truncate(
+                            properties.buffer_features.as_raw(),
+                        ),
+                    },
+                )
+            })
+            .collect();
+
+        let limits = properties.limits;
+        Ok(Some(PhysicalDeviceInfo {
+            index,
+            name: c_str_to_string(properties.device_name_as_c_str()),
+            device_type: properties.device_type.into(),
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            api_version: (
+                vk::api_version_major(properties.api_version),
+                vk::api_version_minor(properties.api_version),
+                vk::api_version_patch(properties.api_version),
+            ),
+            driver_version: properties.driver_version,
+            driver_name: c_str_to_string(driver_properties.driver_name_as_c_str()),
+            driver_info: c_str_to_string(driver_properties.driver_info_as_c_str()),
+            device_uuid: id_properties.device_uuid,
+            pipeline_cache_uuid: properties.pipeline_cache_uuid,
+            limits: DeviceLimits {
+                max_image_dimension_1d: limits.max_image_dimension1_d,
+                max_image_dimension_2d: limits.max_image_dimension2_d,
+                max_image_dimension_3d: limits.max_image_dimension3_d,
+                max_image_dimension_cube: limits.max_image_dimension_cube,
+                max_image_array_layers: limits.max_image_array_layers,
+                max_memory_allocation_count: limits.max_memory_allocation_count,
+                max_sampler_allocation_count: limits.max_sampler_allocation_count,
+                max_push_constants_size: limits.max_push_constants_size,
+                max_storage_buffer_range: limits.max_storage_buffer_range,
+                max_bound_descriptor_sets: limits.max_bound_descriptor_sets,
+                max_per_stage_descriptor_storage_images: limits
+                    .max_per_stage_descriptor_storage_images,
+                max_per_stage_descriptor_sampled_images: limits
+                    .max_per_stage_descriptor_sampled_images,
+                max_descriptor_set_update_after_bind_storage_images: descriptor_indexing_properties
+                    .max_descriptor_set_update_after_bind_storage_images,
+                max_descriptor_set_update_after_bind_sampled_images: descriptor_indexing_properties
+                    .max_descriptor_set_update_after_bind_sampled_images,
+                max_descriptor_set_update_after_bind_samplers: descriptor_indexing_properties
+                    .max_descriptor_set_update_after_bind_samplers,
+                max_per_stage_descriptor_update_after_bind_storage_images:
+                    descriptor_indexing_properties
+                        .max_per_stage_descriptor_update_after_bind_storage_images,
+                max_per_stage_descriptor_update_after_bind_sampled_images:
+                    descriptor_indexing_properties
+                        .max_per_stage_descriptor_update_after_bind_sampled_images,
+                max_per_stage_descriptor_update_after_bind_samplers: descriptor_indexing_properties
+                    .max_per_stage_descriptor_update_after_bind_samplers,
+                max_per_stage_update_after_bind_resources: descriptor_indexing_properties
+                    .max_per_stage_update_after_bind_resources,
+                max_compute_shared_memory_size: limits.max_compute_shared_memory_size,
+                max_compute_work_group_count: limits.max_compute_work_group_count,
+                max_compute_work_group_invocations: limits.max_compute_work_group_invocations,
+                max_compute_work_group_size: limits.max_compute_work_group_size,
+                max_color_attachments: limits.max_color_attachments,
+                max_sampler_anisotropy: limits.max_sampler_anisotropy,
+                min_storage_buffer_offset_alignment: limits.min_storage_buffer_offset_alignment,
+                optimal_buffer_copy_offset_alignment: limits.optimal_buffer_copy_offset_alignment,
+                non_coherent_atom_size: limits.non_coherent_atom_size,
+                timestamp_compute_and_graphics: limits.timestamp_compute_and_graphics == vk::TRUE,
+                timestamp_period: limits.timestamp_period,
+            },
+            features,
+            memory_heaps,
+            memory_types,
+            queue_families,
+            extensions,
+            format_support,
+            handle,
+        }))
+    }
+
+    fn query_features(
+        instance: &ash::Instance,
+        handle: vk::PhysicalDevice,
+        extensions: &[String],
+    ) -> DeviceFeatures {
+        let has_extension = |name: &CStr| extensions.iter().any(|ext| name.to_str() == Ok(ext));
+
+        let mut vulkan11_features = vk::PhysicalDeviceVulkan11Features::default();
+        let mut vulkan12_features = vk::PhysicalDeviceVulkan12Features::default();
+        let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeatures::default();
+        let mut synchronization2_features = vk::PhysicalDeviceSynchronization2Features::default();
+        let mut robustness2_features = vk::PhysicalDeviceRobustness2FeaturesEXT::default();
+
+        let mut features2 = vk::PhysicalDeviceFeatures2::default()
+            .push_next(&mut vulkan11_features)
+            .push_next(&mut vulkan12_features);
+        // Extension structures can only be chained when the extension is supported.
+        if has_extension(ash::khr::dynamic_rendering::NAME) {
+            features2 = features2.push_next(&mut dynamic_rendering_features);
+        }
+        if has_extension(ash::khr::synchronization2::NAME) {
+            features2 = features2.push_next(&mut synchronization2_features);
+        }
+        if has_extension(ash::ext::robustness2::NAME) {
+            features2 = features2.push_next(&mut robustness2_features);
+        }
+
+        unsafe { instance.get_physical_device_features2(handle, &mut features2) };
+        let core = features2.features;
+
+        let supported = |value: vk::Bool32| value == vk::TRUE;
+        let mut features = DeviceFeatures::empty();
+        features.set(
+            DeviceFeatures::ROBUST_BUFFER_ACCESS,
+            supported(core.robust_buffer_access),
+        );
+        features.set(
+            DeviceFeatures::GEOMETRY_SHADER,
+            supported(core.geometry_shader),
+        );
+        features.set(
+            DeviceFeatures::TESSELLATION_SHADER,
+            supported(core.tessellation_shader),
+        );
+        features.set(
+            DeviceFeatures::MULTI_DRAW_INDIRECT,
+            supported(core.multi_draw_indirect),
+        );
+        features.set(DeviceFeatures::DEPTH_CLAMP, supported(core.depth_clamp));
+        features.set(
+            DeviceFeatures::FILL_MODE_NON_SOLID,
+            supported(core.fill_mode_non_solid),
+        );
+        features.set(DeviceFeatures::WIDE_LINES, supported(core.wide_lines));
+        features.set(
+            DeviceFeatures::SAMPLER_ANISOTROPY,
+            supported(core.sampler_anisotropy),
+        );
+        features.set(
+            DeviceFeatures::PIPELINE_STATISTICS_QUERY,
+            supported(core.pipeline_statistics_query),
+        );
+        features.set(
+            DeviceFeatures::SHADER_STORAGE_IMAGE_READ_WITHOUT_FORMAT,
+            supported(core.shader_storage_image_read_without_format),
+        );
+        features.set(
+            DeviceFeatures::SHADER_STORAGE_IMAGE_WRITE_WITHOUT_FORMAT,
+            supported(core.shader_storage_image_write_without_format),
+        );
+        features.set(
+            DeviceFeatures::SHADER_FLOAT64,
+            supported(core.shader_float64),
+        );
+        features.set(DeviceFeatures::SHADER_INT64, supported(core.shader_int64));
+        features.set(DeviceFeatures::SHADER_INT16, supported(core.shader_int16));
+        features.set(
+            DeviceFeatures::SHADER_STORAGE_IMAGE_EXTENDED_FORMATS,
+            supported(core.shader_storage_image_extended_formats),
+        );
+
+        features.set(
+            DeviceFeatures::STORAGE_BUFFER_16BIT_ACCESS,
+            supported(vulkan11_features.storage_buffer16_bit_access),
+        );
+        features.set(
+            DeviceFeatures::SHADER_DRAW_PARAMETERS,
+            supported(vulkan11_features.shader_draw_parameters),
+        );
+
+        features.set(
+            DeviceFeatures::STORAGE_BUFFER_8BIT_ACCESS,
+            supported(vulkan12_features.storage_buffer8_bit_access),
+        );
+        features.set(
+            DeviceFeatures::SHADER_BUFFER_INT64_ATOMICS,
+            supported(vulkan12_features.shader_buffer_int64_atomics),
+        );
+        features.set(
+            DeviceFeatures::SHADER_FLOAT16,
+            supported(vulkan12_features.shader_float16),
+        );
+        features.set(
+            DeviceFeatures::SHADER_INT8,
+            supported(vulkan12_features.shader_int8),
+        );
+        // The bindless model needs runtime sized, partially bound, update after bind arrays.
+        features.set(
+            DeviceFeatures::DESCRIPTOR_INDEXING,
+            supported(vulkan12_features.descriptor_indexing)
+                && supported(vulkan12_features.runtime_descriptor_array)
+                && supported(vulkan12_features.descriptor_binding_partially_bound)
+                && supported(vulkan12_features.descriptor_binding_storage_buffer_update_after_bind)
+                && supported(vulkan12_features.descriptor_binding_storage_image_update_after_bind)
+                && supported(vulkan12_features.descriptor_binding_sampled_image_update_after_bind)
+                && supported(vulkan12_features.shader_storage_image_array_non_uniform_indexing)
+                && supported(vulkan12_features.shader_sampled_image_array_non_uniform_indexing),
+        );
+        features.set(
+            DeviceFeatures::SCALAR_BLOCK_LAYOUT,
+            supported(vulkan12_features.scalar_block_layout),
+        );
+        features.set(
+            DeviceFeatures::HOST_QUERY_RESET,
+            supported(vulkan12_features.host_query_reset),
+        );
+        features.set(
+            DeviceFeatures::TIMELINE_SEMAPHORE,
+            supported(vulkan12_features.timeline_semaphore),
+        );
+        features.set(
+            DeviceFeatures::BUFFER_DEVICE_ADDRESS,
+            supported(vulkan12_features.buffer_device_address),
+        );
+
+        features.set(
+            DeviceFeatures::DYNAMIC_RENDERING,
+            supported(dynamic_rendering_features.dynamic_rendering),
+        );
+        features.set(
+            DeviceFeatures::SYNCHRONIZATION_2,
+            supported(synchronization2_features.synchronization2),
+        );
+        features.set(
+            DeviceFeatures::ROBUSTNESS_2,
+            supported(robustness2_features.robust_buffer_access2)
+                && supported(robustness2_features.robust_image_access2),
+        );
+
+        features
+    }
+
+    pub fn supports_extension(&self, name: &str) -> bool {
+        self.extensions.iter().any(|extension| extension == name)
+    }
+
+    pub fn supports_features(&self, features: DeviceFeatures) -> bool {
+        self.features.contains(features)
+    }
+
+    pub fn format_support(&self, format: Format) -> FormatSupport {
+        self.format_support[&format]
+    }
+
+    /// Total size of all device local heaps in bytes.
+    pub fn device_local_memory(&self) -> u64 {
+        self.memory_heaps
+            .iter()
+            .filter(|heap| heap.device_local)
+            .map(|heap| heap.size)
+            .sum()
+    }
+
+    /// Whether `PAYA_DEVICE` names this device, either by index or a case insensitive part of
+    /// its name.
+    pub(crate) fn matches_override(&self, value: &str) -> bool {
+        match value.trim().parse::<usize>() {
+            Ok(index) => index == self.index,
+            Err(_) => self
+                .name
+                .to_lowercase()
+                .contains(&value.trim().to_lowercase()),
+        }
+    }
+}
+
+fn c_str_to_string(c_str: std::result::Result<&CStr, std::ffi::FromBytesUntilNulError>) -> String {
+    c_str
+        .map(|c_str| c_str.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
@@ -1,10 +1,17 @@
-use std::sync::Arc;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use ash::vk::{self, Extent2D, ShaderStageFlags};
 
 use crate::{
-    common::{AttachmentLoadOp, AttachmentStoreOp, Format, ImageLayout, PolygonMode, Topology},
+    common::{
+        AttachmentLoadOp, AttachmentStoreOp, CompareOp, Format, ImageLayout, PolygonMode,
+        StencilOp, Topology,
+    },
     device::{Device, DeviceInner},
+    error::{Error, Result},
     shader::ShaderInfo,
 };
 
@@ -25,6 +32,152 @@ impl Drop for PipelineInner {
     }
 }
 
+const PIPELINE_CACHE_MAGIC: [u8; 4] = *b"PAYA";
+const PIPELINE_CACHE_VERSION: u32 = 1;
+// magic, version, device uuid, driver version, pipeline cache uuid, data size, data hash
+const PIPELINE_CACHE_HEADER_SIZE: usize = 4 + 4 + vk::UUID_SIZE + 4 + vk::UUID_SIZE + 8 + 8;
+
+/// The vulkan pipeline cache used by every pipeline the device creates. Saved caches are
+/// prefixed with a header identifying the device and driver they were created with, a cache
+/// from a different device or driver is ignored instead of being handed to the driver.
+pub struct PipelineCache {
+    device_dep: Arc<DeviceInner>,
+    pub(crate) handle: vk::PipelineCache,
+}
+
+impl PipelineCache {
+    pub(crate) fn new(device_dep: Arc<DeviceInner>) -> Result<Self> {
+        let handle = Self::create_vk_cache(&device_dep, &[])?;
+        device_dep.set_debug_name(handle, "paya pipeline cache");
+        Ok(PipelineCache { device_dep, handle })
+    }
+
+    fn create_vk_cache(device_inner: &DeviceInner, data: &[u8]) -> Result<vk::PipelineCache> {
+        let create_info = vk::PipelineCacheCreateInfo::default().initial_data(data);
+        Ok(unsafe {
+            device_inner
+                .device
+                .create_pipeline_cache(&create_info, None)
+        }?)
+    }
+
+    pub fn handle(&self) -> vk::PipelineCache {
+        self.handle
+    }
+
+    /// Merges a cache saved with `save` into this one. Returns `false` when the file doesn't
+    /// exist or was written by another device, driver or paya version, the cache is left
+    /// untouched in that case. Only called while creating the device, merging needs exclusive
+    /// access since pipelines can otherwise be created from other threads at the same time.
+    pub(crate) fn load(&mut self, path: impl AsRef<Path>) -> Result<bool> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(error) => return Err(error.into()),
+        };
+
+        let Some(data) = self.validate(&bytes) else {
+            return Ok(false);
+        };
+
+        let loaded_cache = Self::create_vk_cache(&self.device_dep, data)?;
+        let result = unsafe {
+            self.device_dep
+                .device
+                .merge_pipeline_caches(self.handle, &[loaded_cache])
+        };
+        unsafe {
+            self.device_dep
+                .device
+                .destroy_pipeline_cache(loaded_cache, None)
+        };
+        result?;
+
+        Ok(true)
+    }
+
+    /// Writes the cache to `path`, going through a temporary file so a crash never leaves a
+    /// truncated cache behind.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let data = self.data()?;
+        let info = &self.device_dep.physical_device_info;
+
+        let mut bytes = Vec::with_capacity(PIPELINE_CACHE_HEADER_SIZE + data.len());
+        bytes.extend_from_slice(&PIPELINE_CACHE_MAGIC);
+        bytes.extend_from_slice(&PIPELINE_CACHE_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&info.device_uuid);
+        bytes.extend_from_slice(&info.driver_version.to_le_bytes());
+        bytes.extend_from_slice(&info.pipeline_cache_uuid);
+        bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&fnv1a_hash(&data).to_le_bytes());
+        bytes.extend_from_slice(&data);
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let temp_path = temp_path(path);
+        std::fs::write(&temp_path, &bytes)?;
+        std::fs::rename(&temp_path, path)?;
+
+        Ok(())
+    }
+
+    /// The raw driver cache data without paya's header.
+    pub fn data(&self) -> Result<Vec<u8>> {
+        Ok(unsafe { self.device_dep.device.get_pipeline_cache_data(self.handle) }?)
+    }
+
+    /// Returns the driver data if the header matches this device and driver.
+    fn validate<'a>(&self, bytes: &'a [u8]) -> Option<&'a [u8]> {
+        let info = &self.device_dep.physical_device_info;
+        if bytes.len() < PIPELINE_CACHE_HEADER_SIZE {
+            return None;
+        }
+
+        let (header, data) = bytes.split_at(PIPELINE_CACHE_HEADER_SIZE);
+        let (magic, header) = header.split_at(4);
+        let (version, header) = header.split_at(4);
+        let (device_uuid, header) = header.split_at(vk::UUID_SIZE);
+        let (driver_version, header) = header.split_at(4);
+        let (pipeline_cache_uuid, header) = header.split_at(vk::UUID_SIZE);
+        let (data_size, data_hash) = header.split_at(8);
+
+        let valid = magic == PIPELINE_CACHE_MAGIC
+            && u32::from_le_bytes(version.try_into().unwrap()) == PIPELINE_CACHE_VERSION
+            && device_uuid == info.device_uuid
+            && u32::from_le_bytes(driver_version.try_into().unwrap()) == info.driver_version
+            && pipeline_cache_uuid == info.pipeline_cache_uuid
+            && u64::from_le_bytes(data_size.try_into().unwrap()) == data.len() as u64
+            && u64::from_le_bytes(data_hash.try_into().unwrap()) == fnv1a_hash(data);
+
+        valid.then_some(data)
+    }
+}
+
+impl Drop for PipelineCache {
+    fn drop(&mut self) {
+        unsafe {
+            self.device_dep
+                .device
+                .destroy_pipeline_cache(self.handle, None)
+        };
+    }
+}
+
+// Appended instead of replacing the extension so it never collides with the target.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".paya-tmp");
+    temp_path.into()
+}
+
+fn fnv1a_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
 pub enum RasterVertexAttributeType {
     Float,
     Vec2,
@@ -53,6 +206,7 @@ impl RasterVertexAttributeType {
 }
 
 pub struct RasterPipelineInfo {
+    pub name: String,
     pub vertex_shader: ShaderInfo,
     pub fragment_shader: ShaderInfo,
     pub push_constant_size: u32,
@@ -65,6 +219,119 @@ pub struct RasterPipelineInfo {
 
     // Only support 1 subpass for now
     pub color_attachments: Vec<Format>,
+    /// `None` renders without a depth or stencil attachment.
+    pub depth_stencil: Option<DepthStencilInfo>,
+}
+
+/// What a raster pipeline does with the stencil of one face.
+#[derive(Debug, Clone, Copy)]
+pub struct StencilOpState {
+    pub fail_op: StencilOp,
+    pub pass_op: StencilOp,
+    pub depth_fail_op: StencilOp,
+    pub compare_op: CompareOp,
+    pub compare_mask: u32,
+    pub write_mask: u32,
+    pub reference: u32,
+}
+
+impl Default for StencilOpState {
+    fn default() -> Self {
+        StencilOpState {
+            fail_op: StencilOp::Keep,
+            pass_op: StencilOp::Keep,
+            depth_fail_op: StencilOp::Keep,
+            compare_op: CompareOp::Always,
+            compare_mask: 0xff,
+            write_mask: 0xff,
+            reference: 0,
+        }
+    }
+}
+
+impl Into<vk::StencilOpState> for StencilOpState {
+    fn into(self) -> vk::StencilOpState {
+        vk::StencilOpState::default()
+            .fail_op(self.fail_op.into())
+            .pass_op(self.pass_op.into())
+            .depth_fail_op(self.depth_fail_op.into())
+            .compare_op(self.compare_op.into())
+            .compare_mask(self.compare_mask)
+            .write_mask(self.write_mask)
+            .reference(self.reference)
+    }
+}
+
+/// The depth attachment format and the depth and stencil tests of a raster pipeline. The
+/// stencil attachment uses the same format, stencil tests need a format with stencil.
+#[derive(Debug, Clone, Copy)]
+pub struct DepthStencilInfo {
+    pub format: Format,
+    pub depth_test: bool,
+    pub depth_write: bool,
+    pub depth_compare_op: CompareOp,
+    pub stencil_test: bool,
+    pub stencil_front: StencilOpState,
+    pub stencil_back: StencilOpState,
+}
+
+impl DepthStencilInfo {
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Enables the depth test with `compare_op`, use `CompareOp::Greater` for reverse-z.
+    pub fn depth_test(mut self, compare_op: CompareOp) -> Self {
+        self.depth_test = true;
+        self.depth_compare_op = compare_op;
+        self
+    }
+
+    pub fn depth_write(mut self, depth_write: bool) -> Self {
+        self.depth_write = depth_write;
+        self
+    }
+
+    /// Checks that the format has the aspects the tests need.
+    pub(crate) fn validate(&self, pipeline_name: &str) -> Result<()> {
+        if !self.format.has_depth() {
+            return Err(Error::InvalidUsage(format!(
+                "Raster pipeline {} has a {:?} depth attachment, which isn't a depth format",
+                pipeline_name, self.format
+            )));
+        }
+        if self.stencil_test && !self.format.has_stencil() {
+            return Err(Error::InvalidUsage(format!(
+                "Raster pipeline {} tests stencil but its {:?} depth attachment has no stencil",
+                pipeline_name, self.format
+            )));
+        }
+        Ok(())
+    }
+
+    /// Enables the stencil test with the same state for both faces.
+    pub fn stencil_test(mut self, state: StencilOpState) -> Self {
+        self.stencil_test = true;
+        self.stencil_front = state;
+        self.stencil_back = state;
+        self
+    }
+}
+
+impl Default for DepthStencilInfo {
+    /// A `D32Sfloat` attachment with depth test and write enabled and `CompareOp::Less`.
+    fn default() -> Self {
+        DepthStencilInfo {
+            format: Format::D32Sfloat,
+            depth_test: true,
+            depth_write: true,
+            depth_compare_op: CompareOp::Less,
+            stencil_test: false,
+            stencil_front: StencilOpState::default(),
+            stencil_back: StencilOpState::default(),
+        }
+    }
 }
 
 pub struct RasterPipeline {
@@ -72,6 +339,7 @@ pub struct RasterPipeline {
 }
 
 pub struct ComputePipelineInfo {
+    pub name: String,
     pub shader: ShaderInfo,
     pub push_constant_size: u32,
 }
@@ -80,7 +348,7 @@ pub struct ComputePipeline {
     pub(crate) inner: PipelineInner,
 }
 
-pub trait Pipeline {
+pub trait Pipeline: Send + Sync {
     fn inner(&self) -> &PipelineInner;
     fn shader_stages(&self) -> ShaderStageFlags;
 }
@@ -103,3 +371,43 @@ impl Pipeline for ComputePipeline {
         ShaderStageFlags::COMPUTE
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_temp_path_keeps_the_extension() {
+        assert_eq!(
+            temp_path(Path::new("cache/pipelines.tmp")),
+            Path::new("cache/pipelines.tmp.paya-tmp")
+        );
+        assert_eq!(
+            temp_path(Path::new("pipelines.bin")),
+            Path::new("pipelines.bin.paya-tmp")
+        );
+    }
+
+    #[test]
+    fn depth_stencil_needs_a_depth_format() {
+        let info = DepthStencilInfo::default().format(Format::R8G8B8A8Unorm);
+        assert!(info.validate("test").is_err());
+        assert!(DepthStencilInfo::default().validate("test").is_ok());
+    }
+
+    #[test]
+    fn stencil_test_needs_a_stencil_format() {
+        for format in [Format::D16Unorm, Format::D32Sfloat] {
+            let info = DepthStencilInfo::default()
+                .format(format)
+                .stencil_test(StencilOpState::default());
+            assert!(info.validate("test").is_err());
+        }
+        for format in [Format::D24UnormS8Uint, Format::D32SfloatS8Uint] {
+            let info = DepthStencilInfo::default()
+                .format(format)
+                .stencil_test(StencilOpState::default());
+            assert!(info.validate("test").is_ok());
+        }
+    }
+}
@@ -1,4 +1,21 @@
-pub const SHADER_PREAMBLE_GLSL: &str = "\
+use crate::{
+    common::{Format, ImageViewType},
+    gpu_resources::BindlessCapacities,
+};
+
+const VIEW_TYPES: [ImageViewType; 5] = [
+    ImageViewType::Type1D,
+    ImageViewType::Type2D,
+    ImageViewType::Type2DArray,
+    ImageViewType::Type3D,
+    ImageViewType::Cube,
+];
+
+/// Builds the glsl preamble every shader is compiled with. The bindless arrays are sized to
+/// `capacities`, which have to match the device the shaders run on.
+pub fn shader_preamble_glsl(capacities: &BindlessCapacities) -> String {
+    format!(
+        "\
 #version 450
 
 #extension GL_EXT_shader_explicit_arithmetic_types_int8 : enable
@@ -7,15 +24,22 @@ pub const SHADER_PREAMBLE_GLSL: &str = "\
 #extension GL_EXT_shader_explicit_arithmetic_types_int64 : enable
 #extension GL_EXT_buffer_reference : enable
 #extension GL_EXT_debug_printf : enable
+#extension GL_EXT_nonuniform_qualifier : enable
+
+#define PAYA_MAX_BUFFERS {}
+#define PAYA_MAX_IMAGES {}
+#define PAYA_MAX_SAMPLERS {}
 
-layout (set = 0, binding = 0) readonly buffer BufferAddresses {
-  uint64_t addresses[];
-} u_addresses;
-layout (set = 0, binding = 1, rgba8) uniform image2D u_images[100];
+layout (set = 0, binding = 0) readonly buffer BufferAddresses {{
+  uint64_t addresses[PAYA_MAX_BUFFERS];
+}} u_addresses;
+{}
+{}
+layout (set = 0, binding = 3) uniform sampler u_samplers[PAYA_MAX_SAMPLERS];
 
-struct ResourceId {
+struct ResourceId {{
   uint32_t index;
-};
+}};
 
 #define DECL_PUSH_CONSTANTS layout(push_constant) uniform PushConstants
 #define DECL_BUFFER(alignment) layout(std430, buffer_reference, buffer_reference_align = alignment) readonly buffer
@@ -24,5 +48,96 @@ struct ResourceId {
 #define DECL_BUFFER_COHERENT(alignment) layout(std430, buffer_reference, buffer_reference_align = alignment) coherent buffer
 
 #define get_buffer(id, type) type(u_addresses.addresses[id.index]);
-#define get_storage_image(id) u_images[id.index]
-";
+#define get_sampler(id) u_samplers[nonuniformEXT(id.index)]
+#define sample_texture_compare(image_id, sampler_id, uv, reference) texture(sampler2DShadow(get_texture(image_id), get_sampler(sampler_id)), vec3(uv, reference))
+",
+        capacities.buffers,
+        capacities.images,
+        capacities.samplers,
+        storage_image_declarations(),
+        texture_declarations(),
+    )
+}
+
+/// Declares an aliased storage image array on the storage image binding for every view type
+/// and format qualifier in `Format`, with a `get_storage_image<suffix>_<qualifier>(id)`
+/// accessor for each. `get_storage_image<suffix>(id)` accesses `rgba8` images.
+fn storage_image_declarations() -> String {
+    let mut qualifiers = Format::ALL
+        .iter()
+        .filter_map(|format| format.storage_image_qualifier())
+        .collect::<Vec<_>>();
+    qualifiers.dedup();
+
+    let mut declarations = String::new();
+    for view_type in VIEW_TYPES {
+        let (image_type, suffix) = glsl_view_type(view_type);
+        for (qualifier, prefix) in &qualifiers {
+            declarations += &format!(
+                "layout (set = 0, binding = 1, {qualifier}) uniform {prefix}image{image_type} u_images{suffix}_{qualifier}[PAYA_MAX_IMAGES];\n\
+                 #define get_storage_image{suffix}_{qualifier}(id) u_images{suffix}_{qualifier}[id.index]\n"
+            );
+        }
+        declarations +=
+            &format!("#define get_storage_image{suffix}(id) get_storage_image{suffix}_rgba8(id)\n");
+    }
+
+    declarations
+}
+
+/// Declares an aliased sampled image array on the sampled image binding for every view type,
+/// with `get_texture<suffix>(id)`, `sample_texture<suffix>(image_id, sampler_id, coords)` and
+/// `sample_texture<suffix>_lod(image_id, sampler_id, coords, lod)` accessors.
+fn texture_declarations() -> String {
+    let mut declarations = String::new();
+    for view_type in VIEW_TYPES {
+        let (image_type, suffix) = glsl_view_type(view_type);
+        declarations += &format!(
+            "layout (set = 0, binding = 2) uniform texture{image_type} u_textures{suffix}[PAYA_MAX_IMAGES];\n\
+             #define get_texture{suffix}(id) u_textures{suffix}[nonuniformEXT(id.index)]\n\
+             #define sample_texture{suffix}(image_id, sampler_id, coords) texture(sampler{image_type}(get_texture{suffix}(image_id), get_sampler(sampler_id)), coords)\n\
+             #define sample_texture{suffix}_lod(image_id, sampler_id, coords, lod) textureLod(sampler{image_type}(get_texture{suffix}(image_id), get_sampler(sampler_id)), coords, lod)\n"
+        );
+    }
+
+    declarations
+}
+
+/// The glsl image type and accessor suffix of a view type, 2D accessors have no suffix.
+fn glsl_view_type(view_type: ImageViewType) -> (&'static str, &'static str) {
+    match view_type {
+        ImageViewType::Type1D => ("1D", "_1d"),
+        ImageViewType::Type2D => ("2D", ""),
+        ImageViewType::Type2DArray => ("2DArray", "_2d_array"),
+        ImageViewType::Type3D => ("3D", "_3d"),
+        ImageViewType::Cube => ("Cube", "_cube"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preamble_arrays_are_sized_to_the_capacities() {
+        let preamble = shader_preamble_glsl(&BindlessCapacities {
+            buffers: 12,
+            images: 34,
+            samplers: 56,
+        });
+        assert!(preamble.contains("#define PAYA_MAX_BUFFERS 12\n"));
+        assert!(preamble.contains("#define PAYA_MAX_IMAGES 34\n"));
+        assert!(preamble.contains("#define PAYA_MAX_SAMPLERS 56\n"));
+        assert!(preamble.contains("u_samplers[PAYA_MAX_SAMPLERS]"));
+    }
+
+    #[test]
+    fn preamble_declares_an_accessor_per_storage_format() {
+        let preamble = shader_preamble_glsl(&BindlessCapacities::default());
+        for format in Format::ALL {
+            if let Some((qualifier, _)) = format.storage_image_qualifier() {
+                assert!(preamble.contains(&format!("get_storage_image_{}(id)", qualifier)));
+            }
+        }
+    }
+}
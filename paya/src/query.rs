@@ -0,0 +1,212 @@
+use std::sync::Arc;
+
+use ash::vk;
+use bitflags::bitflags;
+
+use crate::{
+    device::{Device, DeviceInner, Submission},
+    error::{Error, Result},
+    physical_device::DeviceFeatures,
+};
+
+bitflags! {
+    /// The counters a pipeline statistics query collects, results hold one value per counter in
+    /// the order of these flags.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct PipelineStatisticFlags: u32 {
+        const INPUT_ASSEMBLY_VERTICES = vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES.as_raw();
+        const INPUT_ASSEMBLY_PRIMITIVES = vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES.as_raw();
+        const VERTEX_SHADER_INVOCATIONS = vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.as_raw();
+        const GEOMETRY_SHADER_INVOCATIONS = vk::QueryPipelineStatisticFlags::GEOMETRY_SHADER_INVOCATIONS.as_raw();
+        const GEOMETRY_SHADER_PRIMITIVES = vk::QueryPipelineStatisticFlags::GEOMETRY_SHADER_PRIMITIVES.as_raw();
+        const CLIPPING_INVOCATIONS = vk::QueryPipelineStatisticFlags::CLIPPING_INVOCATIONS.as_raw();
+        const CLIPPING_PRIMITIVES = vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES.as_raw();
+        const FRAGMENT_SHADER_INVOCATIONS = vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.as_raw();
+        const TESSELLATION_CONTROL_SHADER_PATCHES = vk::QueryPipelineStatisticFlags::TESSELLATION_CONTROL_SHADER_PATCHES.as_raw();
+        const TESSELLATION_EVALUATION_SHADER_INVOCATIONS = vk::QueryPipelineStatisticFlags::TESSELLATION_EVALUATION_SHADER_INVOCATIONS.as_raw();
+        const COMPUTE_SHADER_INVOCATIONS = vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS.as_raw();
+    }
+}
+
+impl Into<vk::QueryPipelineStatisticFlags> for PipelineStatisticFlags {
+    fn into(self) -> vk::QueryPipelineStatisticFlags {
+        vk::QueryPipelineStatisticFlags::from_raw(self.bits())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QueryType {
+    /// Written with `CommandRecorder::write_timestamp`, results are in nanoseconds.
+    Timestamp,
+    /// Requires `DeviceFeatures::PIPELINE_STATISTICS_QUERY`.
+    PipelineStatistics(PipelineStatisticFlags),
+    /// The number of samples that passed the depth and stencil tests.
+    Occlusion,
+}
+
+impl QueryType {
+    /// The number of result values each query produces.
+    pub fn values_per_query(&self) -> usize {
+        match self {
+            QueryType::Timestamp | QueryType::Occlusion => 1,
+            QueryType::PipelineStatistics(flags) => flags.bits().count_ones() as usize,
+        }
+    }
+}
+
+impl Into<vk::QueryType> for QueryType {
+    fn into(self) -> vk::QueryType {
+        match self {
+            QueryType::Timestamp => vk::QueryType::TIMESTAMP,
+            QueryType::PipelineStatistics(_) => vk::QueryType::PIPELINE_STATISTICS,
+            QueryType::Occlusion => vk::QueryType::OCCLUSION,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QueryPoolInfo {
+    pub name: String,
+    pub query_type: QueryType,
+    pub query_count: u32,
+}
+
+/// Queries have to be reset with `CommandRecorder::reset_query_pool` before they are written.
+pub struct QueryPool {
+    device_dep: Arc<DeviceInner>,
+    handle: vk::QueryPool,
+    info: QueryPoolInfo,
+}
+
+impl QueryPool {
+    pub(crate) fn new(device: &Device, info: QueryPoolInfo) -> Result<Self> {
+        if matches!(info.query_type, QueryType::PipelineStatistics(_))
+            && !device.is_feature_enabled(DeviceFeatures::PIPELINE_STATISTICS_QUERY)
+        {
+            return Err(Error::InvalidUsage(
+                "pipeline statistics queries need DeviceFeatures::PIPELINE_STATISTICS_QUERY"
+                    .to_owned(),
+            ));
+        }
+
+        let mut create_info = vk::QueryPoolCreateInfo::default()
+            .query_type(info.query_type.into())
+            .query_count(info.query_count);
+        if let QueryType::PipelineStatistics(flags) = info.query_type {
+            create_info = create_info.pipeline_statistics(flags.into());
+        }
+
+        let handle = unsafe { device.handle().create_query_pool(&create_info, None) }?;
+        device.inner().set_debug_name(handle, &info.name);
+
+        Ok(QueryPool {
+            device_dep: device.create_dep(),
+            handle,
+            info,
+        })
+    }
+
+    pub fn handle(&self) -> vk::QueryPool {
+        self.handle
+    }
+
+    pub fn info(&self) -> &QueryPoolInfo {
+        &self.info
+    }
+
+    pub(crate) fn check_range(&self, first_query: u32, query_count: u32) -> Result<()> {
+        if first_query as u64 + query_count as u64 > self.info.query_count as u64 {
+            return Err(Error::InvalidUsage(format!(
+                "queries {}..{} are out of bounds of query pool {} ({} queries)",
+                first_query,
+                first_query as u64 + query_count as u64,
+                self.info.name,
+                self.info.query_count
+            )));
+        }
+        Ok(())
+    }
+
+    /// Reads the results of `query_count` queries written by `submission`, `None` until the
+    /// submission has completed. Each query has `QueryType::values_per_query` values,
+    /// timestamps are masked to the queue family's valid bits and converted to nanoseconds.
+    pub fn results(
+        &self,
+        device: &Device,
+        submission: Submission,
+        first_query: u32,
+        query_count: u32,
+    ) -> Result<Option<Vec<u64>>> {
+        self.check_range(first_query, query_count)?;
+        let timestamp_valid_bits = self.device_dep.timestamp_valid_bits(submission.queue);
+        if self.info.query_type == QueryType::Timestamp && timestamp_valid_bits == 0 {
+            return Err(Error::InvalidUsage(format!(
+                "the {:?} queue doesn't support timestamps",
+                submission.queue
+            )));
+        }
+        if !device.is_complete(submission)? {
+            return Ok(None);
+        }
+
+        let values_per_query = self.info.query_type.values_per_query();
+        let mut results = vec![0u64; query_count as usize * values_per_query];
+        // Called directly since ash assumes a single value per query. The submission has
+        // completed so every query it wrote is available.
+        unsafe {
+            (self.device_dep.device.fp_v1_0().get_query_pool_results)(
+                self.device_dep.device.handle(),
+                self.handle,
+                first_query,
+                query_count,
+                std::mem::size_of_val(results.as_slice()),
+                results.as_mut_ptr().cast(),
+                (values_per_query * std::mem::size_of::<u64>()) as vk::DeviceSize,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        }
+        .result()?;
+
+        if self.info.query_type == QueryType::Timestamp {
+            let timestamp_period = self
+                .device_dep
+                .physical_device_properties
+                .limits
+                .timestamp_period as f64;
+            for result in &mut results {
+                *result = timestamp_to_nanoseconds(*result, timestamp_valid_bits, timestamp_period);
+            }
+        }
+
+        Ok(Some(results))
+    }
+}
+
+/// Drops the bits the queue family leaves undefined before converting ticks to nanoseconds.
+fn timestamp_to_nanoseconds(ticks: u64, valid_bits: u32, timestamp_period: f64) -> u64 {
+    let mask = u64::MAX.checked_shr(64 - valid_bits).unwrap_or(0);
+    ((ticks & mask) as f64 * timestamp_period) as u64
+}
+
+impl Drop for QueryPool {
+    fn drop(&mut self) {
+        unsafe {
+            self.device_dep.device.destroy_query_pool(self.handle, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_are_masked_to_valid_bits() {
+        assert_eq!(timestamp_to_nanoseconds(u64::MAX, 64, 1.0), u64::MAX);
+        assert_eq!(
+            timestamp_to_nanoseconds(0xffff_0000_0000_0010, 36, 1.0),
+            0x10
+        );
+        assert_eq!(timestamp_to_nanoseconds(0xff, 4, 2.5), 37);
+    }
+}
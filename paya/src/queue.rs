@@ -0,0 +1,111 @@
+use ash::vk;
+
+use crate::{
+    error::{Error, Result},
+    physical_device::{PhysicalDeviceInfo, QueueFlags},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum QueueType {
+    Graphics,
+    /// Runs compute work alongside the graphics queue when the device has a compute only family.
+    Compute,
+    /// Runs copies alongside the other queues when the device has a transfer only family.
+    Transfer,
+}
+
+impl QueueType {
+    pub const ALL: [QueueType; 3] = [QueueType::Graphics, QueueType::Compute, QueueType::Transfer];
+
+    /// Pipeline stages that commands on this queue type can use in barriers.
+    pub(crate) fn supported_stages(self) -> vk::PipelineStageFlags {
+        let common = vk::PipelineStageFlags::TOP_OF_PIPE
+            | vk::PipelineStageFlags::BOTTOM_OF_PIPE
+            | vk::PipelineStageFlags::TRANSFER
+            | vk::PipelineStageFlags::HOST
+            | vk::PipelineStageFlags::ALL_COMMANDS;
+
+        match self {
+            QueueType::Graphics => vk::PipelineStageFlags::from_raw(!0),
+            QueueType::Compute => {
+                common
+                    | vk::PipelineStageFlags::DRAW_INDIRECT
+                    | vk::PipelineStageFlags::COMPUTE_SHADER
+            }
+            QueueType::Transfer => common,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Queue {
+    pub(crate) handle: vk::Queue,
+    pub(crate) family_index: u32,
+}
+
+impl Queue {
+    pub fn handle(&self) -> vk::Queue {
+        self.handle
+    }
+
+    pub fn family_index(&self) -> u32 {
+        self.family_index
+    }
+}
+
+/// The queue family used for every queue type, indexed by `QueueType`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct QueueFamilies {
+    pub(crate) family_indices: [u32; 3],
+}
+
+impl QueueFamilies {
+    /// Prefers families dedicated to compute and transfer so that work can overlap with
+    /// graphics, falling back to the graphics family when there are none.
+    pub(crate) fn select(info: &PhysicalDeviceInfo) -> Result<Self> {
+        let find = |required: QueueFlags, excluded: QueueFlags| {
+            info.queue_families
+                .iter()
+                .find(|family| {
+                    family.queue_count > 0
+                        && family.flags.contains(required)
+                        && !family.flags.intersects(excluded)
+                })
+                .map(|family| family.index)
+        };
+
+        let graphics = find(
+            QueueFlags::GRAPHICS | QueueFlags::COMPUTE,
+            QueueFlags::empty(),
+        )
+        .ok_or_else(|| {
+            Error::NoSuitableDevice(format!(
+                "{} has no queue family supporting graphics and compute",
+                info.name
+            ))
+        })?;
+        let compute = find(QueueFlags::COMPUTE, QueueFlags::GRAPHICS).unwrap_or(graphics);
+        let transfer = find(
+            QueueFlags::TRANSFER,
+            QueueFlags::GRAPHICS | QueueFlags::COMPUTE,
+        )
+        .or_else(|| find(QueueFlags::TRANSFER, QueueFlags::GRAPHICS))
+        .unwrap_or(compute);
+
+        Ok(QueueFamilies {
+            family_indices: [graphics, compute, transfer],
+        })
+    }
+
+    pub(crate) fn family_index(&self, queue_type: QueueType) -> u32 {
+        self.family_indices[queue_type as usize]
+    }
+
+    /// Every distinct family that needs a queue created.
+    pub(crate) fn unique_family_indices(&self) -> Vec<u32> {
+        let mut family_indices = self.family_indices.to_vec();
+        family_indices.sort();
+        family_indices.dedup();
+        family_indices
+    }
+}
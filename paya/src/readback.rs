@@ -0,0 +1,243 @@
+use std::ops::Range;
+
+use ash::vk;
+
+use crate::{
+    allocator::MemoryLocation,
+    command_recorder::CommandRecorder,
+    common::{BufferUsageFlags, ImageLayout, ImageUsageFlags, SampleCount},
+    device::Device,
+    error::{Error, Result},
+    gpu_resources::{BufferId, BufferInfo, ImageId},
+};
+
+/// Copies `range` bytes of the buffer into a readback buffer and blocks until the copy has
+/// completed.
+pub(crate) fn read_buffer(device: &Device, id: BufferId, range: Range<u64>) -> Result<Vec<u8>> {
+    let (buffer_name, buffer_size, buffer_usage) = {
+        let buffer = device.get_buffer(id)?;
+        (buffer.info.name.clone(), buffer.size, buffer.info.usage)
+    };
+    if !buffer_usage.contains(BufferUsageFlags::TRANSFER_SRC) {
+        return Err(Error::InvalidUsage(format!(
+            "Buffer {} needs TRANSFER_SRC usage to be read back",
+            buffer_name
+        )));
+    }
+    if range.start > range.end || range.end > buffer_size {
+        return Err(Error::InvalidUsage(format!(
+            "Readback of bytes {:?} is out of bounds of buffer {} ({} bytes)",
+            range, buffer_name, buffer_size
+        )));
+    }
+    let size = range.end - range.start;
+    if size == 0 {
+        return Ok(Vec::new());
+    }
+
+    read_back(
+        device,
+        &format!("{} readback", buffer_name),
+        size,
+        |recorder, dst| recorder.copy_buffer_to_buffer(device, id, range.start, dst, 0, size),
+    )
+}
+
+/// Copies a single mip level and array layer into a readback buffer, the texels are tightly
+/// packed rows. The image is moved out of `layout` for the copy and back into it afterwards.
+pub(crate) fn read_image(
+    device: &Device,
+    id: ImageId,
+    mip: u32,
+    layer: u32,
+    layout: ImageLayout,
+) -> Result<Vec<u8>> {
+    let (image_handle, info) = {
+        let image = device.get_image(id)?;
+        (image.handle, image.info.clone())
+    };
+    if !info.usage.contains(ImageUsageFlags::TRANSFER_SRC) {
+        return Err(Error::InvalidUsage(format!(
+            "Image {} needs TRANSFER_SRC usage to be read back",
+            info.name
+        )));
+    }
+    if mip >= info.mip_levels || layer >= info.array_layers {
+        return Err(Error::InvalidUsage(format!(
+            "Mip level {} of layer {} is out of bounds of image {} ({} mip levels, {} array \
+             layers)",
+            mip, layer, info.name, info.mip_levels, info.array_layers
+        )));
+    }
+    if info.samples != SampleCount::Type1 {
+        return Err(Error::InvalidUsage(format!(
+            "Image {} is multisampled, resolve it before reading it back",
+            info.name
+        )));
+    }
+    // Copies only cover one aspect, the depth of combined formats is packed differently than
+    // in the image.
+    if info.format.has_stencil() {
+        return Err(Error::InvalidUsage(format!(
+            "Image {} has a stencil aspect, only color and depth only images can be read back",
+            info.name
+        )));
+    }
+    let aspect_mask = info.format.aspect_mask();
+
+    let extent: vk::Extent3D = info.mip_extent(mip).into();
+    let size = extent.width as u64
+        * extent.height as u64
+        * extent.depth as u64
+        * info.format.bytes_per_texel() as u64;
+
+    let subresource_range = vk::ImageSubresourceRange::default()
+        .aspect_mask(aspect_mask)
+        .base_mip_level(mip)
+        .level_count(1)
+        .base_array_layer(layer)
+        .layer_count(1);
+    let layout: vk::ImageLayout = layout.into();
+
+    read_back(
+        device,
+        &format!("{} readback", info.name),
+        size,
+        |recorder, dst| {
+            let dst_handle = device.get_buffer(dst)?.handle;
+
+            let to_transfer = vk::ImageMemoryBarrier::default()
+                .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                .old_layout(layout)
+                .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image_handle)
+                .subresource_range(subresource_range);
+            let region = vk::BufferImageCopy::default()
+                .buffer_offset(0)
+                .image_subresource(
+                    vk::ImageSubresourceLayers::default()
+                        .aspect_mask(aspect_mask)
+                        .mip_level(mip)
+                        .base_array_layer(layer)
+                        .layer_count(1),
+                )
+                .image_extent(extent);
+            let from_transfer = vk::ImageMemoryBarrier::default()
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE)
+                .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .new_layout(layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image_handle)
+                .subresource_range(subresource_range);
+
+            unsafe {
+                let command_buffer = recorder.handle();
+                device.handle().cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[to_transfer],
+                );
+                device.handle().cmd_copy_image_to_buffer(
+                    command_buffer,
+                    image_handle,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    dst_handle,
+                    &[region],
+                );
+                device.handle().cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[from_transfer],
+                );
+            }
+
+            Ok(())
+        },
+    )
+}
+
+/// Records `copy` into a `size` byte readback buffer on the graphics queue, waits for it and
+/// returns the buffer's contents.
+fn read_back(
+    device: &Device,
+    name: &str,
+    size: u64,
+    copy: impl FnOnce(&mut CommandRecorder, BufferId) -> Result<()>,
+) -> Result<Vec<u8>> {
+    let readback_buffer = device.create_buffer(BufferInfo {
+        name: name.to_owned(),
+        size,
+        memory_location: MemoryLocation::GpuToCpu,
+        usage: BufferUsageFlags::TRANSFER_DST,
+    })?;
+
+    if let Err(error) = copy_and_wait(device, readback_buffer, copy) {
+        // The copy may still be running when waiting failed.
+        device.destroy_buffer_deferred(readback_buffer);
+        return Err(error);
+    }
+
+    let data = {
+        let ptr = device.map_buffer_typed::<u8>(readback_buffer)?;
+        unsafe { std::slice::from_raw_parts(ptr, size as usize) }.to_vec()
+    };
+    device.destroy_buffer(readback_buffer)?;
+
+    Ok(data)
+}
+
+fn copy_and_wait(
+    device: &Device,
+    readback_buffer: BufferId,
+    copy: impl FnOnce(&mut CommandRecorder, BufferId) -> Result<()>,
+) -> Result<()> {
+    device.immediate(|recorder| {
+        // Writes made by earlier submissions have to land before the copy reads them.
+        let before = vk::MemoryBarrier::default()
+            .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ);
+        unsafe {
+            device.handle().cmd_pipeline_barrier(
+                recorder.handle(),
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[before],
+                &[],
+                &[],
+            )
+        };
+
+        copy(recorder, readback_buffer)?;
+
+        let after = vk::MemoryBarrier::default()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ);
+        unsafe {
+            device.handle().cmd_pipeline_barrier(
+                recorder.handle(),
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[after],
+                &[],
+                &[],
+            )
+        };
+
+        Ok(())
+    })
+}
@@ -1,12 +1,11 @@
 use std::{
     collections::{HashMap, HashSet},
-    error::Error,
     path::PathBuf,
 };
 
 use regex::{Captures, Regex};
 
-use crate::preamble;
+use crate::{gpu_resources::BindlessCapacities, preamble};
 
 pub struct ShaderInfo {
     pub byte_code: Vec<u32>,
@@ -30,6 +29,7 @@ pub enum ShaderOptimization {
 
 pub struct ShaderCompiler {
     compiler: shaderc::Compiler,
+    preamble: String,
 }
 
 pub struct ShaderLoadOptions {
@@ -39,17 +39,41 @@ pub struct ShaderLoadOptions {
     name: String,
 }
 
+impl ShaderLoadOptions {
+    /// Compiles the `main` entry point optimized for performance.
+    pub fn new(shader_type: ShaderType, name: &str) -> Self {
+        Self {
+            shader_type,
+            optimization: ShaderOptimization::Performance,
+            entry_point: "main".to_owned(),
+            name: name.to_owned(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum CompilationError {
     CompilationErrors { message: String },
+    IncludeNotFound { path: String, message: String },
+    CyclicInclude { path: String },
+    UnsupportedShaderType { extension: String },
     Undefined { message: String },
 }
 
 impl ShaderCompiler {
-    pub fn new() -> Self {
-        Self {
-            compiler: shaderc::Compiler::new().expect("Failed to create shaderc compiler."),
-        }
+    /// Compiles against the capacities of a device, see `Device::bindless_capacities`.
+    /// `Device::create_shader_compiler` does this for the device's own capacities.
+    pub fn with_bindless_capacities(
+        capacities: BindlessCapacities,
+    ) -> Result<Self, CompilationError> {
+        let compiler = shaderc::Compiler::new().ok_or_else(|| CompilationError::Undefined {
+            message: "Failed to create shaderc compiler.".to_owned(),
+        })?;
+
+        Ok(Self {
+            compiler,
+            preamble: preamble::shader_preamble_glsl(&capacities),
+        })
     }
 
     pub fn load_string(
@@ -65,14 +89,16 @@ impl ShaderCompiler {
         };
 
         let mut options =
-            shaderc::CompileOptions::new().expect("Failed to create shaderc compile options.");
+            shaderc::CompileOptions::new().ok_or_else(|| CompilationError::Undefined {
+                message: "Failed to create shaderc compile options.".to_owned(),
+            })?;
         options.set_optimization_level(match load_options.optimization {
             ShaderOptimization::None => shaderc::OptimizationLevel::Zero,
             ShaderOptimization::Performance => shaderc::OptimizationLevel::Performance,
             ShaderOptimization::Size => shaderc::OptimizationLevel::Size,
         });
 
-        let final_source = preamble::SHADER_PREAMBLE_GLSL.to_string() + &shader_source;
+        let final_source = self.preamble.clone() + &shader_source;
 
         let code_result = self.compiler.compile_into_spirv(
             &final_source,
@@ -82,26 +108,21 @@ impl ShaderCompiler {
             Some(&options),
         );
 
-        if let Err(error) = code_result {
-            match error {
-                shaderc::Error::CompilationError(num_errors, errors) => {
-                    return Err(CompilationError::CompilationErrors { message: errors })
-                }
-                _ => {
-                    return Err(CompilationError::Undefined {
-                        message: error.to_string(),
-                    });
-                }
+        match code_result {
+            Ok(artifact) => Ok(artifact.as_binary().into()),
+            Err(shaderc::Error::CompilationError(_, errors)) => {
+                Err(CompilationError::CompilationErrors { message: errors })
             }
+            Err(error) => Err(CompilationError::Undefined {
+                message: error.to_string(),
+            }),
         }
-
-        Ok(code_result.unwrap().as_binary().into())
     }
 
     /// Loads the glsl file and parses includes with relative paths.
     pub fn load_from_file(&self, file_path: String) -> Result<Vec<u32>, CompilationError> {
         let root_path = PathBuf::from(file_path.clone());
-        let root_path_string = root_path.as_os_str().to_owned().into_string().unwrap();
+        let root_path_string = root_path.to_string_lossy().into_owned();
         let include_regex = Regex::new(r##"#include "([^\"]*\/)*[^"]+""##).unwrap();
         let string_regex = Regex::new(r#""[^"]+""#).unwrap();
 
@@ -126,15 +147,17 @@ impl ShaderCompiler {
             let current_file = to_process_files.last().unwrap().clone();
             let current_file_dir = PathBuf::from(current_file.clone())
                 .parent()
-                .unwrap()
-                .as_os_str()
-                .to_owned()
-                .into_string()
-                .unwrap();
-            let contents = cached_files.entry(current_file.clone()).or_insert_with(|| {
-                std::fs::read_to_string(PathBuf::from(current_file.clone()))
-                    .expect(&format!("Could not read file {}", &current_file))
-            });
+                .map(|parent| parent.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            if !cached_files.contains_key(&current_file) {
+                let contents = std::fs::read_to_string(PathBuf::from(current_file.clone()))
+                    .map_err(|error| CompilationError::IncludeNotFound {
+                        path: current_file.clone(),
+                        message: error.to_string(),
+                    })?;
+                cached_files.insert(current_file.clone(), contents);
+            }
+            let contents = &cached_files[&current_file];
 
             let mut needs_deps = false;
             for capture in include_regex.captures_iter(contents) {
@@ -142,10 +165,7 @@ impl ShaderCompiler {
 
                 if !processed_files.contains_key(&full_path) {
                     if visited_files.contains(&full_path) {
-                        panic!(
-                            "acyclic deps are not allowed, tried including {}",
-                            full_path
-                        );
+                        return Err(CompilationError::CyclicInclude { path: full_path });
                     }
                     visited_files.insert(full_path.clone());
                     to_process_files.push(full_path);
@@ -178,7 +198,11 @@ impl ShaderCompiler {
             "geom" => ShaderType::Geometry,
             "frag" => ShaderType::Fragment,
             "comp" => ShaderType::Compute,
-            _ => panic!("Glsl sub-extension not supported"),
+            _ => {
+                return Err(CompilationError::UnsupportedShaderType {
+                    extension: file_extension.to_owned(),
+                })
+            }
         };
 
         self.load_string(
@@ -188,10 +212,8 @@ impl ShaderCompiler {
                 entry_point: "main".to_owned(),
                 name: root_path
                     .file_name()
-                    .unwrap()
-                    .to_owned()
-                    .into_string()
-                    .unwrap(),
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
                 optimization: ShaderOptimization::None,
             },
         )
@@ -5,12 +5,16 @@ use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
 
 use crate::{
     common::{Extent2D, Extent3D, Format, ImageUsageFlags},
+    debug::report_error,
     device::{Device, DeviceInner, ImageInfo},
+    error::{Error, Result},
     gpu_resources::ImageId,
+    queue::QueueType,
     sync::{BinarySemaphore, TimelineSemaphore},
 };
 
 pub struct SwapchainCreateInfo<'a> {
+    pub name: &'a str,
     pub window_handle: &'a dyn HasWindowHandle,
     pub display_handle: &'a dyn HasDisplayHandle,
     pub preferred_extent: (u32, u32),
@@ -38,6 +42,7 @@ pub struct SwapchainInfo {
 
 pub struct Swapchain {
     device_dep: Arc<DeviceInner>,
+    name: String,
     swapchain_loader: ash::khr::swapchain::Device,
     swapchain: vk::SwapchainKHR,
     surface_loader: ash::khr::surface::Instance,
@@ -54,7 +59,7 @@ pub struct Swapchain {
 }
 
 impl Swapchain {
-    pub(crate) fn new(device: &mut Device, create_info: SwapchainCreateInfo<'_>) -> Self {
+    pub(crate) fn new(device: &Device, create_info: SwapchainCreateInfo<'_>) -> Result<Self> {
         let surface_loader = ash::khr::surface::Instance::new(
             &device.instance().loader,
             &device.instance().instance,
@@ -64,16 +69,26 @@ impl Swapchain {
             ash_window::create_surface(
                 &device.instance().loader,
                 &device.instance().instance,
-                create_info
-                    .display_handle
-                    .display_handle()
-                    .unwrap()
-                    .as_raw(),
-                create_info.window_handle.window_handle().unwrap().as_raw(),
+                create_info.display_handle.display_handle()?.as_raw(),
+                create_info.window_handle.window_handle()?.as_raw(),
                 None,
             )
+        }?;
+
+        // Presenting happens on the graphics queue.
+        let present_supported = unsafe {
+            surface_loader.get_physical_device_surface_support(
+                device.inner().physical_device,
+                device.queue(QueueType::Graphics).family_index(),
+                surface,
+            )
+        }?;
+        if !present_supported {
+            unsafe { surface_loader.destroy_surface(surface, None) };
+            return Err(Error::InvalidUsage(
+                "the graphics queue can't present to this surface".to_owned(),
+            ));
         }
-        .unwrap();
 
         let swapchain_loader =
             ash::khr::swapchain::Device::new(&device.instance().instance, device.handle());
@@ -92,32 +107,46 @@ impl Swapchain {
                 image_usage: create_info.image_usage,
                 max_frames_in_flight: create_info.max_frames_in_flight,
             },
-        );
+        )?;
+        device.inner().set_debug_name(swapchain, create_info.name);
 
         let images = images
             .into_iter()
-            .map(|image| {
+            .enumerate()
+            .map(|(i, image)| {
                 device.create_swapchain_image(
                     image,
                     &ImageInfo {
+                        name: format!("{} image {}", create_info.name, i),
                         dimensions: 2,
                         extent: Extent3D::new(info.extent.width, info.extent.height, 1),
                         format: info.format.clone(),
                         usage: info.image_usage,
+                        ..Default::default()
                     },
                 )
             })
-            .collect();
-
-        let (acquire_image_semaphores, present_image_semaphores) = (0..create_info
-            .max_frames_in_flight)
-            .map(|_| (BinarySemaphore::new(device), BinarySemaphore::new(device)))
-            .unzip();
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut acquire_image_semaphores = Vec::new();
+        let mut present_image_semaphores = Vec::new();
+        for i in 0..create_info.max_frames_in_flight {
+            acquire_image_semaphores.push(BinarySemaphore::new(
+                device,
+                &format!("{} acquire semaphore {}", create_info.name, i),
+            )?);
+            present_image_semaphores.push(BinarySemaphore::new(
+                device,
+                &format!("{} present semaphore {}", create_info.name, i),
+            )?);
+        }
 
-        let gpu_timeline_semaphore = TimelineSemaphore::new(device, 0);
+        let gpu_timeline_semaphore =
+            TimelineSemaphore::new(device, 0, &format!("{} frame timeline", create_info.name))?;
 
-        Swapchain {
+        Ok(Swapchain {
             device_dep: device.create_dep(),
+            name: create_info.name.to_owned(),
             swapchain_loader,
             swapchain,
             surface_loader,
@@ -129,56 +158,60 @@ impl Swapchain {
             gpu_timeline_semaphore,
             cpu_timeline: 0,
             last_aquired_image_index: None,
-        }
+        })
     }
 
     fn create_swapchain(
         device_inner: &DeviceInner,
         swapchain_loader: &ash::khr::swapchain::Device,
         info: InternalSwapchainKHRCreateInfo,
-    ) -> (vk::SwapchainKHR, Vec<vk::Image>, SwapchainInfo) {
+    ) -> Result<(vk::SwapchainKHR, Vec<vk::Image>, SwapchainInfo)> {
         let surface_loader = ash::khr::surface::Instance::new(
             &device_inner.instance_dep.loader,
             &device_inner.instance_dep.instance,
         );
         let surface_capabilities = unsafe {
-            surface_loader
-                .get_physical_device_surface_capabilities(
-                    device_inner.physical_device,
-                    info.surface,
-                )
-                .unwrap()
-        };
+            surface_loader.get_physical_device_surface_capabilities(
+                device_inner.physical_device,
+                info.surface,
+            )
+        }?;
 
         let surface_formats = unsafe {
             surface_loader
                 .get_physical_device_surface_formats(device_inner.physical_device, info.surface)
-                .unwrap()
-        };
+        }?;
 
         let surface_present_modes = unsafe {
-            surface_loader
-                .get_physical_device_surface_present_modes(
-                    device_inner.physical_device,
-                    info.surface,
-                )
-                .unwrap()
-        };
+            surface_loader.get_physical_device_surface_present_modes(
+                device_inner.physical_device,
+                info.surface,
+            )
+        }?;
 
-        let surface_format = surface_formats
-            .iter()
-            .find(|format| {
+        // Only formats that paya can represent are considered, prefer srgb if it is available.
+        let supported_formats = || {
+            surface_formats
+                .iter()
+                .filter_map(|format| Some((format, Format::try_from(format.format).ok()?)))
+        };
+        let (surface_format, format) = supported_formats()
+            .find(|(format, _)| {
                 format.format == vk::Format::B8G8R8A8_SRGB
                     && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
             })
-            .unwrap_or(&surface_formats[0]);
+            .or_else(|| supported_formats().next())
+            .ok_or_else(|| {
+                Error::InvalidUsage(format!(
+                    "surface reports no format supported by paya, available formats: {:?}",
+                    surface_formats
+                ))
+            })?;
 
-        println!("Surface present_modes: {:?}", surface_present_modes);
         let present_mode = surface_present_modes
             .iter()
             .find(|&present_mode| *present_mode == vk::PresentModeKHR::MAILBOX)
             .unwrap_or(&vk::PresentModeKHR::FIFO);
-        println!("Present mode: {:?}", present_mode);
 
         let extent = info.preferred_extent;
 
@@ -208,35 +241,31 @@ impl Swapchain {
             swapchain_create_info = swapchain_create_info.old_swapchain(old_swapchain);
         }
 
-        let swapchain = unsafe {
-            swapchain_loader
-                .create_swapchain(&swapchain_create_info, None)
-                .unwrap()
-        };
+        let swapchain = unsafe { swapchain_loader.create_swapchain(&swapchain_create_info, None) }?;
 
         if let Some(old_swapchain) = info.old_swapchain {
             unsafe {
-                device_inner.device.device_wait_idle().unwrap();
+                device_inner.device.device_wait_idle()?;
                 swapchain_loader.destroy_swapchain(old_swapchain, None);
             }
         }
 
-        let images = unsafe { swapchain_loader.get_swapchain_images(swapchain).unwrap() };
+        let images = unsafe { swapchain_loader.get_swapchain_images(swapchain) }?;
 
-        (
+        Ok((
             swapchain,
             images,
             SwapchainInfo {
-                format: Format::from(surface_format.format),
+                format,
                 extent: Extent2D::new(extent.width, extent.height),
                 image_usage: info.image_usage,
                 image_count,
                 max_frames_in_flight: info.max_frames_in_flight,
             },
-        )
+        ))
     }
 
-    pub fn resize(&mut self, device: &mut Device, width: u32, height: u32) {
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32) -> Result<()> {
         let (new_swapchain, images, info) = Self::create_swapchain(
             &self.device_dep,
             &self.swapchain_loader,
@@ -248,25 +277,31 @@ impl Swapchain {
                 image_usage: self.info.image_usage,
                 max_frames_in_flight: self.info.max_frames_in_flight,
             },
-        );
+        )?;
 
         self.swapchain = new_swapchain;
+        self.device_dep.set_debug_name(new_swapchain, &self.name);
         self.images = images
             .into_iter()
-            .map(|image| {
+            .enumerate()
+            .map(|(i, image)| {
                 device.create_swapchain_image(
                     image,
                     &ImageInfo {
+                        name: format!("{} image {}", self.name, i),
                         dimensions: 2,
                         extent: Extent3D::new(info.extent.width, info.extent.height, 1),
                         format: info.format.clone(),
                         usage: info.image_usage,
+                        ..Default::default()
                     },
                 )
             })
-            .collect();
+            .collect::<Result<Vec<_>>>()?;
 
         self.info.extent = Extent2D::new(width, height);
+
+        Ok(())
     }
 
     pub fn info(&self) -> &SwapchainInfo {
@@ -281,12 +316,15 @@ impl Swapchain {
         &self.swapchain_loader
     }
 
-    pub fn acquire_next_image(&mut self) -> Option<ImageId> {
-        let gpu_index = unsafe {
-            self.device_dep
-                .device
-                .get_semaphore_counter_value(self.gpu_timeline_semaphore.handle())
-        };
+    /// Returns `None` when the swapchain is out of date and needs to be resized.
+    pub fn acquire_next_image(&mut self) -> Result<Option<ImageId>> {
+        // Throttle the cpu so no more than `max_frames_in_flight` frames are queued up, the
+        // frame's semaphores can't be reused before then.
+        let max_frames_in_flight = self.info.max_frames_in_flight as u64;
+        if self.cpu_timeline + 1 > max_frames_in_flight {
+            self.gpu_timeline_semaphore
+                .wait(self.cpu_timeline + 1 - max_frames_in_flight, None)?;
+        }
 
         let acquire_semaphore = &self.acquire_image_semaphores
             [((self.cpu_timeline + 1) % self.info.max_frames_in_flight as u64) as usize];
@@ -302,14 +340,14 @@ impl Swapchain {
             Ok((image_index, _)) => Some(image_index),
             Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => None,
             Err(vk::Result::SUBOPTIMAL_KHR) => None,
-            Err(result) => panic!("Failed to acquire next image: {:?}", result),
+            Err(result) => return Err(result.into()),
         };
         self.last_aquired_image_index = result;
 
         if result.is_some() {
             self.cpu_timeline += 1;
         }
-        result.map(|image_index| self.images[image_index as usize])
+        Ok(result.map(|image_index| self.images[image_index as usize]))
     }
 
     pub fn current_acquire_semaphore(&self) -> &BinarySemaphore {
@@ -330,6 +368,13 @@ impl Swapchain {
         &self.gpu_timeline_semaphore
     }
 
+    /// The timeline semaphore and value the submission rendering the last acquired image has
+    /// to signal, `acquire_next_image` waits on them to throttle the cpu. The value counts this
+    /// swapchain's acquires, so other swapchains and presents don't affect it.
+    pub fn current_frame_signal(&self) -> (&TimelineSemaphore, u64) {
+        (&self.gpu_timeline_semaphore, self.cpu_timeline)
+    }
+
     pub fn last_aquired_image_index(&self) -> Option<u32> {
         self.last_aquired_image_index
     }
@@ -339,7 +384,11 @@ impl Drop for Swapchain {
     fn drop(&mut self) {
         // This is safe because we are the only one who has access to the swapchain
         unsafe {
-            self.device_dep.device.device_wait_idle().unwrap();
+            if let Err(error) = self.device_dep.device.device_wait_idle() {
+                report_error(format_args!(
+                    "failed to wait for the device before destroying the swapchain: {error}"
+                ));
+            }
             self.swapchain_loader
                 .destroy_swapchain(self.swapchain, None);
             self.surface_loader.destroy_surface(self.surface, None);
@@ -1,8 +1,11 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use ash::vk;
 
-use crate::device::{Device, DeviceInner};
+use crate::{
+    device::{Device, DeviceInner},
+    error::Result,
+};
 
 pub struct BinarySemaphore {
     device_dep: Arc<DeviceInner>,
@@ -10,21 +13,16 @@ pub struct BinarySemaphore {
 }
 
 impl BinarySemaphore {
-    pub(crate) fn new(device: &Device) -> Self {
+    pub(crate) fn new(device: &Device, name: &str) -> Result<Self> {
         let create_info = vk::SemaphoreCreateInfo::default();
 
-        let handle = unsafe {
-            device
-                .inner()
-                .device
-                .create_semaphore(&create_info, None)
-                .expect("Failed to create semaphore")
-        };
+        let handle = unsafe { device.inner().device.create_semaphore(&create_info, None) }?;
+        device.inner().set_debug_name(handle, name);
 
-        BinarySemaphore {
+        Ok(BinarySemaphore {
             device_dep: device.create_dep(),
             handle,
-        }
+        })
     }
 
     pub fn handle(&self) -> vk::Semaphore {
@@ -43,34 +41,77 @@ impl Drop for BinarySemaphore {
 pub struct TimelineSemaphore {
     device_dep: Arc<DeviceInner>,
     handle: vk::Semaphore,
-    value: u64,
 }
 
 impl TimelineSemaphore {
-    pub(crate) fn new(device: &Device, value: u64) -> Self {
-        let mut type_create_info =
-            vk::SemaphoreTypeCreateInfo::default().semaphore_type(vk::SemaphoreType::TIMELINE);
+    pub(crate) fn new(device: &Device, initial_value: u64, name: &str) -> Result<Self> {
+        Self::from_dep(device.create_dep(), initial_value, name)
+    }
+
+    pub(crate) fn from_dep(
+        device_dep: Arc<DeviceInner>,
+        initial_value: u64,
+        name: &str,
+    ) -> Result<Self> {
+        let mut type_create_info = vk::SemaphoreTypeCreateInfo::default()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(initial_value);
 
         let create_info = vk::SemaphoreCreateInfo::default().push_next(&mut type_create_info);
 
-        let handle = unsafe {
-            device
-                .inner()
-                .device
-                .create_semaphore(&create_info, None)
-                .expect("Failed to create semaphore")
-        };
+        let handle = unsafe { device_dep.device.create_semaphore(&create_info, None) }?;
+        device_dep.set_debug_name(handle, name);
 
-        TimelineSemaphore {
-            device_dep: device.create_dep(),
-            handle,
-            value,
-        }
+        Ok(TimelineSemaphore { device_dep, handle })
     }
 
     pub fn handle(&self) -> vk::Semaphore {
         self.handle
     }
+
+    /// The value the semaphore has currently reached.
+    pub fn value(&self) -> Result<u64> {
+        Ok(unsafe {
+            self.device_dep
+                .device
+                .get_semaphore_counter_value(self.handle)
+        }?)
+    }
+
+    /// Sets the semaphore to `value` from the host, it has to be greater than the current value.
+    pub fn signal(&self, value: u64) -> Result<()> {
+        let signal_info = vk::SemaphoreSignalInfo::default()
+            .semaphore(self.handle)
+            .value(value);
+
+        Ok(unsafe { self.device_dep.device.signal_semaphore(&signal_info) }?)
+    }
+
+    /// Blocks until the semaphore reaches `value`, `None` waits forever. Returns `false` when
+    /// the timeout elapsed first.
+    pub fn wait(&self, value: u64, timeout: Option<Duration>) -> Result<bool> {
+        wait_semaphores(&self.device_dep, &[self.handle], &[value], timeout)
+    }
+}
+
+pub(crate) fn wait_semaphores(
+    device_inner: &DeviceInner,
+    semaphores: &[vk::Semaphore],
+    values: &[u64],
+    timeout: Option<Duration>,
+) -> Result<bool> {
+    let wait_info = vk::SemaphoreWaitInfo::default()
+        .semaphores(semaphores)
+        .values(values);
+    let timeout = timeout.map_or(u64::MAX, |timeout| {
+        timeout.as_nanos().min(u64::MAX as u128) as u64
+    });
+
+    match unsafe { device_inner.device.wait_semaphores(&wait_info, timeout) } {
+        Ok(()) => Ok(true),
+        Err(vk::Result::TIMEOUT) => Ok(false),
+        Err(result) => Err(result.into()),
+    }
 }
 
 impl Drop for TimelineSemaphore {
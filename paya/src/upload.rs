@@ -0,0 +1,417 @@
+use std::collections::{HashSet, VecDeque};
+
+use ash::vk;
+
+use crate::{
+    allocator::MemoryLocation,
+    command_recorder::CommandRecorder,
+    common::{BufferUsageFlags, PipelineStageFlags},
+    device::{Device, Submission, SubmitInfo},
+    error::{Error, Result},
+    gpu_resources::{BufferId, BufferInfo},
+    queue::QueueType,
+};
+
+/// Size of the staging ring, uploads that don't fit get a staging buffer of their own.
+const STAGING_RING_SIZE: u64 = 32 * 1024 * 1024;
+
+// Keeps every upload aligned for any `T` the caller copies from.
+const STAGING_ALIGNMENT: u64 = 16;
+
+/// Copies data into buffers the cpu can't write to through a host visible staging ring. Copies
+/// are batched into a single transfer command list until `flush` submits them.
+#[derive(Default)]
+pub(crate) struct Uploader {
+    // Created on the first upload.
+    ring: Option<StagingRing>,
+    recorder: Option<CommandRecorder>,
+    // Buffers written by the pending copies, they are handed over to the graphics queue when
+    // flushing.
+    pending_buffers: HashSet<BufferId>,
+}
+
+struct StagingRing {
+    buffer: BufferId,
+    ptr: StagingPtr,
+    // Offsets grow until the ring is idle again, the position in the buffer is the offset
+    // modulo its size.
+    head: u64,
+    tail: u64,
+    // The end of the region each submitted batch reads from, in submission order.
+    in_flight: VecDeque<(Submission, u64)>,
+}
+
+struct StagingPtr(*mut u8);
+
+// Safety: the ring is only written while holding the uploader lock and the gpu only reads
+// regions that are no longer handed out.
+unsafe impl Send for StagingPtr {}
+
+impl Uploader {
+    pub(crate) fn upload_buffer(
+        &mut self,
+        device: &Device,
+        id: BufferId,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        {
+            let buffer = device.get_buffer(id)?;
+            if !buffer.info.usage.contains(BufferUsageFlags::TRANSFER_DST) {
+                return Err(Error::InvalidUsage(format!(
+                    "Buffer {} needs TRANSFER_DST usage to be uploaded to",
+                    buffer.info.name
+                )));
+            }
+            if offset + data.len() as u64 > buffer.size {
+                return Err(Error::InvalidUsage(format!(
+                    "Upload of {} bytes at offset {} is out of bounds of buffer {} ({} bytes)",
+                    data.len(),
+                    offset,
+                    buffer.info.name,
+                    buffer.size
+                )));
+            }
+        }
+
+        let size = data.len() as u64;
+        if size > STAGING_RING_SIZE {
+            return self.upload_buffer_dedicated(device, id, offset, data);
+        }
+
+        let staging_offset = self.allocate(device, size)?;
+        let ring = self.ring.as_ref().unwrap();
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                ring.ptr.0.add(staging_offset as usize),
+                data.len(),
+            )
+        };
+        let staging_buffer = ring.buffer;
+
+        self.recorder(device)?.copy_buffer_to_buffer(
+            device,
+            staging_buffer,
+            staging_offset,
+            id,
+            offset,
+            size,
+        )?;
+        self.pending_buffers.insert(id);
+
+        Ok(())
+    }
+
+    /// Uploads that are larger than the ring get a staging buffer that's destroyed once the
+    /// copy has completed.
+    fn upload_buffer_dedicated(
+        &mut self,
+        device: &Device,
+        id: BufferId,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<()> {
+        let staging_buffer = device.create_buffer(BufferInfo {
+            name: "paya staging buffer".to_owned(),
+            size: data.len() as u64,
+            memory_location: MemoryLocation::CpuToGpu,
+            usage: BufferUsageFlags::TRANSFER_SRC,
+        })?;
+        let ptr = device.map_buffer_typed::<u8>(staging_buffer)?;
+        unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len()) };
+
+        let recorder = self.recorder(device)?;
+        recorder.copy_buffer_to_buffer(device, staging_buffer, 0, id, offset, data.len() as u64)?;
+        recorder.destroy_buffer_deferred(staging_buffer);
+        self.pending_buffers.insert(id);
+
+        Ok(())
+    }
+
+    fn recorder(&mut self, device: &Device) -> Result<&mut CommandRecorder> {
+        if self.recorder.is_none() {
+            self.recorder =
+                Some(device.create_command_recorder(QueueType::Transfer, "paya uploads")?);
+        }
+
+        Ok(self.recorder.as_mut().unwrap())
+    }
+
+    /// Returns the offset in the staging buffer of a `size` byte region, flushing and waiting
+    /// for earlier uploads when the ring is full.
+    fn allocate(&mut self, device: &Device, size: u64) -> Result<u64> {
+        if self.ring.is_none() {
+            let buffer = device.create_buffer(BufferInfo {
+                name: "paya staging ring".to_owned(),
+                size: STAGING_RING_SIZE,
+                memory_location: MemoryLocation::CpuToGpu,
+                usage: BufferUsageFlags::TRANSFER_SRC,
+            })?;
+            self.ring = Some(StagingRing {
+                buffer,
+                ptr: StagingPtr(device.map_buffer_typed::<u8>(buffer)?),
+                head: 0,
+                tail: 0,
+                in_flight: VecDeque::new(),
+            });
+        }
+
+        loop {
+            let ring = self.ring.as_mut().unwrap();
+            while let Some((submission, end)) = ring.in_flight.front().copied() {
+                if !device.is_complete(submission)? {
+                    break;
+                }
+                ring.tail = end;
+                ring.in_flight.pop_front();
+            }
+            // Nothing is using the ring, start over at the front.
+            if ring.tail == ring.head {
+                ring.head = 0;
+                ring.tail = 0;
+            }
+
+            let mut start = ring.head.next_multiple_of(STAGING_ALIGNMENT);
+            // Regions never wrap around the end of the buffer.
+            let position = start % STAGING_RING_SIZE;
+            if position + size > STAGING_RING_SIZE {
+                start += STAGING_RING_SIZE - position;
+            }
+            if start + size - ring.tail <= STAGING_RING_SIZE {
+                ring.head = start + size;
+                return Ok(start % STAGING_RING_SIZE);
+            }
+
+            // Only the pending copies are using the ring, submit them so they can be waited on.
+            if ring.in_flight.is_empty() {
+                self.flush(device)?;
+            }
+            let ring = self.ring.as_mut().unwrap();
+            if let Some((submission, end)) = ring.in_flight.pop_front() {
+                device.wait(submission, None)?;
+                ring.tail = end;
+            }
+        }
+    }
+
+    /// Submits the pending copies. The copies wait for earlier graphics work, which may still
+    /// be reading the buffers, and take the buffers over from the graphics queue family. The
+    /// returned submission completes once the data is visible to work submitted to the graphics
+    /// queue afterwards.
+    pub(crate) fn flush(&mut self, device: &Device) -> Result<Option<Submission>> {
+        let Some(recorder) = self.recorder.take() else {
+            return Ok(None);
+        };
+        let buffers = std::mem::take(&mut self.pending_buffers);
+
+        let transfer_queue = device.queue(QueueType::Transfer);
+        let graphics_queue = device.queue(QueueType::Graphics);
+        let ownership_transfer = transfer_queue.family_index != graphics_queue.family_index;
+
+        // Recorded in front of the copies once the buffers they write are known.
+        let prologue = device.create_command_recorder(QueueType::Transfer, "paya upload wait")?;
+        let mut wait_timeline_semaphores = vec![];
+        if transfer_queue.handle == graphics_queue.handle {
+            record_memory_barrier(
+                device,
+                &prologue,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::AccessFlags::MEMORY_WRITE,
+            );
+        } else {
+            // Signalled once every graphics submission made so far has completed, after
+            // releasing the buffers when the queues are in different families.
+            let release =
+                device.create_command_recorder(QueueType::Graphics, "paya upload release")?;
+            if ownership_transfer {
+                record_buffer_barriers(
+                    device,
+                    &release,
+                    &buffers,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::AccessFlags::MEMORY_WRITE,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    vk::AccessFlags::empty(),
+                    graphics_queue.family_index,
+                    transfer_queue.family_index,
+                )?;
+                record_buffer_barriers(
+                    device,
+                    &prologue,
+                    &buffers,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::AccessFlags::empty(),
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    graphics_queue.family_index,
+                    transfer_queue.family_index,
+                )?;
+            }
+            let release_submission = device.submit(SubmitInfo {
+                queue: QueueType::Graphics,
+                commands: vec![release.finish(device)?],
+                wait_semaphores: vec![],
+                wait_timeline_semaphores: vec![],
+                signal_semaphores: vec![],
+                signal_timeline_semaphores: vec![],
+            })?;
+            wait_timeline_semaphores.push((
+                device.queue_timeline(QueueType::Graphics),
+                release_submission.value,
+                PipelineStageFlags::TRANSFER,
+            ));
+        }
+
+        // Copies on the graphics queue itself only need a barrier in front of the work that
+        // follows them.
+        if transfer_queue.handle == graphics_queue.handle {
+            record_memory_barrier(
+                device,
+                &recorder,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+            );
+        } else if ownership_transfer {
+            record_buffer_barriers(
+                device,
+                &recorder,
+                &buffers,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::AccessFlags::empty(),
+                transfer_queue.family_index,
+                graphics_queue.family_index,
+            )?;
+        }
+
+        let transfer_submission = device.submit(SubmitInfo {
+            queue: QueueType::Transfer,
+            commands: vec![prologue.finish(device)?, recorder.finish(device)?],
+            wait_semaphores: vec![],
+            wait_timeline_semaphores,
+            signal_semaphores: vec![],
+            signal_timeline_semaphores: vec![],
+        })?;
+        if let Some(ring) = &mut self.ring {
+            let submitted_end = ring.in_flight.back().map_or(ring.tail, |(_, end)| *end);
+            if ring.head > submitted_end {
+                ring.in_flight.push_back((transfer_submission, ring.head));
+            }
+        }
+
+        if transfer_queue.handle == graphics_queue.handle {
+            return Ok(Some(transfer_submission));
+        }
+
+        // A barrier after the wait orders every later graphics submission behind the copies,
+        // and acquires the buffers when they live in another queue family.
+        let recorder =
+            device.create_command_recorder(QueueType::Graphics, "paya upload acquire")?;
+        if ownership_transfer {
+            record_buffer_barriers(
+                device,
+                &recorder,
+                &buffers,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::AccessFlags::empty(),
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+                transfer_queue.family_index,
+                graphics_queue.family_index,
+            )?;
+        } else {
+            record_memory_barrier(
+                device,
+                &recorder,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::AccessFlags::MEMORY_WRITE,
+            );
+        }
+
+        let graphics_submission = device.submit(SubmitInfo {
+            queue: QueueType::Graphics,
+            commands: vec![recorder.finish(device)?],
+            wait_semaphores: vec![],
+            wait_timeline_semaphores: vec![(
+                device.queue_timeline(QueueType::Transfer),
+                transfer_submission.value,
+                PipelineStageFlags::ALL_COMMANDS,
+            )],
+            signal_semaphores: vec![],
+            signal_timeline_semaphores: vec![],
+        })?;
+
+        Ok(Some(graphics_submission))
+    }
+}
+
+fn record_memory_barrier(
+    device: &Device,
+    recorder: &CommandRecorder,
+    src_stages: vk::PipelineStageFlags,
+    src_access: vk::AccessFlags,
+) {
+    let barrier = vk::MemoryBarrier::default()
+        .src_access_mask(src_access)
+        .dst_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE);
+
+    unsafe {
+        device.handle().cmd_pipeline_barrier(
+            recorder.handle(),
+            src_stages,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[],
+            &[],
+        )
+    };
+}
+
+#[allow(clippy::too_many_arguments)]
+fn record_buffer_barriers(
+    device: &Device,
+    recorder: &CommandRecorder,
+    buffers: &HashSet<BufferId>,
+    src_stages: vk::PipelineStageFlags,
+    src_access: vk::AccessFlags,
+    dst_stages: vk::PipelineStageFlags,
+    dst_access: vk::AccessFlags,
+    src_family: u32,
+    dst_family: u32,
+) -> Result<()> {
+    let barriers = buffers
+        .iter()
+        .map(|id| {
+            let buffer = device.get_buffer(*id)?;
+            Ok(vk::BufferMemoryBarrier::default()
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access)
+                .src_queue_family_index(src_family)
+                .dst_queue_family_index(dst_family)
+                .buffer(buffer.handle)
+                .offset(0)
+                .size(vk::WHOLE_SIZE))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    unsafe {
+        device.handle().cmd_pipeline_barrier(
+            recorder.handle(),
+            src_stages,
+            dst_stages,
+            vk::DependencyFlags::empty(),
+            &[],
+            &barriers,
+            &[],
+        )
+    };
+
+    Ok(())
+}